@@ -2,30 +2,107 @@ use chain_syncer::api::{BlockPersistence, BlockProvider};
 use chain_syncer::{info, settings};
 use std::{env, fs, sync::Arc, time::Duration};
 
+use bitcoin_explorer::backpressure::WriteThrottle;
 use bitcoin_explorer::block_persistence::BtcBlockPersistence;
 use bitcoin_explorer::block_provider::BtcBlockProvider;
+use bitcoin_explorer::header_validation::HeaderValidator;
 use bitcoin_explorer::btc_client::{BtcBlock, BtcClient};
-use bitcoin_explorer::config::BitcoinConfig;
-use bitcoin_explorer::model::{Block, Height};
+use bitcoin_explorer::checkpoints::Checkpoints;
+use bitcoin_explorer::config::{ApiSettings, BitcoinConfig, CheckpointSettings, FinalitySettings, StatusSettings};
+use bitcoin_explorer::finality::Finality;
+use bitcoin_explorer::model::{Address, Block, BlockPointer, Height, TransactionPointer, UtxoAddress};
+use bitcoin_explorer::notifier::Notifier;
+use bitcoin_explorer::raw_blocks::RawBlocks;
+use bitcoin_explorer::status::SyncStatus;
 use bitcoin_explorer::storage;
 use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use redb::Database;
 
-fn criterion_benchmark(c: &mut Criterion) {
-    let app_config = settings::AppConfig::new("config/settings").unwrap();
-    let btc_config = BitcoinConfig::new("config/bitcoin").expect("Failed to load Bitcoin configuration");
-    let db_name = format!("{}/{}", "btc_indexer", "benchmark");
-    let db_path = env::temp_dir().join(&db_name);
+/// Address that outputs without one used to share before they got none, to compare write cost of both layouts
+const SENTINEL: [u8; 25] = [
+    0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0xD3, 0x0A, 0x40, 0x06,
+];
+
+fn with_sentinel_addresses(mut block: Block) -> Block {
+    for utxo in block.transactions.iter_mut().flat_map(|tx| tx.utxos.iter_mut()) {
+        let id = utxo.id.clone();
+        utxo.address.get_or_insert_with(|| UtxoAddress { id, address: Address(SENTINEL.to_vec()) });
+    }
+    block
+}
+
+/// Outputs sharing the sentinel address before the benchmarked block, set `SENTINEL_OUTPUTS` to the number of outputs
+/// without an address of the database being compared
+const SENTINEL_OUTPUTS: usize = 10_000_000;
+
+/// Block range whose output scripts are measured raw and compressed, a day of blocks by default,
+/// override by `SCRIPT_SIZE_HEIGHTS=<from>..<until>` env variable
+const SCRIPT_SIZE_HEIGHTS: (u32, u32) = (800_000, 800_144);
+
+fn fresh_db(name: &str) -> Arc<Database> {
+    let db_path = env::temp_dir().join("btc_indexer").join(name);
     if db_path.exists() {
         info!("Removing existing database directory: {}", db_path.display());
         fs::remove_dir_all(&db_path).unwrap();
     }
-    let db = Arc::new(storage::get_db(db_path).expect("Failed to open database"));
+    Arc::new(storage::get_db(db_path, false).expect("Failed to open database"))
+}
+
+/// Fills the sentinel posting list like the old layout did over the whole chain, at heights below the benchmarked blocks
+fn preload_sentinel(db: &Database, outputs: usize) {
+    let per_tx = 1000;
+    let per_block = per_tx * 1000;
+    for height in 0..outputs.div_ceil(per_block) {
+        let write_tx = db.begin_write().unwrap();
+        for index in 0..per_block.min(outputs - height * per_block) {
+            let tx = BlockPointer::from_parent(Height(height as u32 + 1), (index / per_tx) as u16);
+            let id = TransactionPointer::from_parent(tx, (index % per_tx) as u16);
+            UtxoAddress::store(&write_tx, &UtxoAddress { id, address: Address(SENTINEL.to_vec()) }).unwrap();
+        }
+        write_tx.commit().unwrap();
+    }
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let app_config = settings::AppConfig::new("config/settings").unwrap();
+    let btc_config = BitcoinConfig::new("config/bitcoin").expect("Failed to load Bitcoin configuration");
+    let db = fresh_db("benchmark");
+    let sentinel_db = fresh_db("benchmark_sentinel");
 
     let btc_client = Arc::new(BtcClient::new(&btc_config).expect("Failed to create Bitcoin client"));
+    let sync_status = Arc::new(SyncStatus::new(true, false, StatusSettings::default()));
+    let checkpoints = Arc::new(Checkpoints::new(btc_config.network, &CheckpointSettings::default()).expect("Invalid checkpoints"));
     let fetching_par: usize = app_config.indexer.fetching_parallelism.clone().into();
-    let block_provider: Arc<dyn BlockProvider<BtcBlock, Block>> =
-        Arc::new(BtcBlockProvider::new(btc_client.clone(), fetching_par).expect("Failed to create block provider"));
-    let block_persistence: Arc<dyn BlockPersistence<Block>> = Arc::new(BtcBlockPersistence { db: Arc::clone(&db) });
+    let block_provider: Arc<dyn BlockProvider<BtcBlock, Block>> = Arc::new(
+        BtcBlockProvider::new(btc_client.clone(), fetching_par, Arc::clone(&sync_status), Arc::clone(&checkpoints), Arc::new(RawBlocks::disabled()))
+            .expect("Failed to create block provider"),
+    );
+    let persistence_of = |db: &Arc<Database>| -> Arc<dyn BlockPersistence<Block>> {
+        Arc::new(BtcBlockPersistence {
+            db: Arc::clone(db),
+            notifier: Arc::new(Notifier::new()),
+            status: Arc::clone(&sync_status),
+            throttle: Arc::new(WriteThrottle::new(&ApiSettings::default())),
+            header_validator: HeaderValidator::new(btc_config.network),
+            checkpoints: Arc::clone(&checkpoints),
+            finality: Arc::new(Finality::new(&FinalitySettings::default())),
+            raw_blocks: Arc::new(RawBlocks::disabled()),
+            #[cfg(feature = "ordinals")]
+            ordinals: bitcoin_explorer::ordinals::Ordinals::new(btc_config.network),
+            #[cfg(feature = "runes")]
+            runes: bitcoin_explorer::runes::Runes::new(btc_config.network),
+            #[cfg(feature = "script_verification")]
+            script_verifier: bitcoin_explorer::script_verification::ScriptVerifier::new(btc_config.network),
+        })
+    };
+    let block_persistence = persistence_of(&db);
+    // the old layout only hurts once the sentinel posting list holds every output without an address stored so far
+    let sentinel_outputs = env::var("SENTINEL_OUTPUTS").ok().and_then(|n| n.parse().ok()).unwrap_or(SENTINEL_OUTPUTS);
+    info!("Preloading {} outputs with the sentinel address", sentinel_outputs);
+    preload_sentinel(&sentinel_db, sentinel_outputs);
+    let sentinel_persistence = persistence_of(&sentinel_db);
 
     info!("Getting small block with 29 txs");
     let small_block = btc_client.get_block_by_height(Height(135204)).unwrap();
@@ -38,6 +115,25 @@ fn criterion_benchmark(c: &mut Criterion) {
     let processed_huge_block = block_provider.process_block(&huge_block).expect("Failed to process huge_block");
     let processed_avg_block = block_provider.process_block(&avg_block).expect("Failed to process avg_block");
     let processed_small_block = block_provider.process_block(&small_block).expect("Failed to process small_block");
+    let (from, until) = env::var("SCRIPT_SIZE_HEIGHTS")
+        .ok()
+        .and_then(|range| range.split_once("..").and_then(|(from, until)| Some((from.parse().ok()?, until.parse().ok()?))))
+        .unwrap_or(SCRIPT_SIZE_HEIGHTS);
+    let (raw_size, compressed_size) = (from..until)
+        .map(|height| {
+            let block = btc_client.get_block_by_height(Height(height)).unwrap();
+            block_provider.process_block(&block).expect("Failed to process block")
+        })
+        .flat_map(|block| block.transactions.into_iter().flat_map(|tx| tx.utxos))
+        .fold((0, 0), |(raw, compressed), utxo| (raw + utxo.script_hash.to_script().len(), compressed + utxo.script_hash.0.len()));
+    info!(
+        "Output scripts of blocks {}..{} take {} bytes raw and {} bytes compressed, {:.1}% saved",
+        from,
+        until,
+        raw_size,
+        compressed_size,
+        100.0 * (1.0 - compressed_size as f64 / raw_size as f64)
+    );
 
     info!("Initiating indexing");
     let mut group = c.benchmark_group("persistence");
@@ -93,6 +189,21 @@ fn criterion_benchmark(c: &mut Criterion) {
             BatchSize::LargeInput,
         );
     });
+    // huge block persisted the way outputs without an address were stored before, all sharing a sentinel address
+    // whose posting list already holds `SENTINEL_OUTPUTS` entries
+    let sentinel_huge_block = with_sentinel_addresses(processed_huge_block.clone());
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::from_parameter("huge_block_persistence_sentinel_address"), |bencher| {
+        bencher.iter_batched_ref(
+            || vec![sentinel_huge_block.clone()], // setup once
+            |blocks| {
+                sentinel_persistence
+                    .store_blocks(std::mem::take(blocks))
+                    .expect("Failed to persist huge_block");
+            },
+            BatchSize::LargeInput,
+        );
+    });
 
     group.finish();
 }
@@ -0,0 +1,249 @@
+use crate::config::ApiSettings;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use metrics::counter;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// latency samples older than this no longer throttle indexing, otherwise a single slow request would do it forever
+const LATENCY_STALENESS: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("Query pool is shut down")]
+    PoolClosed,
+    #[error("Too many queries are waiting")]
+    QueueFull,
+    #[error("Query did not finish within {0:?}")]
+    Timeout(Duration),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+impl IntoResponse for QueryError {
+    fn into_response(self) -> Response {
+        unavailable(self.to_string())
+    }
+}
+
+pub fn unavailable(error: String) -> Response {
+    (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "1")], Json(ErrorBody { error })).into_response()
+}
+
+pub fn error_response(code: StatusCode, error: String) -> Response {
+    (code, Json(ErrorBody { error })).into_response()
+}
+
+/// Json response of a query run on `QueryPool`, query errors are internal errors
+pub fn json_response<T: Serialize, E: std::fmt::Display>(result: Result<Result<T, E>, QueryError>) -> Response {
+    match result {
+        Ok(Ok(body)) => Json(body).into_response(),
+        Ok(Err(e)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Dedicated threads for db queries of http handlers, so that they do not compete
+/// with indexer's blocking tasks that fetch blocks from node. At most `queue` queries wait for a thread,
+/// so that a burst of requests is refused right away instead of piling up until each of them times out.
+pub struct QueryPool {
+    sender: SyncSender<Job>,
+}
+
+impl QueryPool {
+    pub fn new(threads: usize, queue: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver: Arc<Mutex<Receiver<Job>>> = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("query-{}", i))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break, // pool dropped
+                    }
+                })
+                .expect("Failed to spawn query thread");
+        }
+        QueryPool { sender }
+    }
+
+    pub async fn run<T, F>(&self, query: F) -> Result<T, QueryError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.sender
+            .try_send(Box::new(move || {
+                // the request timed out or was cancelled while the query was waiting
+                if !result_tx.is_closed() {
+                    let _ = result_tx.send(query());
+                }
+            }))
+            .map_err(|e| match e {
+                TrySendError::Full(_) => {
+                    counter!("explorer_query_queue_full_total").increment(1);
+                    QueryError::QueueFull
+                }
+                TrySendError::Disconnected(_) => QueryError::PoolClosed,
+            })?;
+        result_rx.await.map_err(|_| QueryError::PoolClosed)
+    }
+}
+
+/// Slows down indexing while api latency exceeds `api.throttle_latency_ms`
+pub struct WriteThrottle {
+    threshold: Duration,
+    pause: Duration,
+    created: Instant,
+    latency_micros: AtomicU64,
+    last_sample_millis: AtomicU64,
+}
+
+impl WriteThrottle {
+    pub fn new(settings: &ApiSettings) -> Self {
+        WriteThrottle {
+            threshold: Duration::from_millis(settings.throttle_latency_ms),
+            pause: Duration::from_millis(settings.throttle_pause_ms),
+            created: Instant::now(),
+            latency_micros: AtomicU64::new(0),
+            last_sample_millis: AtomicU64::new(0),
+        }
+    }
+
+    /// Exponentially weighted moving average, so that a single outlier does not stop indexing
+    pub fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_micros() as u64;
+        let _ = self.latency_micros.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| Some((avg * 4 + sample) / 5));
+        self.last_sample_millis.store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn api_latency(&self) -> Duration {
+        Duration::from_micros(self.latency_micros.load(Ordering::Relaxed))
+    }
+
+    fn is_congested(&self) -> bool {
+        if self.threshold.is_zero() || self.pause.is_zero() {
+            return false;
+        }
+        let last_sample = Duration::from_millis(self.last_sample_millis.load(Ordering::Relaxed));
+        let fresh = self.created.elapsed().saturating_sub(last_sample) < LATENCY_STALENESS;
+        fresh && self.api_latency() > self.threshold
+    }
+
+    /// Called by persistence between write transactions, it blocks the indexing thread
+    pub fn pause_if_congested(&self) {
+        if self.is_congested() {
+            counter!("explorer_write_throttled_total").increment(1);
+            thread::sleep(self.pause);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Guard {
+    pub timeout: Duration,
+    pub throttle: Arc<WriteThrottle>,
+}
+
+/// Middleware that turns requests exceeding `api.request_timeout_ms` into 503 and feeds latencies to `WriteThrottle`
+pub async fn guard(State(guard): State<Guard>, request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let response = match tokio::time::timeout(guard.timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            counter!("explorer_http_timeouts_total").increment(1);
+            QueryError::Timeout(guard.timeout).into_response()
+        }
+    };
+    guard.throttle.record_latency(start.elapsed());
+    response
+}
+
+/// Middleware for redbit generated routes whose handlers read the database right in their future,
+/// it drives them to completion on `QueryPool` threads so that they never block runtime workers
+pub async fn pooled(State(pool): State<Arc<QueryPool>>, request: Request, next: Next) -> Response {
+    let runtime = Handle::current();
+    match pool.run(move || runtime.block_on(next.run(request))).await {
+        Ok(response) => response,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::AtomicBool;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_pooled_handlers_run_on_query_threads() {
+        let pool = Arc::new(QueryPool::new(1, 1));
+        let app = Router::new()
+            .route("/thread", get(|| async { thread::current().name().unwrap_or_default().to_string() }))
+            .layer(axum::middleware::from_fn_with_state(pool, pooled));
+        let response = app.oneshot(Request::get("/thread").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "query-0");
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_refuses_and_cancelled_queries_are_skipped() {
+        let pool = QueryPool::new(1, 1);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let mut busy = Box::pin(pool.run(move || {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap()
+        }));
+        assert!(futures::poll!(&mut busy).is_pending());
+        started_rx.recv().unwrap();
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let query_ran = Arc::clone(&ran);
+        let mut waiting = Box::pin(pool.run(move || query_ran.store(true, Ordering::Relaxed)));
+        assert!(futures::poll!(&mut waiting).is_pending());
+        assert!(matches!(pool.run(|| ()).await, Err(QueryError::QueueFull)));
+
+        // the waiting request gives up before the thread is free
+        drop(waiting);
+        release_tx.send(()).unwrap();
+        busy.await.unwrap();
+        pool.run(|| ()).await.unwrap();
+        assert!(!ran.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_guard_times_out_and_records_latency() {
+        let throttle = Arc::new(WriteThrottle::new(&ApiSettings { throttle_latency_ms: 1, ..ApiSettings::default() }));
+        let guard = Guard { timeout: Duration::from_millis(10), throttle: Arc::clone(&throttle) };
+        let app = Router::new()
+            .route("/slow", get(|| async { tokio::time::sleep(Duration::from_secs(5)).await }))
+            .layer(axum::middleware::from_fn_with_state(guard, super::guard));
+        let response = app.oneshot(Request::get("/slow").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        assert!(throttle.api_latency() >= Duration::from_millis(1));
+        assert!(throttle.is_congested());
+    }
+}
@@ -1,15 +1,68 @@
-use crate::model::{Block, BlockHash, BlockHeader, Height, InputRef, Transaction, BlockPointer, TransactionPointer};
+use crate::backpressure::WriteThrottle;
+use crate::checkpoints::Checkpoints;
+use crate::filters;
+use crate::finality::Finality;
+use crate::header_validation::HeaderValidator;
+use crate::integrity;
+use crate::model::{Block, BlockHash, BlockHeader, FeedEvent, Height, InputRef, Reorg, Transaction, BlockPointer, TransactionPointer, TxHash, Utxo};
+use crate::notifier::{BlockRef, Notifier};
+use crate::raw_blocks::RawBlocks;
+#[cfg(feature = "ordinals")]
+use crate::ordinals::{self, Ordinals};
+#[cfg(feature = "ordinals")]
+use crate::model::{Inscription, InscriptionTransfer};
+#[cfg(feature = "brc20")]
+use crate::brc20;
+#[cfg(feature = "brc20")]
+use crate::model::{Brc20Balance, Brc20Event, Brc20Supply, Brc20Token};
+#[cfg(feature = "runes")]
+use crate::runes::{self, Runes};
+#[cfg(feature = "runes")]
+use crate::model::{Rune, RuneBalance, RuneHolding, RuneMint, RuneSpend};
+#[cfg(feature = "script_verification")]
+use crate::script_verification::ScriptVerifier;
+#[cfg(feature = "silent_payments")]
+use crate::silent_payments;
+#[cfg(feature = "silent_payments")]
+use crate::model::SpTweak;
+use crate::reorgs;
+use crate::status::SyncStatus;
+use crate::telemetry;
 use chain_syncer::api::*;
+use chain_syncer::info;
+use chrono::Utc;
+use bitcoin::{Amount, TxOut};
 use redbit::*;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use redbit::redb::ReadTransaction;
 
 pub struct BtcBlockPersistence {
     pub db: Arc<Database>,
+    pub notifier: Arc<Notifier>,
+    pub status: Arc<SyncStatus>,
+    pub throttle: Arc<WriteThrottle>,
+    pub header_validator: HeaderValidator,
+    pub checkpoints: Arc<Checkpoints>,
+    pub finality: Arc<Finality>,
+    pub raw_blocks: Arc<RawBlocks>,
+    #[cfg(feature = "ordinals")]
+    pub ordinals: Ordinals,
+    #[cfg(feature = "runes")]
+    pub runes: Runes,
+    #[cfg(feature = "script_verification")]
+    pub script_verifier: ScriptVerifier,
 }
 
 impl BtcBlockPersistence {
-    fn populate_inputs(read_tx: &ReadTransaction, block: &mut Block) -> Result<(), ChainSyncError> {
+    /// Pointer of inputs whose prevout is not indexed, the genesis coinbase output it points to can never be spent
+    fn unresolved_input() -> TransactionPointer {
+        TransactionPointer::from_parent(BlockPointer::from_parent(Height(0), 0), 0)
+    }
+
+    fn populate_inputs(read_tx: &ReadTransaction, block: &mut Block) -> Result<usize, ChainSyncError> {
+        let mut unresolved = 0;
         for tx in &mut block.transactions {
             for transient_input in tx.transient_inputs.iter_mut() {
                 let tx_pointers =
@@ -22,13 +75,157 @@ impl BtcBlockPersistence {
                         });
                     }
                     None => {
-                        tx.inputs.push(InputRef {
-                            id: TransactionPointer::from_parent(BlockPointer::from_parent(Height(0), 0), 0)
-                        })
+                        unresolved += 1;
+                        tx.inputs.push(InputRef { id: Self::unresolved_input() })
                     }
                 }
             }
         }
+        Ok(unresolved)
+    }
+
+    /// Outputs spent by every transaction in input order, prevouts created within the block are taken from it as they are not stored yet,
+    /// prevouts which are not indexed are `None`
+    fn prevouts(read_tx: &ReadTransaction, block: &Block) -> Result<Vec<Vec<Option<TxOut>>>, ChainSyncError> {
+        let block_txs: HashMap<[u8; 32], &Transaction> = block.transactions.iter().map(|tx| (tx.hash.0, tx)).collect();
+        let mut prevouts = Vec::with_capacity(block.transactions.len());
+        // coinbase spends nothing
+        prevouts.push(Vec::new());
+        for tx in block.transactions.iter().skip(1) {
+            let mut tx_prevouts = Vec::with_capacity(tx.transient_inputs.len());
+            for (outpoint, input_ref) in tx.transient_inputs.iter().zip(&tx.inputs) {
+                let utxo = match block_txs.get(&outpoint.tx_hash.0) {
+                    Some(parent) => parent.utxos.get(outpoint.index as usize).cloned(),
+                    None if input_ref.id == Self::unresolved_input() => None,
+                    None => Utxo::get(read_tx, &input_ref.id)?,
+                };
+                let prevout = utxo.map(|utxo| TxOut { value: Amount::from_sat(utxo.amount), script_pubkey: utxo.script_hash.to_script() });
+                tx_prevouts.push(prevout);
+            }
+            prevouts.push(tx_prevouts);
+        }
+        Ok(prevouts)
+    }
+
+    fn spent_addresses(read_tx: &ReadTransaction, block: &Block) -> Result<Vec<(Vec<u8>, TxHash)>, ChainSyncError> {
+        let mut spent = Vec::new();
+        for tx in &block.transactions {
+            for input in tx.inputs.iter().filter(|input| input.id != Self::unresolved_input()) {
+                if let Some(address) = Utxo::get(read_tx, &input.id)?.and_then(|utxo| utxo.address) {
+                    spent.push((address.address.0, tx.hash.clone()));
+                }
+            }
+        }
+        Ok(spent)
+    }
+
+    fn notify_connected(&self, block: &Block) -> Result<(), ChainSyncError> {
+        if self.notifier.has_subscribers() {
+            let read_tx = self.db.begin_read()?;
+            let spent = Self::spent_addresses(&read_tx, block)?;
+            self.notifier.block_connected(block, spent);
+        }
+        Ok(())
+    }
+
+    fn store(&self, mut blocks: Vec<Block>) -> Result<(), ChainSyncError> {
+        // every block is committed separately so that api readers get fresh snapshots during historical indexing
+        for block in &mut blocks {
+            self.throttle.pause_if_congested();
+            let read_tx = self.db.begin_read()?;
+            let parent = if block.id.0 > 0 { BlockHeader::get(&read_tx, &Height(block.id.0 - 1))? } else { None };
+            integrity::check_parent(&block.header, parent.as_ref())?;
+            if !self.checkpoints.is_assumed_valid(&block.id) {
+                self.header_validator.validate(&block.header, parent.as_ref(), |height| Ok(BlockHeader::get(&read_tx, &height)?))?;
+            }
+            let unresolved_inputs = Self::populate_inputs(&read_tx, block)?;
+            let events = self.finality.connected_events(&read_tx, &block.header)?;
+            let prevouts = Self::prevouts(&read_tx, block)?;
+            block.filter = filters::block_filter(&read_tx, block, &prevouts)?;
+            // scripts are trusted up to the last checkpoint like headers are
+            #[cfg(feature = "script_verification")]
+            if !self.checkpoints.is_assumed_valid(&block.id) {
+                self.script_verifier.verify_block(block, &prevouts)?;
+            }
+            #[cfg(feature = "ordinals")]
+            let inscriptions = self.ordinals.index_block(&read_tx, block, &prevouts)?;
+            #[cfg(feature = "brc20")]
+            let tokens = brc20::index_block(&read_tx, block, &inscriptions)?;
+            #[cfg(feature = "runes")]
+            let runes = self.runes.index_block(&read_tx, block)?;
+            #[cfg(feature = "silent_payments")]
+            let tweaks = silent_payments::index_block(&read_tx, block, &prevouts)?;
+            let start = Instant::now();
+            // feed events are committed with the block so that consumers replaying the feed never miss one
+            let write_tx = self.db.begin_write()?;
+            Block::store(&write_tx, block)?;
+            for event in &events {
+                FeedEvent::store(&write_tx, event)?;
+            }
+            #[cfg(feature = "ordinals")]
+            {
+                for inscription in &inscriptions.inscriptions {
+                    Inscription::store(&write_tx, inscription)?;
+                }
+                for transfer in &inscriptions.transfers {
+                    InscriptionTransfer::store(&write_tx, transfer)?;
+                }
+            }
+            #[cfg(feature = "brc20")]
+            {
+                for token in &tokens.tokens {
+                    Brc20Token::store(&write_tx, token)?;
+                }
+                for event in &tokens.events {
+                    Brc20Event::store(&write_tx, event)?;
+                }
+                for balance in &tokens.balances {
+                    Brc20Balance::store(&write_tx, balance)?;
+                }
+                for supply in &tokens.supplies {
+                    Brc20Supply::store(&write_tx, supply)?;
+                }
+            }
+            // mint counts are updated in place, redbit has no update so the rune is replaced
+            #[cfg(feature = "runes")]
+            {
+                for rune in &runes.etched {
+                    Rune::store(&write_tx, rune)?;
+                }
+                for rune in &runes.minted {
+                    Rune::delete(&write_tx, &rune.id)?;
+                    Rune::store(&write_tx, rune)?;
+                }
+                for balance in &runes.balances {
+                    RuneBalance::store(&write_tx, balance)?;
+                }
+                for spend in &runes.spends {
+                    RuneSpend::store(&write_tx, spend)?;
+                }
+                for mint in &runes.mints {
+                    RuneMint::store(&write_tx, mint)?;
+                }
+                for holding in &runes.holdings {
+                    RuneHolding::store(&write_tx, holding)?;
+                }
+            }
+            #[cfg(feature = "silent_payments")]
+            for tweak in &tweaks {
+                SpTweak::store(&write_tx, tweak)?;
+            }
+            write_tx.commit()?;
+            self.finality.record_tip(block.id.0);
+            telemetry::record_write("store", start.elapsed());
+            telemetry::record_stored(block, unresolved_inputs);
+            // cached only once stored so that neither tip probes nor blocks dropped by a reorg end up in the cache,
+            // a failed write is not fatal as the cache is filled from the node when the block is requested
+            if !block.raw.is_empty()
+                && let Err(e) = self.raw_blocks.store_serialized(&block.header.hash.0, &std::mem::take(&mut block.raw))
+            {
+                info!("Failed to cache raw block {}: {}", block.id.0, e);
+            }
+            self.notify_connected(block)?;
+        }
         Ok(())
     }
 }
@@ -46,21 +243,107 @@ impl BlockPersistence<Block> for BtcBlockPersistence {
         Ok(header)
     }
 
-    fn store_blocks(&self, mut blocks: Vec<Block>) -> Result<(), ChainSyncError> {
-        for block in &mut blocks {
-            let read_tx = self.db.begin_read()?;
-            Self::populate_inputs(&read_tx, block)?;
-            Block::store_and_commit(&self.db, block)?;
-        }
-        Ok(())
+    fn store_blocks(&self, blocks: Vec<Block>) -> Result<(), ChainSyncError> {
+        let block_count = blocks.len();
+        self.store(blocks).inspect(|_| self.status.record_stored(block_count)).inspect_err(|e| self.status.record_error(e))
     }
 
     fn update_blocks(&self, mut blocks: Vec<Block>) -> Result<(), ChainSyncError> {
+        if let Some(first) = blocks.first() {
+            self.checkpoints.check_reorg(&first.id).inspect_err(|e| self.status.record_error(e))?;
+        }
+        let read_tx = self.db.begin_read()?;
+        let mut stale = Vec::with_capacity(blocks.len());
+        for block in &blocks {
+            if let Some(stored) = Block::get(&read_tx, &block.id)? {
+                stale.push(stored);
+            }
+        }
+        let rolled_back: Vec<BlockRef> = stale.iter().map(|block| BlockRef::from(&block.header)).collect();
+        let fork_height = blocks.first().map_or(Height(0), |b| Height(b.id.0.saturating_sub(1)));
+        let reorg_id = reorgs::next_id(&read_tx)?;
+        let events = self.finality.disconnected_events(&read_tx, &stale)?;
+        #[cfg(feature = "ordinals")]
+        let (inscription_ids, transfer_ids) = ordinals::rollback_ids(&read_tx, &stale.iter().map(|block| block.id.clone()).collect::<Vec<_>>())?;
+        #[cfg(feature = "brc20")]
+        let brc20_rollback = brc20::rollback(&read_tx, &stale.iter().map(|block| block.id.clone()).collect::<Vec<_>>())?;
+        #[cfg(feature = "runes")]
+        let runes_rollback = runes::rollback(&read_tx, &stale.iter().map(|block| block.id.clone()).collect::<Vec<_>>())?;
+        #[cfg(feature = "silent_payments")]
+        let tweak_ids = silent_payments::rollback(&read_tx, &stale.iter().map(|block| block.id.clone()).collect::<Vec<_>>())?;
+        let start = Instant::now();
         let write_tx = self.db.begin_write()?;
+        // superseded blocks are archived in the same transaction they are deleted in
+        if let Some(new_tip) = blocks.last().filter(|_| !stale.is_empty()) {
+            let reorg = reorgs::archive(reorg_id, Utc::now().timestamp() as u32, fork_height, &stale, &new_tip.header.hash);
+            Reorg::store(&write_tx, &reorg)?;
+        }
         for block in &mut blocks {
             Block::delete(&write_tx, &block.id)?;
         }
+        // inscriptions of deleted blocks are forgotten and their transfers undone, so that earlier locations become current again
+        #[cfg(feature = "ordinals")]
+        {
+            for id in &inscription_ids {
+                Inscription::delete(&write_tx, id)?;
+            }
+            for id in &transfer_ids {
+                InscriptionTransfer::delete(&write_tx, id)?;
+            }
+        }
+        // balance and supply snapshots of deleted blocks are dropped, so that earlier ones become current again
+        #[cfg(feature = "brc20")]
+        {
+            for id in &brc20_rollback.tokens {
+                Brc20Token::delete(&write_tx, id)?;
+            }
+            for id in &brc20_rollback.events {
+                Brc20Event::delete(&write_tx, id)?;
+            }
+            for id in &brc20_rollback.balances {
+                Brc20Balance::delete(&write_tx, id)?;
+            }
+            for id in &brc20_rollback.supplies {
+                Brc20Supply::delete(&write_tx, id)?;
+            }
+        }
+        // rune balances, spends and holding snapshots of deleted blocks are undone, surviving runes get their mints recounted
+        #[cfg(feature = "runes")]
+        {
+            for id in &runes_rollback.runes {
+                Rune::delete(&write_tx, id)?;
+            }
+            for id in &runes_rollback.balances {
+                RuneBalance::delete(&write_tx, id)?;
+            }
+            for id in &runes_rollback.spends {
+                RuneSpend::delete(&write_tx, id)?;
+            }
+            for id in &runes_rollback.mints {
+                RuneMint::delete(&write_tx, id)?;
+            }
+            for id in &runes_rollback.holdings {
+                RuneHolding::delete(&write_tx, id)?;
+            }
+            for rune in &runes_rollback.recounted {
+                Rune::delete(&write_tx, &rune.id)?;
+                Rune::store(&write_tx, rune)?;
+            }
+        }
+        #[cfg(feature = "silent_payments")]
+        for id in &tweak_ids {
+            SpTweak::delete(&write_tx, id)?;
+        }
+        for event in &events {
+            FeedEvent::store(&write_tx, event)?;
+        }
         write_tx.commit()?;
+        if !stale.is_empty() {
+            self.finality.record_tip(fork_height.0);
+        }
+        telemetry::record_write("delete", start.elapsed());
+        telemetry::record_reorg(rolled_back.len());
+        self.notifier.blocks_rolled_back(fork_height, rolled_back);
         self.store_blocks(blocks)?;
         Ok(())
     }
@@ -1,5 +1,12 @@
 use crate::btc_client::{BtcBlock, BtcClient};
-use crate::model::{Address, Block, BlockHash, BlockHeader, Height, BlockTimestamp, ExplorerError, ScriptHash, TempInputRef, Transaction, TxHash, BlockPointer, Utxo, TransactionPointer, MerkleRoot};
+use crate::checkpoints::Checkpoints;
+use crate::integrity;
+use crate::op_return;
+use crate::pubkeys;
+use crate::raw_blocks::RawBlocks;
+use crate::status::SyncStatus;
+use crate::telemetry;
+use crate::model::{Address, Block, BlockFilter, BlockHash, BlockHeader, FilterHeaderHash, GcsFilter, Envelope, Height, TapscriptPush, BlockTimestamp, ExplorerError, ScriptHash, ScriptType, SpInput, TempInputRef, Transaction, TxHash, BlockPointer, Utxo, UtxoAddress, TransactionPointer, MerkleRoot, Bits, BlockVersion, Nonce};
 use async_trait::async_trait;
 use chain_syncer::api::{BlockProvider, ChainSyncError};
 use chain_syncer::info;
@@ -9,20 +16,23 @@ use futures::Stream;
 use redbit::*;
 use std::{pin::Pin, sync::Arc};
 
-pub const SENTINEL: [u8; 25] = [
-    0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    0x00, 0x00, 0x00, 0x00, 0x00, 0xD3, 0x0A, 0x40, 0x06,
-];
-
 pub struct BtcBlockProvider {
     pub client: Arc<BtcClient>,
     pub fetching_par: usize,
+    pub status: Arc<SyncStatus>,
+    pub checkpoints: Arc<Checkpoints>,
+    pub raw_blocks: Arc<RawBlocks>,
 }
 
 impl BtcBlockProvider {
-    pub fn new(client: Arc<BtcClient>, fetching_par: usize) -> Result<Self, ExplorerError> {
-        Ok(BtcBlockProvider { client, fetching_par })
+    pub fn new(
+        client: Arc<BtcClient>,
+        fetching_par: usize,
+        status: Arc<SyncStatus>,
+        checkpoints: Arc<Checkpoints>,
+        raw_blocks: Arc<RawBlocks>,
+    ) -> Result<Self, ExplorerError> {
+        Ok(BtcBlockProvider { client, fetching_par, status, checkpoints, raw_blocks })
     }
     fn process_inputs(&self, ins: &[bitcoin::TxIn]) -> Vec<TempInputRef> {
         ins.iter()
@@ -35,20 +45,54 @@ impl BtcBlockProvider {
     fn process_outputs(&self, outs: &[bitcoin::TxOut], tx_pointer: BlockPointer) -> (BoxWeight, Vec<Utxo>) {
         let mut result_outs = Vec::with_capacity(outs.len());
         for (out_index, out) in outs.iter().enumerate() {
-            let address = if let Ok(address) = bitcoin::Address::from_script(out.script_pubkey.as_script(), bitcoin::Network::Bitcoin) {
-                address.to_string().into_bytes()
-            } else {
-                SENTINEL.to_vec()
-            };
+            let id = TransactionPointer::from_parent(tx_pointer.clone(), out_index as u16);
+            let address = bitcoin::Address::from_script(out.script_pubkey.as_script(), bitcoin::Network::Bitcoin)
+                .ok()
+                .map(|address| UtxoAddress { id: id.clone(), address: Address(address.to_string().into_bytes()) });
+            let script_type = ScriptType::of(&out.script_pubkey);
             result_outs.push(Utxo {
-                id: TransactionPointer::from_parent(tx_pointer.clone(), out_index as u16),
+                pubkeys: pubkeys::utxo_pubkeys(&out.script_pubkey, script_type, &id),
+                id,
                 amount: out.value.to_sat().into(),
-                script_hash: ScriptHash(out.script_pubkey.as_bytes().to_vec()),
-                address: Address(address),
+                script_hash: ScriptHash::from_script(&out.script_pubkey),
+                address,
+                script_type,
             })
         }
         (result_outs.len(), result_outs)
     }
+    #[cfg(feature = "ordinals")]
+    fn process_envelopes(&self, tx: &bitcoin::Transaction) -> Vec<Envelope> {
+        crate::ordinals::parse_envelopes(tx)
+    }
+    #[cfg(not(feature = "ordinals"))]
+    fn process_envelopes(&self, _tx: &bitcoin::Transaction) -> Vec<Envelope> {
+        Vec::new()
+    }
+    #[cfg(feature = "runes")]
+    fn process_tapscript_pushes(&self, tx: &bitcoin::Transaction) -> Vec<TapscriptPush> {
+        crate::runes::parse_tapscript_pushes(tx)
+    }
+    #[cfg(not(feature = "runes"))]
+    fn process_tapscript_pushes(&self, _tx: &bitcoin::Transaction) -> Vec<TapscriptPush> {
+        Vec::new()
+    }
+    #[cfg(feature = "silent_payments")]
+    fn process_sp_inputs(&self, tx: &bitcoin::Transaction) -> Vec<SpInput> {
+        crate::silent_payments::sp_inputs(tx)
+    }
+    #[cfg(not(feature = "silent_payments"))]
+    fn process_sp_inputs(&self, _tx: &bitcoin::Transaction) -> Vec<SpInput> {
+        Vec::new()
+    }
+    #[cfg(feature = "script_verification")]
+    fn process_raw(&self, tx: &bitcoin::Transaction) -> Vec<u8> {
+        bitcoin::consensus::serialize(tx)
+    }
+    #[cfg(not(feature = "script_verification"))]
+    fn process_raw(&self, _tx: &bitcoin::Transaction) -> Vec<u8> {
+        Vec::new()
+    }
     fn process_tx(&self, height: Height, tx_index: u16, tx: &bitcoin::Transaction) -> Transaction {
         let tx_pointer = BlockPointer::from_parent(height, tx_index);
         let (_, outputs) = self.process_outputs(&tx.output, tx_pointer.clone());
@@ -58,38 +102,60 @@ impl BtcBlockProvider {
             utxos: outputs,
             inputs: vec![],
             transient_inputs: self.process_inputs(&tx.input),
+            envelopes: self.process_envelopes(tx),
+            tapscript_pushes: self.process_tapscript_pushes(tx),
+            sp_inputs: self.process_sp_inputs(tx),
+            raw: self.process_raw(tx),
+            op_returns: op_return::op_returns(tx, &tx_pointer),
         }
     }
-}
-
-#[async_trait]
-impl BlockProvider<BtcBlock, Block> for BtcBlockProvider {
-    fn process_block(&self, block: &BtcBlock) -> Result<Block, ChainSyncError> {
+    /// Raw block is kept for the cache only if `raw`, the persistence writes it once the block is stored
+    fn process(&self, block: &BtcBlock, raw: bool) -> Result<Block, ChainSyncError> {
         let header = BlockHeader {
             id: block.height.clone(),
             timestamp: BlockTimestamp(block.underlying.header.time),
             hash: BlockHash(*block.underlying.block_hash().as_ref()),
             prev_hash: BlockHash(*block.underlying.header.prev_blockhash.as_ref()),
             merkle_root: MerkleRoot(*block.underlying.header.merkle_root.as_ref()),
+            bits: Bits(block.underlying.header.bits.to_consensus()),
+            version: BlockVersion(block.underlying.header.version.to_consensus()),
+            nonce: Nonce(block.underlying.header.nonce),
         };
+        // every streamed block passes here, so a node on a different chain is caught at the first checkpoint
+        self.checkpoints.check(&header)?;
 
         let mut block_weight = 0;
+        let transactions: Vec<Transaction> = block
+            .underlying
+            .txdata
+            .iter()
+            .enumerate()
+            .map(|(tx_index, tx)| {
+                block_weight += tx.input.len() + tx.output.len();
+                self.process_tx(block.height.clone(), tx_index as u16, &tx)
+            })
+            .collect();
+
+        let tx_hashes: Vec<TxHash> = transactions.iter().map(|tx| tx.hash.clone()).collect();
+        integrity::check_merkle_root(&header, &tx_hashes)?;
+
         Ok(Block {
             id: block.height.clone(),
             header,
-            transactions: block
-                .underlying
-                .txdata
-                .iter()
-                .enumerate()
-                .map(|(tx_index, tx)| {
-                    block_weight += tx.input.len() + tx.output.len();
-                    self.process_tx(block.height.clone(), tx_index as u16, &tx)
-                })
-                .collect(),
+            transactions,
+            // spent prevout scripts are known only at persistence, which builds the filter
+            filter: BlockFilter { id: block.height.clone(), filter: GcsFilter(Vec::new()), header: FilterHeaderHash([0; 32]) },
             weight: block_weight as u32, // TODO usize
+            raw: if raw { bitcoin::consensus::serialize(&block.underlying) } else { Vec::new() },
         })
     }
+}
+
+#[async_trait]
+impl BlockProvider<BtcBlock, Block> for BtcBlockProvider {
+    fn process_block(&self, block: &BtcBlock) -> Result<Block, ChainSyncError> {
+        self.process(block, self.raw_blocks.enabled())
+    }
 
     fn get_processed_block(&self, header: BlockHeader) -> Result<Block, ChainSyncError> {
         let block = self.client.get_block_by_hash(header.hash)?;
@@ -97,8 +163,11 @@ impl BlockProvider<BtcBlock, Block> for BtcBlockProvider {
     }
 
     async fn get_chain_tip(&self) -> Result<BlockHeader, ChainSyncError> {
-        let best_block = self.client.get_best_block()?;
-        let processed_block = self.process_block(&best_block)?;
+        let best_block = self.client.get_best_block().inspect_err(|e| self.status.record_error(e))?;
+        // the tip is only probed here, it is cached when streamed and stored
+        let processed_block = self.process(&best_block, false)?;
+        self.status.record_node_tip(processed_block.header.id.0);
+        telemetry::record_node_tip(processed_block.header.id.0);
         Ok(processed_block.header)
     }
 
@@ -111,10 +180,18 @@ impl BlockProvider<BtcBlock, Block> for BtcBlockProvider {
         info!("Indexing from {:?} to {:?}", last_height, chain_tip_header);
         let heights = last_height..=chain_tip_header.id.0;
         let client = Arc::clone(&self.client);
+        let status = Arc::clone(&self.status);
         tokio_stream::iter(heights)
             .map(move |height| {
                 let client = Arc::clone(&client);
-                tokio::task::spawn_blocking(move || client.get_block_by_height(Height(height)).expect("Failed to get block by height"))
+                let status = Arc::clone(&status);
+                tokio::task::spawn_blocking(move || match client.get_block_by_height(Height(height)) {
+                    Ok(block) => block,
+                    Err(e) => {
+                        status.record_error(&e);
+                        panic!("Failed to get block by height {}: {}", height, e)
+                    }
+                })
             })
             .buffered(self.fetching_par)
             .map(|res| match res {
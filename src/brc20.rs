@@ -0,0 +1,816 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec;
+use crate::model::{
+    Block, Brc20Amount, Brc20Balance, Brc20BalanceId, Brc20Event, Brc20EventId, Brc20Holder, Brc20Op, Brc20Supply, Brc20SupplyId, Brc20Token,
+    Brc20TokenId, Height, Inscription, ScriptHash, Tick, Transaction,
+};
+use crate::ordinals::{inscription_number, OrdinalsBatch};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hex::DisplayHex;
+use bitcoin::Script;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+pub const MAX_LIMIT: usize = 100;
+pub const TICK_LEN: usize = 4;
+pub const MAX_DECIMALS: u8 = 18;
+// amounts are kept with 18 decimals so that tokens of any precision share one representation
+const SCALE: u128 = 1_000_000_000_000_000_000;
+const MAX_SUPPLY: u128 = u64::MAX as u128 * SCALE;
+
+/// BRC-20 operation as inscribed, mint and transfer amounts are parsed once decimals of the token are known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Deploy { tick: String, max: u128, lim: u128, dec: u8 },
+    Mint { tick: String, amt: String },
+    Transfer { tick: String, amt: String },
+}
+
+/// Parses a decimal amount with at most `dec` fractional digits, no sign, exponent or bare dot is allowed
+pub fn parse_amount(amount: &str, dec: u8) -> Option<u128> {
+    let (integer, fraction) = match amount.split_once('.') {
+        Some((integer, fraction)) => (integer, fraction),
+        None => (amount, ""),
+    };
+    if integer.is_empty()
+        || (amount.contains('.') && fraction.is_empty())
+        || fraction.len() > dec as usize
+        || !integer.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit())
+    {
+        return None;
+    }
+    let integer = integer.parse::<u128>().ok()?.checked_mul(SCALE)?;
+    let fraction = if fraction.is_empty() { 0 } else { fraction.parse::<u128>().ok()? * 10u128.pow(MAX_DECIMALS as u32 - fraction.len() as u32) };
+    integer.checked_add(fraction)
+}
+
+/// Amount with `dec` decimals and trailing zeros trimmed
+pub fn format_amount(amount: u128, dec: u8) -> String {
+    let integer = amount / SCALE;
+    let fraction = (amount % SCALE) / 10u128.pow((MAX_DECIMALS - dec) as u32);
+    if fraction == 0 {
+        return integer.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = dec as usize);
+    format!("{}.{}", integer, fraction.trim_end_matches('0'))
+}
+
+/// Operation of a json inscription, `None` for anything that is not strictly a BRC-20 operation
+pub fn parse_operation(content_type: &[u8], content_encoding: &[u8], content: &[u8]) -> Option<Operation> {
+    let media_type = content_type.split(|byte| *byte == b';').next().unwrap_or_default();
+    if (media_type != b"text/plain" && media_type != b"application/json") || !content_encoding.is_empty() {
+        return None;
+    }
+    let json: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(content).ok()?;
+    let field = |name: &str| json.get(name).map(|value| value.as_str());
+    if field("p")?? != "brc-20" {
+        return None;
+    }
+    let tick = field("tick")??;
+    if tick.len() != TICK_LEN {
+        return None;
+    }
+    let tick = tick.to_lowercase();
+    match field("op")?? {
+        "deploy" => {
+            let dec = match field("dec") {
+                Some(dec) => dec.filter(|dec| dec.bytes().all(|byte| byte.is_ascii_digit()))?.parse::<u8>().ok().filter(|dec| *dec <= MAX_DECIMALS)?,
+                None => MAX_DECIMALS,
+            };
+            let max = parse_amount(field("max")??, dec).filter(|max| *max > 0 && *max <= MAX_SUPPLY)?;
+            let lim = match field("lim") {
+                Some(lim) => parse_amount(lim?, dec).filter(|lim| *lim > 0 && *lim <= MAX_SUPPLY)?,
+                None => max,
+            };
+            Some(Operation::Deploy { tick, max, lim, dec })
+        }
+        "mint" => Some(Operation::Mint { tick, amt: field("amt")??.to_string() }),
+        "transfer" => Some(Operation::Transfer { tick, amt: field("amt")??.to_string() }),
+        _ => None,
+    }
+}
+
+/// Ledger state stored before the block being indexed
+pub trait Brc20Source {
+    fn token(&self, tick: &str) -> Result<Option<Brc20Token>, AppError>;
+    /// Available and transferable balance
+    fn balance(&self, holder: &Brc20Holder) -> Result<(u128, u128), AppError>;
+    fn minted(&self, token: u32) -> Result<u128, AppError>;
+    /// Transfer inscription that has not moved yet
+    fn pending_transfer(&self, inscription: u32) -> Result<Option<Brc20Event>, AppError>;
+}
+
+struct DbSource<'a> {
+    read_tx: &'a ReadTransaction,
+}
+
+impl Brc20Source for DbSource<'_> {
+    fn token(&self, tick: &str) -> Result<Option<Brc20Token>, AppError> {
+        find_token(self.read_tx, tick)
+    }
+
+    fn balance(&self, holder: &Brc20Holder) -> Result<(u128, u128), AppError> {
+        let latest = match Brc20Balance::get_ids_by_holder(self.read_tx, holder)?.into_iter().max() {
+            Some(id) => Brc20Balance::get(self.read_tx, &id)?,
+            None => None,
+        };
+        Ok(latest.map_or((0, 0), |balance| (balance.available.0, balance.transferable.0)))
+    }
+
+    fn minted(&self, token: u32) -> Result<u128, AppError> {
+        minted(self.read_tx, token)
+    }
+
+    fn pending_transfer(&self, inscription: u32) -> Result<Option<Brc20Event>, AppError> {
+        let mut pending = None;
+        for id in Brc20Event::get_ids_by_inscription(self.read_tx, &inscription)? {
+            match Brc20Event::get(self.read_tx, &id)? {
+                Some(event) if event.op == Brc20Op::TRANSFER => return Ok(None),
+                Some(event) if event.op == Brc20Op::INSCRIBE_TRANSFER => pending = Some(event),
+                _ => {}
+            }
+        }
+        Ok(pending)
+    }
+}
+
+/// Ledger changes of a block, stored in the same write transaction as the block
+#[derive(Default)]
+pub struct Brc20Batch {
+    pub tokens: Vec<Brc20Token>,
+    pub events: Vec<Brc20Event>,
+    pub balances: Vec<Brc20Balance>,
+    pub supplies: Vec<Brc20Supply>,
+}
+
+/// Ids the batch continues from
+#[derive(Default, Clone, Copy)]
+pub struct NextIds {
+    pub token: u32,
+    pub event: u32,
+    pub balance: u32,
+    pub supply: u32,
+}
+
+impl NextIds {
+    fn load(read_tx: &ReadTransaction) -> Result<Self, AppError> {
+        Ok(NextIds {
+            token: Brc20Token::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+            event: Brc20Event::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+            balance: Brc20Balance::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+            supply: Brc20Supply::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+        })
+    }
+}
+
+/// Applies operations of a block in order on top of `source`, balances and supplies touched by the block get a new snapshot
+pub struct Ledger<'a, S: Brc20Source> {
+    source: &'a S,
+    height: u32,
+    next: NextIds,
+    tokens: HashMap<String, Option<Brc20Token>>,
+    balances: HashMap<Brc20Holder, (u128, u128)>,
+    // holders in order of their first change, so that snapshot ids follow the block
+    changed_balances: Vec<Brc20Holder>,
+    minted: BTreeMap<u32, u128>,
+    pending: HashMap<u32, Option<Brc20Event>>,
+    batch: Brc20Batch,
+}
+
+impl<'a, S: Brc20Source> Ledger<'a, S> {
+    pub fn new(source: &'a S, height: u32, next: NextIds) -> Self {
+        Ledger {
+            source,
+            height,
+            next,
+            tokens: HashMap::new(),
+            balances: HashMap::new(),
+            changed_balances: Vec::new(),
+            minted: BTreeMap::new(),
+            pending: HashMap::new(),
+            batch: Brc20Batch::default(),
+        }
+    }
+
+    fn token(&mut self, tick: &str) -> Result<Option<Brc20Token>, AppError> {
+        if !self.tokens.contains_key(tick) {
+            let token = self.source.token(tick)?;
+            self.tokens.insert(tick.to_string(), token);
+        }
+        Ok(self.tokens[tick].clone())
+    }
+
+    fn balance(&mut self, token: u32, script: &[u8]) -> Result<&mut (u128, u128), AppError> {
+        let holder = Brc20Holder { token, script: script.to_vec() };
+        if !self.balances.contains_key(&holder) {
+            let balance = self.source.balance(&holder)?;
+            self.balances.insert(holder.clone(), balance);
+            self.changed_balances.push(holder.clone());
+        }
+        Ok(self.balances.get_mut(&holder).unwrap())
+    }
+
+    fn minted(&mut self, token: u32) -> Result<&mut u128, AppError> {
+        if !self.minted.contains_key(&token) {
+            let minted = self.source.minted(token)?;
+            self.minted.insert(token, minted);
+        }
+        Ok(self.minted.get_mut(&token).unwrap())
+    }
+
+    fn event(&mut self, token: u32, op: Brc20Op, inscription: u32, from: &[u8], to: &[u8], amount: u128) -> Brc20Event {
+        let event = Brc20Event {
+            id: Brc20EventId(self.next.event),
+            token,
+            op,
+            inscription,
+            from: ScriptHash::from_script(Script::from_bytes(from)),
+            to: ScriptHash::from_script(Script::from_bytes(to)),
+            amount: Brc20Amount(amount),
+            height: self.height,
+        };
+        self.next.event += 1;
+        self.batch.events.push(event.clone());
+        event
+    }
+
+    /// New inscription landing on an output with `owner` script, `None` if it was inscribed straight to fees.
+    /// Cursed inscriptions are not operations, as in the indexers that BRC-20 is defined by.
+    pub fn inscribed(&mut self, inscription: &Inscription, owner: Option<&[u8]>) -> Result<(), AppError> {
+        if inscription.cursed {
+            return Ok(());
+        }
+        let (Some(operation), Some(owner)) = (parse_operation(&inscription.content_type.0, &inscription.content_encoding.0, &inscription.content.0), owner) else {
+            return Ok(());
+        };
+        let number = inscription.id.0;
+        match operation {
+            Operation::Deploy { tick, max, lim, dec } => {
+                if self.token(&tick)?.is_some() {
+                    return Ok(());
+                }
+                let token =
+                    Brc20Token { id: Brc20TokenId(self.next.token), tick: Tick(tick.clone()), max: Brc20Amount(max), lim: Brc20Amount(lim), dec, inscription: number, height: self.height };
+                self.next.token += 1;
+                self.event(token.id.0, Brc20Op::DEPLOY, number, owner, owner, max);
+                self.tokens.insert(tick, Some(token.clone()));
+                self.minted.insert(token.id.0, 0);
+                self.batch.tokens.push(token);
+            }
+            Operation::Mint { tick, amt } => {
+                let Some(token) = self.token(&tick)? else {
+                    return Ok(());
+                };
+                let Some(amount) = parse_amount(&amt, token.dec).filter(|amount| *amount > 0 && *amount <= token.lim.0) else {
+                    return Ok(());
+                };
+                let minted = self.minted(token.id.0)?;
+                // the last mint gets whatever is left of the supply
+                let amount = amount.min(token.max.0.saturating_sub(*minted));
+                if amount == 0 {
+                    return Ok(());
+                }
+                *minted += amount;
+                self.balance(token.id.0, owner)?.0 += amount;
+                self.event(token.id.0, Brc20Op::MINT, number, owner, owner, amount);
+            }
+            Operation::Transfer { tick, amt } => {
+                let Some(token) = self.token(&tick)? else {
+                    return Ok(());
+                };
+                let Some(amount) = parse_amount(&amt, token.dec).filter(|amount| *amount > 0) else {
+                    return Ok(());
+                };
+                let balance = self.balance(token.id.0, owner)?;
+                if balance.0 < amount {
+                    return Ok(());
+                }
+                balance.0 -= amount;
+                balance.1 += amount;
+                let event = self.event(token.id.0, Brc20Op::INSCRIBE_TRANSFER, number, owner, owner, amount);
+                self.pending.insert(number, Some(event));
+            }
+        }
+        Ok(())
+    }
+
+    /// Existing inscription moved to an output with `receiver` script, `None` if it was spent to fees which returns the amount to the sender
+    pub fn moved(&mut self, inscription: u32, receiver: Option<&[u8]>) -> Result<(), AppError> {
+        let pending = match self.pending.get(&inscription) {
+            Some(pending) => pending.clone(),
+            None => self.source.pending_transfer(inscription)?,
+        };
+        let Some(inscribed) = pending else {
+            return Ok(());
+        };
+        // only the first move of a transfer inscription counts
+        self.pending.insert(inscription, None);
+        let amount = inscribed.amount.0;
+        let sender = inscribed.from.to_script().into_bytes();
+        let receiver = receiver.unwrap_or(&sender).to_vec();
+        self.balance(inscribed.token, &sender)?.1 -= amount;
+        self.balance(inscribed.token, &receiver)?.0 += amount;
+        self.event(inscribed.token, Brc20Op::TRANSFER, inscription, &sender, &receiver, amount);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Brc20Batch {
+        for holder in self.changed_balances {
+            let (available, transferable) = self.balances[&holder];
+            self.batch.balances.push(Brc20Balance {
+                id: Brc20BalanceId(self.next.balance),
+                token: holder.token,
+                script: ScriptHash::from_script(Script::from_bytes(&holder.script)),
+                holder,
+                available: Brc20Amount(available),
+                transferable: Brc20Amount(transferable),
+                height: self.height,
+            });
+            self.next.balance += 1;
+        }
+        for (token, minted) in self.minted {
+            self.batch.supplies.push(Brc20Supply { id: Brc20SupplyId(self.next.supply), token, minted: Brc20Amount(minted), height: self.height });
+            self.next.supply += 1;
+        }
+        self.batch
+    }
+}
+
+/// Applies inscriptions and transfers of a block in the order `ordinals` found them
+pub fn index_block(read_tx: &ReadTransaction, block: &Block, ordinals: &OrdinalsBatch) -> Result<Brc20Batch, AppError> {
+    if ordinals.transfers.is_empty() {
+        return Ok(Brc20Batch::default());
+    }
+    let source = DbSource { read_tx };
+    let mut ledger = Ledger::new(&source, block.id.0, NextIds::load(read_tx)?);
+    let inscriptions: HashMap<u32, &Inscription> = ordinals.inscriptions.iter().map(|inscription| (inscription.id.0, inscription)).collect();
+    let txs: HashMap<[u8; 32], &Transaction> = block.transactions.iter().map(|tx| (tx.hash.0, tx)).collect();
+    let coinbase = block.transactions.first().map(|tx| tx.hash.0);
+    let mut inscribed = HashSet::new();
+    for transfer in &ordinals.transfers {
+        // inscriptions landing in coinbase were spent to fees
+        let owner = if Some(transfer.outpoint.tx_hash.0) == coinbase {
+            None
+        } else {
+            txs.get(&transfer.outpoint.tx_hash.0).and_then(|tx| tx.utxos.get(transfer.outpoint.vout as usize)).map(|utxo| utxo.script_hash.to_script())
+        };
+        let owner = owner.as_ref().map(|script| script.as_bytes());
+        // the first transfer of a new inscription is its genesis location
+        match inscriptions.get(&transfer.inscription).filter(|_| inscribed.insert(transfer.inscription)) {
+            Some(inscription) => ledger.inscribed(inscription, owner)?,
+            None => ledger.moved(transfer.inscription, owner)?,
+        }
+    }
+    Ok(ledger.finish())
+}
+
+/// Ledger rows of blocks at `heights` that are about to be deleted by a reorg, earlier snapshots become current again
+#[derive(Default)]
+pub struct Brc20Rollback {
+    pub tokens: Vec<Brc20TokenId>,
+    pub events: Vec<Brc20EventId>,
+    pub balances: Vec<Brc20BalanceId>,
+    pub supplies: Vec<Brc20SupplyId>,
+}
+
+pub fn rollback(read_tx: &ReadTransaction, heights: &[Height]) -> Result<Brc20Rollback, AppError> {
+    let mut rollback = Brc20Rollback::default();
+    for height in heights {
+        rollback.tokens.extend(Brc20Token::get_ids_by_height(read_tx, &height.0)?);
+        rollback.events.extend(Brc20Event::get_ids_by_height(read_tx, &height.0)?);
+        rollback.balances.extend(Brc20Balance::get_ids_by_height(read_tx, &height.0)?);
+        rollback.supplies.extend(Brc20Supply::get_ids_by_height(read_tx, &height.0)?);
+    }
+    Ok(rollback)
+}
+
+pub fn find_token(read_tx: &ReadTransaction, tick: &str) -> Result<Option<Brc20Token>, AppError> {
+    match Brc20Token::get_ids_by_tick(read_tx, &Tick(tick.to_lowercase()))?.into_iter().next() {
+        Some(id) => Brc20Token::get(read_tx, &id),
+        None => Ok(None),
+    }
+}
+
+fn minted(read_tx: &ReadTransaction, token: u32) -> Result<u128, AppError> {
+    match Brc20Supply::get_ids_by_token(read_tx, &token)?.into_iter().max() {
+        Some(id) => Ok(Brc20Supply::get(read_tx, &id)?.map_or(0, |supply| supply.minted.0)),
+        None => Ok(0),
+    }
+}
+
+/// Latest snapshots of `ids` per holder
+fn latest_balances(read_tx: &ReadTransaction, ids: Vec<Brc20BalanceId>) -> Result<Vec<Brc20Balance>, AppError> {
+    let mut latest: HashMap<Brc20Holder, Brc20Balance> = HashMap::new();
+    for id in ids {
+        if let Some(balance) = Brc20Balance::get(read_tx, &id)?
+            && latest.get(&balance.holder).is_none_or(|current| current.id < balance.id)
+        {
+            latest.insert(balance.holder.clone(), balance);
+        }
+    }
+    Ok(latest.into_values().collect())
+}
+
+fn display_script(script: &[u8]) -> String {
+    match bitcoin::Address::from_script(Script::from_bytes(script), bitcoin::Network::Bitcoin) {
+        Ok(address) => address.to_string(),
+        Err(_) => script.to_lower_hex_string(),
+    }
+}
+
+/// Ledger refers to inscriptions by sequence, views by `ord`'s number
+fn number_of(read_tx: &ReadTransaction, sequence: u32) -> Result<i32, AppError> {
+    inscription_number(read_tx, sequence)?.ok_or_else(|| AppError::Internal(format!("Inscription of sequence {} is missing", sequence)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenView {
+    pub tick: String,
+    pub max: String,
+    pub lim: String,
+    pub dec: u8,
+    pub minted: String,
+    pub deploy_inscription: i32,
+    pub height: u32,
+}
+
+impl TokenView {
+    fn new(read_tx: &ReadTransaction, token: &Brc20Token, minted: u128) -> Result<Self, AppError> {
+        Ok(TokenView {
+            tick: token.tick.0.clone(),
+            max: format_amount(token.max.0, token.dec),
+            lim: format_amount(token.lim.0, token.dec),
+            dec: token.dec,
+            minted: format_amount(minted, token.dec),
+            deploy_inscription: number_of(read_tx, token.inscription)?,
+            height: token.height,
+        })
+    }
+}
+
+/// Holder is an address, or script hex if it has none
+#[derive(Debug, Serialize)]
+pub struct BalanceView {
+    pub tick: String,
+    pub holder: String,
+    pub available: String,
+    pub transferable: String,
+    pub overall: String,
+}
+
+impl BalanceView {
+    fn new(token: &Brc20Token, balance: &Brc20Balance) -> Self {
+        BalanceView {
+            tick: token.tick.0.clone(),
+            holder: display_script(balance.script.to_script().as_bytes()),
+            available: format_amount(balance.available.0, token.dec),
+            transferable: format_amount(balance.transferable.0, token.dec),
+            overall: format_amount(balance.available.0 + balance.transferable.0, token.dec),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventView {
+    pub op: &'static str,
+    pub inscription: i32,
+    pub from: String,
+    pub to: String,
+    pub amount: String,
+    pub height: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub before: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+/// Latest deployed tokens first, starting below `before` token id if given
+pub fn list_tokens(read_tx: &ReadTransaction, params: &PageParams) -> Result<Vec<TokenView>, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let until = match params.before {
+        Some(before) => before,
+        None => Brc20Token::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+    };
+    let mut result = Vec::with_capacity(limit);
+    for id in (0..until).rev() {
+        if result.len() >= limit {
+            break;
+        }
+        if let Some(token) = Brc20Token::get(read_tx, &Brc20TokenId(id))? {
+            result.push(TokenView::new(read_tx, &token, minted(read_tx, id)?)?);
+        }
+    }
+    Ok(result)
+}
+
+pub fn token_view(read_tx: &ReadTransaction, tick: &str) -> Result<Option<TokenView>, AppError> {
+    match find_token(read_tx, tick)? {
+        Some(token) => Ok(Some(TokenView::new(read_tx, &token, minted(read_tx, token.id.0)?)?)),
+        None => Ok(None),
+    }
+}
+
+/// Holders with a non zero balance, largest first
+pub fn holders(read_tx: &ReadTransaction, token: &Brc20Token, limit: usize) -> Result<Vec<BalanceView>, AppError> {
+    let mut balances = latest_balances(read_tx, Brc20Balance::get_ids_by_token(read_tx, &token.id.0)?)?;
+    balances.retain(|balance| balance.available.0 + balance.transferable.0 > 0);
+    balances.sort_by(|a, b| (b.available.0 + b.transferable.0).cmp(&(a.available.0 + a.transferable.0)).then_with(|| a.script.0.cmp(&b.script.0)));
+    Ok(balances.iter().take(limit).map(|balance| BalanceView::new(token, balance)).collect())
+}
+
+/// Operations of a token, latest first, starting below `before` event id if given
+pub fn token_events(read_tx: &ReadTransaction, token: &Brc20Token, params: &PageParams) -> Result<Vec<EventView>, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let mut ids = Brc20Event::get_ids_by_token(read_tx, &token.id.0)?;
+    ids.retain(|id| params.before.is_none_or(|before| id.0 < before));
+    ids.sort();
+    let mut result = Vec::with_capacity(limit);
+    for id in ids.into_iter().rev().take(limit) {
+        if let Some(event) = Brc20Event::get(read_tx, &id)? {
+            result.push(EventView {
+                op: event.op.name(),
+                inscription: number_of(read_tx, event.inscription)?,
+                from: display_script(event.from.to_script().as_bytes()),
+                to: display_script(event.to.to_script().as_bytes()),
+                amount: format_amount(event.amount.0, token.dec),
+                height: event.height,
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// Token balances of an address
+pub fn address_balances(read_tx: &ReadTransaction, address: &bitcoin::Address) -> Result<Vec<BalanceView>, AppError> {
+    let script = ScriptHash::from_script(&address.script_pubkey());
+    let mut result = Vec::new();
+    for balance in latest_balances(read_tx, Brc20Balance::get_ids_by_script(read_tx, &script)?)? {
+        if let Some(token) = Brc20Token::get(read_tx, &Brc20TokenId(balance.token))? {
+            result.push(BalanceView::new(&token, &balance));
+        }
+    }
+    result.sort_by(|a, b| a.tick.cmp(&b.tick));
+    Ok(result)
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/brc20/tokens", get(tokens))
+        .route("/brc20/token/{tick}", get(token))
+        .route("/brc20/token/{tick}/holders", get(token_holders))
+        .route("/brc20/token/{tick}/events", get(events))
+        .route("/address/{address}/brc20", get(balances_of_address))
+        .layer(Extension(pool))
+}
+
+async fn tokens(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<PageParams>) -> Response {
+    json_response(pool.run(move || list_tokens(&state.db.begin_read()?, &params)).await)
+}
+
+async fn token(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(tick): Path<String>) -> Response {
+    let query_tick = tick.clone();
+    match pool.run(move || token_view(&state.db.begin_read()?, &query_tick)).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Token {} not deployed", tick)),
+        result => json_response(result),
+    }
+}
+
+async fn token_holders(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(tick): Path<String>,
+    Query(params): Query<PageParams>,
+) -> Response {
+    let query_tick = tick.clone();
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let result = pool
+        .run(move || {
+            let read_tx = state.db.begin_read()?;
+            match find_token(&read_tx, &query_tick)? {
+                Some(token) => holders(&read_tx, &token, limit).map(Some),
+                None => Ok(None),
+            }
+        })
+        .await;
+    match result {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Token {} not deployed", tick)),
+        result => json_response(result),
+    }
+}
+
+async fn events(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(tick): Path<String>,
+    Query(params): Query<PageParams>,
+) -> Response {
+    let query_tick = tick.clone();
+    let result = pool
+        .run(move || {
+            let read_tx = state.db.begin_read()?;
+            match find_token(&read_tx, &query_tick)? {
+                Some(token) => token_events(&read_tx, &token, &params).map(Some),
+                None => Ok(None),
+            }
+        })
+        .await;
+    match result {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Token {} not deployed", tick)),
+        result => json_response(result),
+    }
+}
+
+async fn balances_of_address(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(address): Path<String>) -> Response {
+    let address = match codec::parse_address(&address) {
+        Ok(address) => address,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    json_response(pool.run(move || address_balances(&state.db.begin_read()?, &address)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Content, ContentEncoding, ContentType, InscriptionNumber, InscriptionSequence, TxHash};
+
+    #[derive(Default)]
+    struct EmptySource;
+
+    impl Brc20Source for EmptySource {
+        fn token(&self, _tick: &str) -> Result<Option<Brc20Token>, AppError> {
+            Ok(None)
+        }
+        fn balance(&self, _holder: &Brc20Holder) -> Result<(u128, u128), AppError> {
+            Ok((0, 0))
+        }
+        fn minted(&self, _token: u32) -> Result<u128, AppError> {
+            Ok(0)
+        }
+        fn pending_transfer(&self, _inscription: u32) -> Result<Option<Brc20Event>, AppError> {
+            Ok(None)
+        }
+    }
+
+    fn inscription(sequence: u32, json: &str) -> Inscription {
+        Inscription {
+            id: InscriptionSequence(sequence),
+            number: InscriptionNumber(sequence as i32),
+            genesis_tx: TxHash([0; 32]),
+            genesis_index: 0,
+            height: 1,
+            content_type: ContentType(b"text/plain;charset=utf-8".to_vec()),
+            content_encoding: ContentEncoding(vec![]),
+            content: Content(json.as_bytes().to_vec()),
+            cursed: false,
+        }
+    }
+
+    fn units(amount: u128) -> u128 {
+        amount * SCALE
+    }
+
+    #[test]
+    fn test_amounts() {
+        assert_eq!(parse_amount("1000", 18), Some(units(1000)));
+        assert_eq!(parse_amount("1.5", 1), Some(units(15) / 10));
+        assert_eq!(parse_amount("0.000000000000000001", 18), Some(1));
+        assert_eq!(parse_amount("007", 0), Some(units(7)));
+        for invalid in ["", "1.", ".5", "-1", "+1", " 1", "1e3", "1.5.5", "0x10", "١"] {
+            assert_eq!(parse_amount(invalid, 18), None, "{}", invalid);
+        }
+        // more decimals than the token has
+        assert_eq!(parse_amount("1.55", 1), None);
+        assert_eq!(format_amount(units(15) / 10, 18), "1.5");
+        assert_eq!(format_amount(units(21_000_000), 8), "21000000");
+        assert_eq!(format_amount(1, 18), "0.000000000000000001");
+    }
+
+    #[test]
+    fn test_operations() {
+        let parse = |json: &str| parse_operation(b"text/plain;charset=utf-8", b"", json.as_bytes());
+        assert_eq!(
+            parse(r#"{"p":"brc-20","op":"deploy","tick":"ORDI","max":"21000000","lim":"1000"}"#),
+            Some(Operation::Deploy { tick: "ordi".to_string(), max: units(21_000_000), lim: units(1000), dec: 18 })
+        );
+        assert_eq!(
+            parse(r#"{"p":"brc-20","op":"deploy","tick":"sats","max":"100","dec":"0"}"#),
+            Some(Operation::Deploy { tick: "sats".to_string(), max: units(100), lim: units(100), dec: 0 })
+        );
+        // 4 bytes, not 4 characters
+        assert!(parse(r#"{"p":"brc-20","op":"deploy","tick":"🔥","max":"1"}"#).is_some());
+        assert_eq!(parse(r#"{"p":"brc-20","op":"mint","tick":"ordi","amt":"1000"}"#), Some(Operation::Mint { tick: "ordi".to_string(), amt: "1000".to_string() }));
+        for invalid in [
+            r#"{"p":"brc-20","op":"deploy","tick":"ord","max":"1"}"#,
+            r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":1000}"#,
+            r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"0"}"#,
+            r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"18446744073709551616"}"#,
+            r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"1","dec":"19"}"#,
+            r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"1.5","dec":"0"}"#,
+            r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"1","dec":"+1"}"#,
+            r#"{"p":"brc20","op":"mint","tick":"ordi","amt":"1"}"#,
+            r#"{"p":"brc-20","op":"burn","tick":"ordi","amt":"1"}"#,
+            r#"{"p":"brc-20","op":"mint","tick":"ordi"}"#,
+            r#"["brc-20"]"#,
+            "not json",
+        ] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+        let mint = br#"{"p":"brc-20","op":"mint","tick":"ordi","amt":"1"}"#;
+        assert!(parse_operation(b"application/json", b"", mint).is_some());
+        assert_eq!(parse_operation(b"image/png", b"", mint), None);
+        assert_eq!(parse_operation(b"text/plain", b"br", mint), None);
+    }
+
+    #[test]
+    fn test_ledger() {
+        let (alice, bob) = (b"alice".as_slice(), b"bob".as_slice());
+        let source = EmptySource;
+        let mut ledger = Ledger::new(&source, 1, NextIds::default());
+        ledger.inscribed(&inscription(0, r#"{"p":"brc-20","op":"deploy","tick":"test","max":"25","lim":"10","dec":"1"}"#), Some(alice)).unwrap();
+        // the first deploy of a tick wins
+        ledger.inscribed(&inscription(1, r#"{"p":"brc-20","op":"deploy","tick":"TEST","max":"99"}"#), Some(bob)).unwrap();
+        ledger.inscribed(&inscription(2, r#"{"p":"brc-20","op":"mint","tick":"test","amt":"10.1"}"#), Some(alice)).unwrap();
+        ledger.inscribed(&inscription(3, r#"{"p":"brc-20","op":"mint","tick":"test","amt":"10"}"#), Some(alice)).unwrap();
+        ledger.inscribed(&inscription(4, r#"{"p":"brc-20","op":"mint","tick":"test","amt":"10"}"#), Some(bob)).unwrap();
+        // the last mint is cut to the rest of supply, further ones are void
+        ledger.inscribed(&inscription(5, r#"{"p":"brc-20","op":"mint","tick":"test","amt":"10"}"#), Some(bob)).unwrap();
+        ledger.inscribed(&inscription(6, r#"{"p":"brc-20","op":"mint","tick":"test","amt":"1"}"#), Some(bob)).unwrap();
+        // mint inscribed to fees has no owner
+        ledger.inscribed(&inscription(7, r#"{"p":"brc-20","op":"mint","tick":"test","amt":"1"}"#), None).unwrap();
+        // transfers above available balance are void
+        ledger.inscribed(&inscription(8, r#"{"p":"brc-20","op":"transfer","tick":"test","amt":"10.5"}"#), Some(alice)).unwrap();
+        ledger.inscribed(&inscription(9, r#"{"p":"brc-20","op":"transfer","tick":"test","amt":"4.5"}"#), Some(alice)).unwrap();
+        ledger.inscribed(&inscription(10, r#"{"p":"brc-20","op":"transfer","tick":"test","amt":"5"}"#), Some(alice)).unwrap();
+        assert_eq!(ledger.balances[&Brc20Holder { token: 0, script: alice.to_vec() }], (units(5) / 10, units(95) / 10));
+        ledger.moved(9, Some(bob)).unwrap();
+        // only the first move counts
+        ledger.moved(9, Some(alice)).unwrap();
+        // transfer spent to fees returns to sender
+        ledger.moved(10, None).unwrap();
+        ledger.moved(3, Some(bob)).unwrap();
+
+        let batch = ledger.finish();
+        assert_eq!(batch.tokens.len(), 1);
+        let ops: Vec<&str> = batch.events.iter().map(|event| event.op.name()).collect();
+        assert_eq!(ops, ["deploy", "mint", "mint", "mint", "inscribe_transfer", "inscribe_transfer", "transfer", "transfer"]);
+        let balance = |script: &[u8]| batch.balances.iter().find(|balance| balance.script.to_script().as_bytes() == script).map(|balance| (balance.available.0, balance.transferable.0));
+        assert_eq!(balance(alice), Some((units(55) / 10, 0)));
+        assert_eq!(balance(bob), Some((units(195) / 10, 0)));
+        assert_eq!(batch.supplies.iter().map(|supply| supply.minted.0).collect::<Vec<_>>(), vec![units(25)]);
+    }
+
+    /// Examples of the BRC-20 indexing rules, https://layer1.gitbook.io/layer1-foundation/protocols/brc-20/indexing
+    #[test]
+    fn test_indexing_rules() {
+        let (alice, bob) = (b"alice".as_slice(), b"bob".as_slice());
+        let source = EmptySource;
+        let mut ledger = Ledger::new(&source, 1, NextIds::default());
+        let mut number = 0;
+        let mut inscribe = |ledger: &mut Ledger<EmptySource>, json: &str, owner: &[u8], cursed: bool| {
+            ledger.inscribed(&Inscription { cursed, ..inscription(number, json) }, Some(owner)).unwrap();
+            number += 1;
+            number - 1
+        };
+        let available = |ledger: &Ledger<EmptySource>, owner: &[u8]| {
+            ledger.balances.get(&Brc20Holder { token: 0, script: owner.to_vec() }).map_or(0, |balance| balance.0)
+        };
+
+        // cursed deploy does not take the tick
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"1","lim":"1"}"#, bob, true);
+        // extra fields are ignored, numbers must be strings
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"21000000","lim":"1000","memo":"x","dec":18}"#, alice, false);
+        assert!(ledger.batch.tokens.is_empty());
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"deploy","tick":"ordi","max":"21000000","lim":"1000","memo":"x"}"#, alice, false);
+        assert_eq!(ledger.batch.tokens.iter().map(|token| (token.tick.0.as_str(), token.inscription)).collect::<Vec<_>>(), vec![("ordi", 2)]);
+
+        // mints above limit or of zero are void, cursed ones too
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"mint","tick":"ordi","amt":"1000.000000000000000001"}"#, alice, false);
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"mint","tick":"ordi","amt":"0"}"#, alice, false);
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"mint","tick":"ORDI","amt":"1000"}"#, alice, true);
+        assert_eq!(available(&ledger, alice), 0);
+        // ticks are case insensitive, leading zeros are fine and a repeated key takes its last value
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"mint","tick":"ORDI","amt":"0500"}"#, alice, false);
+        inscribe(&mut ledger, r#"{"p":"brc-20","op":"mint","tick":"ordi","amt":"1","amt":"250"}"#, alice, false);
+        assert_eq!(available(&ledger, alice), units(750));
+
+        // cursed transfer inscription does not lock balance and its move is no transfer
+        let cursed_transfer = inscribe(&mut ledger, r#"{"p":"brc-20","op":"transfer","tick":"ordi","amt":"100"}"#, alice, true);
+        ledger.moved(cursed_transfer, Some(bob)).unwrap();
+        assert_eq!((available(&ledger, alice), available(&ledger, bob)), (units(750), 0));
+        let transfer = inscribe(&mut ledger, r#"{"p":"brc-20","op":"transfer","tick":"ordi","amt":"100"}"#, alice, false);
+        ledger.moved(transfer, Some(bob)).unwrap();
+        assert_eq!((available(&ledger, alice), available(&ledger, bob)), (units(650), units(100)));
+
+        let ops: Vec<&str> = ledger.finish().events.iter().map(|event| event.op.name()).collect();
+        assert_eq!(ops, ["deploy", "mint", "mint", "inscribe_transfer", "transfer"]);
+    }
+}
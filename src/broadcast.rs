@@ -0,0 +1,467 @@
+use crate::backpressure::{json_response, QueryPool};
+use crate::btc_client::BtcClient;
+use crate::config::BroadcastSettings;
+use crate::decode::{self, DecodeRequest};
+use crate::model::{ExplorerError, Transaction, TxHash};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json};
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{OutPoint, TxOut};
+use chrono::Utc;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// bitcoind evicts transactions that are not mined within two weeks
+const EXPIRY_SECS: i64 = 14 * 24 * 3600;
+
+// JSON-RPC error codes of bitcoind's sendrawtransaction
+const RPC_DESERIALIZATION_ERROR: i32 = -22;
+const RPC_VERIFY_ERROR: i32 = -25;
+const RPC_VERIFY_REJECTED: i32 = -26;
+const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+
+struct PendingTx {
+    tx: bitcoin::Transaction,
+    fee: u64,
+    received: i64,
+}
+
+/// Transactions relayed by `POST /tx` until they get indexed, expire or leave the node's mempool. It is not the node's mempool,
+/// only what went through this explorer, so that its inputs and outputs are known before the next block.
+#[derive(Default)]
+pub struct Mempool {
+    txs: Mutex<HashMap<[u8; 32], PendingTx>>,
+    synced: Mutex<Option<Instant>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MempoolTx {
+    /// in display byte order
+    pub tx_hash: String,
+    pub fee: u64,
+    pub vsize: u64,
+    pub fee_rate: f64,
+    /// unix time of the relay
+    pub received: i64,
+}
+
+impl Mempool {
+    /// Adds a transaction the node accepted, pending ones spending the same outputs were replaced by it and are dropped with their descendants
+    fn insert(&self, txid: [u8; 32], tx: bitcoin::Transaction, fee: u64) {
+        let mut txs = self.txs.lock().unwrap();
+        let spent: HashSet<OutPoint> = tx.input.iter().map(|input| input.previous_output).collect();
+        let mut replaced: Vec<[u8; 32]> = txs
+            .iter()
+            .filter(|(id, pending)| **id != txid && pending.tx.input.iter().any(|input| spent.contains(&input.previous_output)))
+            .map(|(id, _)| *id)
+            .collect();
+        while let Some(replaced_txid) = replaced.pop() {
+            if txs.remove(&replaced_txid).is_some() {
+                replaced.extend(
+                    txs.iter()
+                        .filter(|(_, pending)| pending.tx.input.iter().any(|input| input.previous_output.txid.to_byte_array() == replaced_txid))
+                        .map(|(id, _)| *id),
+                );
+            }
+        }
+        txs.insert(txid, PendingTx { tx, fee, received: Utc::now().timestamp() });
+    }
+
+    /// Whether pending transactions are due to be compared with the node's mempool, it counts as synced right away
+    /// so that concurrent requests do not all ask the node
+    fn sync_due(&self, interval: Duration) -> bool {
+        if self.txs.lock().unwrap().is_empty() {
+            return false;
+        }
+        let mut synced = self.synced.lock().unwrap();
+        if synced.is_some_and(|synced| synced.elapsed() < interval) {
+            return false;
+        }
+        *synced = Some(Instant::now());
+        true
+    }
+
+    /// Drops transactions the node no longer has, they were replaced, evicted or mined. Those relayed since `asked`
+    /// may be missing from the node's answer, so they are kept.
+    fn retain_in_node(&self, node_txids: &[bitcoin::Txid], asked: i64) {
+        let node_txids: HashSet<[u8; 32]> = node_txids.iter().map(|txid| txid.to_byte_array()).collect();
+        self.txs.lock().unwrap().retain(|txid, pending| node_txids.contains(txid) || pending.received >= asked);
+    }
+
+    /// Drops transactions that got indexed or expired
+    fn prune(&self, read_tx: &ReadTransaction) -> Result<(), AppError> {
+        let expired_before = Utc::now().timestamp() - EXPIRY_SECS;
+        let txids: Vec<[u8; 32]> = self.txs.lock().unwrap().keys().copied().collect();
+        let mut stale = Vec::new();
+        for txid in txids {
+            if !Transaction::get_ids_by_hash(read_tx, &TxHash(txid))?.is_empty() {
+                stale.push(txid);
+            }
+        }
+        let mut txs = self.txs.lock().unwrap();
+        stale.iter().for_each(|txid| {
+            txs.remove(txid);
+        });
+        txs.retain(|_, pending| pending.received >= expired_before);
+        Ok(())
+    }
+
+    /// Output created by a pending transaction
+    fn output(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        let txs = self.txs.lock().unwrap();
+        txs.get(&outpoint.txid.to_byte_array()).and_then(|pending| pending.tx.output.get(outpoint.vout as usize).cloned())
+    }
+
+    pub fn txs(&self, read_tx: &ReadTransaction) -> Result<Vec<MempoolTx>, AppError> {
+        self.prune(read_tx)?;
+        Ok(self.view())
+    }
+
+    /// Pending transactions, latest relayed first
+    fn view(&self) -> Vec<MempoolTx> {
+        let txs = self.txs.lock().unwrap();
+        let mut view: Vec<MempoolTx> = txs
+            .iter()
+            .map(|(txid, pending)| {
+                let vsize = pending.tx.vsize() as u64;
+                let tx_hash = bitcoin::Txid::from_byte_array(*txid).to_string();
+                MempoolTx { tx_hash, fee: pending.fee, vsize, fee_rate: pending.fee as f64 / vsize as f64, received: pending.received }
+            })
+            .collect();
+        view.sort_by_key(|tx| std::cmp::Reverse(tx.received));
+        view
+    }
+}
+
+/// Reason a transaction is not relayed, `code` is stable for clients to branch on and `error` is for humans
+#[derive(Debug, Serialize)]
+pub struct Rejection {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub error: String,
+}
+
+impl Rejection {
+    fn new(status: StatusCode, code: &'static str, error: String) -> Self {
+        Rejection { status, code, error }
+    }
+}
+
+impl From<AppError> for Rejection {
+    fn from(e: AppError) -> Self {
+        Rejection::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", e.to_string())
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+fn outpoint_name(outpoint: &OutPoint) -> String {
+    format!("{}:{}", outpoint.txid.to_byte_array().to_lower_hex_string(), outpoint.vout)
+}
+
+/// Refuses outputs that bitcoind would not relay as dust, OP_RETURN outputs are exempt
+pub fn check_outputs(tx: &bitcoin::Transaction) -> Result<(), Rejection> {
+    for (vout, output) in tx.output.iter().enumerate() {
+        let dust_limit = output.script_pubkey.minimal_non_dust();
+        if !output.script_pubkey.is_op_return() && output.value < dust_limit {
+            return Err(Rejection::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "dust",
+                format!("Output {} of {} sat is below the dust limit of {} sat", vout, output.value.to_sat(), dust_limit.to_sat()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn check_fee_rate(fee: u64, vsize: u64, settings: &BroadcastSettings) -> Result<f64, Rejection> {
+    let fee_rate = fee as f64 / vsize as f64;
+    if fee_rate < settings.min_fee_rate {
+        Err(Rejection::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "fee_too_low",
+            format!("Fee rate {:.2} sat/vB is below the minimum of {} sat/vB", fee_rate, settings.min_fee_rate),
+        ))
+    } else if fee_rate > settings.max_fee_rate {
+        Err(Rejection::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "fee_too_high",
+            format!("Fee rate {:.2} sat/vB exceeds the maximum of {} sat/vB", fee_rate, settings.max_fee_rate),
+        ))
+    } else {
+        Ok(fee_rate)
+    }
+}
+
+/// Checks that a transaction spends existing unspent outputs, indexed or pending, creates no dust
+/// and pays a sane fee rate, returns its fee. Conflicts with pending transactions are left to the node as they may be replacements.
+pub fn precheck(read_tx: &ReadTransaction, mempool: &Mempool, settings: &BroadcastSettings, tx: &bitcoin::Transaction) -> Result<u64, Rejection> {
+    if tx.is_coinbase() {
+        return Err(Rejection::new(StatusCode::BAD_REQUEST, "invalid_tx", "Coinbase transactions cannot be relayed".to_string()));
+    }
+    let txid = tx.compute_txid().to_byte_array();
+    if !Transaction::get_ids_by_hash(read_tx, &TxHash(txid))?.is_empty() {
+        return Err(Rejection::new(StatusCode::CONFLICT, "already_in_chain", format!("Transaction {} is already indexed", txid.to_lower_hex_string())));
+    }
+    let mut input_sum = 0u64;
+    for tx_in in &tx.input {
+        let outpoint = &tx_in.previous_output;
+        let amount = match mempool.output(outpoint) {
+            Some(output) => output.value.to_sat(),
+            None => match decode::find_prevout(read_tx, outpoint)? {
+                Some((pointer, _)) if decode::is_spent(read_tx, &pointer)? => {
+                    return Err(Rejection::new(StatusCode::CONFLICT, "inputs_spent", format!("Output {} is already spent", outpoint_name(outpoint))));
+                }
+                Some((_, utxo)) => utxo.amount,
+                None => {
+                    return Err(Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "missing_inputs", format!("Output {} does not exist", outpoint_name(outpoint))));
+                }
+            },
+        };
+        input_sum += amount;
+    }
+    check_outputs(tx)?;
+    let output_sum: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+    let fee = input_sum.checked_sub(output_sum).ok_or_else(|| {
+        Rejection::new(StatusCode::UNPROCESSABLE_ENTITY, "outputs_exceed_inputs", format!("Outputs spend {} sat but inputs have only {} sat", output_sum, input_sum))
+    })?;
+    check_fee_rate(fee, tx.vsize() as u64, settings)?;
+    Ok(fee)
+}
+
+/// Maps a reject reason of sendrawtransaction, reasons are more specific than codes so they are matched first
+pub fn node_rejection(code: i32, message: &str) -> Rejection {
+    let reason = format!("Node rejected the transaction: {}", message);
+    let (status, code) = if message.contains("missingorspent") || message.contains("missing-inputs") {
+        (StatusCode::CONFLICT, "inputs_spent")
+    } else if message.contains("txn-mempool-conflict") || message.contains("insufficient fee") {
+        (StatusCode::CONFLICT, "mempool_conflict")
+    } else if message.contains("min relay fee not met") || message.contains("mempool min fee not met") {
+        (StatusCode::UNPROCESSABLE_ENTITY, "fee_too_low")
+    } else if message.contains("max-fee-exceeded") || message.contains("Fee exceeds maximum") {
+        (StatusCode::UNPROCESSABLE_ENTITY, "fee_too_high")
+    } else if message.contains("dust") {
+        (StatusCode::UNPROCESSABLE_ENTITY, "dust")
+    } else if message.contains("non-final") || message.contains("non-BIP68-final") {
+        (StatusCode::UNPROCESSABLE_ENTITY, "non_final")
+    } else {
+        match code {
+            RPC_DESERIALIZATION_ERROR => (StatusCode::BAD_REQUEST, "invalid_tx"),
+            RPC_VERIFY_ALREADY_IN_CHAIN => (StatusCode::CONFLICT, "already_in_chain"),
+            RPC_VERIFY_ERROR | RPC_VERIFY_REJECTED => (StatusCode::UNPROCESSABLE_ENTITY, "rejected"),
+            _ => (StatusCode::BAD_GATEWAY, "node_error"),
+        }
+    };
+    Rejection::new(status, code, reason)
+}
+
+fn relay_rejection(e: ExplorerError) -> Rejection {
+    match e {
+        ExplorerError::Rpc(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::error::Error::Rpc(e))) => node_rejection(e.code, &e.message),
+        ExplorerError::Rpc(e) => Rejection::new(StatusCode::SERVICE_UNAVAILABLE, "node_unavailable", format!("Node is unavailable: {}", e)),
+        e => Rejection::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", e.to_string()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Relayed {
+    /// in display byte order, as the node returns it
+    pub tx_hash: String,
+    pub fee: u64,
+    pub fee_rate: f64,
+}
+
+/// Drops pending transactions the node no longer has, the view is kept as is while the node is unreachable.
+/// Node calls run outside of the query pool so that db queries never wait on the node.
+async fn sync_with_node(mempool: &Mempool, client: Arc<BtcClient>, settings: &BroadcastSettings) {
+    if !mempool.sync_due(Duration::from_secs(settings.mempool_sync_secs)) {
+        return;
+    }
+    let asked = Utc::now().timestamp();
+    if let Ok(Ok(node_txids)) = tokio::task::spawn_blocking(move || client.get_raw_mempool()).await {
+        mempool.retain_in_node(&node_txids, asked);
+    }
+}
+
+/// Prechecks a transaction against the index and relays it to the node, it is pending in the mempool view once accepted
+async fn relay(
+    state: RequestState,
+    mempool: Arc<Mempool>,
+    client: Arc<BtcClient>,
+    settings: BroadcastSettings,
+    pool: Arc<QueryPool>,
+    tx: bitcoin::Transaction,
+) -> Result<Relayed, Response> {
+    sync_with_node(&mempool, client.clone(), &settings).await;
+    let (checked_mempool, checked_tx) = (mempool.clone(), tx.clone());
+    let fee = match pool
+        .run(move || {
+            let read_tx = state.db.begin_read().map_err(AppError::from)?;
+            checked_mempool.prune(&read_tx)?;
+            precheck(&read_tx, &checked_mempool, &settings, &checked_tx)
+        })
+        .await
+    {
+        Ok(Ok(fee)) => fee,
+        Ok(Err(rejection)) => return Err(rejection.into_response()),
+        Err(e) => return Err(e.into_response()),
+    };
+    let txid = match tokio::task::spawn_blocking({
+        let tx = tx.clone();
+        move || client.send_raw_transaction(&tx).map_err(relay_rejection)
+    })
+    .await
+    {
+        Ok(Ok(txid)) => txid.to_byte_array(),
+        Ok(Err(rejection)) => return Err(rejection.into_response()),
+        Err(e) => return Err(Rejection::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", e.to_string()).into_response()),
+    };
+    let relayed = Relayed { tx_hash: bitcoin::Txid::from_byte_array(txid).to_string(), fee, fee_rate: fee as f64 / tx.vsize() as f64 };
+    mempool.insert(txid, tx, fee);
+    Ok(relayed)
+}
+
+pub fn routes(mempool: Arc<Mempool>, client: Arc<BtcClient>, settings: BroadcastSettings, pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/tx", post(broadcast))
+        .route("/mempool", get(pending))
+        .layer(Extension(mempool))
+        .layer(Extension(client))
+        .layer(Extension(settings))
+        .layer(Extension(pool))
+}
+
+async fn broadcast(
+    State(state): State<RequestState>,
+    Extension(mempool): Extension<Arc<Mempool>>,
+    Extension(client): Extension<Arc<BtcClient>>,
+    Extension(settings): Extension<BroadcastSettings>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Json(request): Json<DecodeRequest>,
+) -> Response {
+    let tx = match decode::parse(&request.tx) {
+        Ok((tx, None)) => tx,
+        Ok((_, Some(_))) => {
+            return Rejection::new(StatusCode::BAD_REQUEST, "invalid_tx", "PSBT has no final scripts, finalize and extract the transaction first".to_string()).into_response();
+        }
+        Err(e) => return Rejection::new(StatusCode::BAD_REQUEST, "invalid_tx", e).into_response(),
+    };
+    match relay(state, mempool, client, settings, pool, tx).await {
+        Ok(relayed) => Json(relayed).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn pending(
+    State(state): State<RequestState>,
+    Extension(mempool): Extension<Arc<Mempool>>,
+    Extension(client): Extension<Arc<BtcClient>>,
+    Extension(settings): Extension<BroadcastSettings>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+) -> Response {
+    sync_with_node(&mempool, client, &settings).await;
+    json_response(pool.run(move || mempool.txs(&state.db.begin_read()?)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TX_F4184;
+    use bitcoin::{Amount, ScriptBuf};
+
+    #[test]
+    fn test_dust_and_fee_rate() {
+        let (mut tx, _) = decode::parse(TX_F4184).unwrap();
+        assert!(check_outputs(&tx).is_ok());
+        tx.output.push(TxOut { value: Amount::ZERO, script_pubkey: ScriptBuf::new_op_return([0u8; 4]) });
+        assert!(check_outputs(&tx).is_ok());
+        tx.output[0].value = Amount::from_sat(100);
+        assert_eq!(check_outputs(&tx).unwrap_err().code, "dust");
+
+        let settings = BroadcastSettings::default();
+        assert_eq!(check_fee_rate(200, 200, &settings).unwrap(), 1.0);
+        assert_eq!(check_fee_rate(199, 200, &settings).unwrap_err().code, "fee_too_low");
+        assert_eq!(check_fee_rate(2_000_001, 200, &settings).unwrap_err().code, "fee_too_high");
+    }
+
+    #[test]
+    fn test_node_rejection() {
+        let rejection = node_rejection(RPC_VERIFY_ERROR, "bad-txns-inputs-missingorspent");
+        assert_eq!((rejection.status, rejection.code), (StatusCode::CONFLICT, "inputs_spent"));
+        assert_eq!(node_rejection(RPC_VERIFY_REJECTED, "min relay fee not met, 100 < 141").code, "fee_too_low");
+        assert_eq!(node_rejection(RPC_VERIFY_REJECTED, "txn-mempool-conflict").code, "mempool_conflict");
+        assert_eq!(node_rejection(RPC_VERIFY_REJECTED, "mandatory-script-verify-flag-failed").code, "rejected");
+        assert_eq!(node_rejection(RPC_VERIFY_ALREADY_IN_CHAIN, "Transaction already in block chain").status, StatusCode::CONFLICT);
+        assert_eq!(node_rejection(RPC_DESERIALIZATION_ERROR, "TX decode failed").status, StatusCode::BAD_REQUEST);
+        assert_eq!(node_rejection(-1, "unexpected").status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn test_pending_outputs() {
+        let (tx, _) = decode::parse(TX_F4184).unwrap();
+        let txid = tx.compute_txid();
+        let mempool = Mempool::default();
+        mempool.insert(txid.to_byte_array(), tx.clone(), 0);
+        assert_eq!(mempool.output(&OutPoint { txid, vout: 1 }), Some(tx.output[1].clone()));
+        assert_eq!(mempool.output(&OutPoint { txid, vout: 2 }), None);
+    }
+
+    #[test]
+    fn test_replacements_and_node_sync() {
+        let (tx, _) = decode::parse(TX_F4184).unwrap();
+        let txid = tx.compute_txid();
+        let mut child = tx.clone();
+        child.input.truncate(1);
+        child.input[0].previous_output = OutPoint { txid, vout: 0 };
+        let child_txid = child.compute_txid();
+        let mut bump = tx.clone();
+        bump.output[0].value -= Amount::from_sat(1000);
+        let bump_txid = bump.compute_txid();
+
+        let mempool = Mempool::default();
+        mempool.insert(txid.to_byte_array(), tx.clone(), 0);
+        mempool.insert(child_txid.to_byte_array(), child, 0);
+        // the node accepted the fee bump, so the replaced transaction and its child are gone
+        let before_bump = Utc::now().timestamp();
+        mempool.insert(bump_txid.to_byte_array(), bump.clone(), 1000);
+        assert_eq!(mempool.output(&OutPoint { txid, vout: 0 }), None);
+        assert_eq!(mempool.output(&OutPoint { txid: child_txid, vout: 0 }), None);
+        assert_eq!(mempool.output(&OutPoint { txid: bump_txid, vout: 0 }), Some(bump.output[0].clone()));
+
+        // a node asked before the fee bump was relayed does not know it yet
+        mempool.retain_in_node(&[txid], before_bump);
+        assert!(mempool.output(&OutPoint { txid: bump_txid, vout: 0 }).is_some());
+        // the node evicted the fee bump
+        mempool.retain_in_node(&[txid], Utc::now().timestamp() + 1);
+        assert_eq!(mempool.output(&OutPoint { txid: bump_txid, vout: 0 }), None);
+    }
+
+    #[test]
+    fn test_node_is_asked_only_when_due() {
+        let (tx, _) = decode::parse(TX_F4184).unwrap();
+        let txid = tx.compute_txid();
+        let mempool = Mempool::default();
+        let interval = Duration::from_secs(60);
+        assert!(!mempool.sync_due(interval));
+        mempool.insert(txid.to_byte_array(), tx, 0);
+        assert!(mempool.sync_due(interval));
+        assert!(!mempool.sync_due(interval));
+        assert!(mempool.sync_due(Duration::ZERO));
+
+        let view = mempool.view();
+        assert_eq!(view[0].tx_hash, "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16");
+    }
+}
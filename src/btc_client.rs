@@ -1,8 +1,10 @@
 use crate::config::BitcoinConfig;
 use crate::model::{BlockHash, Height, ExplorerError};
+use crate::telemetry;
 use bitcoin::hashes::Hash;
 use bitcoincore_rpc::{Auth, Client, RpcApi};
 use std::sync::Arc;
+use std::time::Instant;
 
 // Bitcoin block wrapper
 #[derive(Debug, Clone)]
@@ -30,26 +32,49 @@ impl BtcClient {
 }
 
 impl BtcClient {
+    fn timed<T>(method: &'static str, call: impl FnOnce() -> bitcoincore_rpc::Result<T>) -> bitcoincore_rpc::Result<T> {
+        let start = Instant::now();
+        let result = call();
+        telemetry::record_rpc(method, start.elapsed(), result.is_ok());
+        result
+    }
+
     pub fn get_best_block(&self) -> Result<BtcBlock, ExplorerError> {
-        let best_block_hash = self.rpc_client.get_best_block_hash()?;
-        let best_block = self.rpc_client.get_block(&best_block_hash)?;
+        let best_block_hash = Self::timed("getbestblockhash", || self.rpc_client.get_best_block_hash())?;
+        let best_block = Self::timed("getblock", || self.rpc_client.get_block(&best_block_hash))?;
         let height = self.get_block_height(&best_block)?;
         Ok(BtcBlock { height, underlying: best_block })
     }
 
     pub fn get_block_by_hash(&self, hash: BlockHash) -> Result<BtcBlock, ExplorerError> {
         let bitcoin_hash = bitcoin::BlockHash::from_raw_hash(Hash::from_byte_array(hash.0));
-        let block = self.rpc_client.get_block(&bitcoin_hash)?;
+        let block = Self::timed("getblock", || self.rpc_client.get_block(&bitcoin_hash))?;
         let height = self.get_block_height(&block)?;
         Ok(BtcBlock { height, underlying: block })
     }
 
     pub fn get_block_by_height(&self, height: Height) -> Result<BtcBlock, ExplorerError> {
-        let block_hash = self.rpc_client.get_block_hash(height.0 as u64)?;
-        let block = self.rpc_client.get_block(&block_hash)?;
+        let block_hash = Self::timed("getblockhash", || self.rpc_client.get_block_hash(height.0 as u64))?;
+        let block = Self::timed("getblock", || self.rpc_client.get_block(&block_hash))?;
         Ok(BtcBlock { height, underlying: block })
     }
 
+    /// Block without resolving its height
+    pub fn get_raw_block(&self, hash: &BlockHash) -> Result<bitcoin::Block, ExplorerError> {
+        let bitcoin_hash = bitcoin::BlockHash::from_raw_hash(Hash::from_byte_array(hash.0));
+        Ok(Self::timed("getblock", || self.rpc_client.get_block(&bitcoin_hash))?)
+    }
+
+    /// Relays a transaction to the node's mempool, node reject reasons come back as JSON-RPC errors
+    pub fn send_raw_transaction(&self, tx: &bitcoin::Transaction) -> Result<bitcoin::Txid, ExplorerError> {
+        Ok(Self::timed("sendrawtransaction", || self.rpc_client.send_raw_transaction(tx))?)
+    }
+
+    /// Txids of the transactions in the node's mempool
+    pub fn get_raw_mempool(&self) -> Result<Vec<bitcoin::Txid>, ExplorerError> {
+        Ok(Self::timed("getrawmempool", || self.rpc_client.get_raw_mempool())?)
+    }
+
     fn get_block_height(&self, block: &bitcoin::Block) -> Result<Height, ExplorerError> {
         // Try to get height using fast method (BIP34)
         if let Ok(height) = block.bip34_block_height() {
@@ -57,7 +82,7 @@ impl BtcClient {
         }
         // Fallback to fetching block header for height
         let block_hash = block.block_hash();
-        let verbose_block = self.rpc_client.get_block_info(&block_hash)?;
+        let verbose_block = Self::timed("getblockinfo", || self.rpc_client.get_block_info(&block_hash))?;
         Ok(Height(verbose_block.height as u32))
     }
 }
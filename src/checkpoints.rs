@@ -0,0 +1,142 @@
+use crate::config::CheckpointSettings;
+use crate::model::{BlockHeader, ExplorerError, Height};
+use bitcoin::hashes::Hash;
+use bitcoin::Network;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+// checkpoints of Bitcoin Core's chainparams, genesis blocks are added from rust-bitcoin
+const MAINNET: &[(u32, &str)] = &[
+    (11111, "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d"),
+    (33333, "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6"),
+    (74000, "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20"),
+    (105000, "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97"),
+    (134444, "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe"),
+    (168000, "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763"),
+    (193000, "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317"),
+    (210000, "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e"),
+    (216116, "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e"),
+    (225430, "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932"),
+    (250000, "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214"),
+    (279000, "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40"),
+    (295000, "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983"),
+];
+
+const TESTNET: &[(u32, &str)] = &[(546, "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70")];
+
+/// Height to block hash table the indexer must follow, with operator's checkpoints from `settings.toml`
+pub struct Checkpoints {
+    by_height: BTreeMap<u32, [u8; 32]>,
+    assume_valid: bool,
+}
+
+fn parse_hash(height: u32, hash: &str) -> Result<[u8; 32], ExplorerError> {
+    bitcoin::BlockHash::from_str(hash)
+        .map(|hash| hash.to_byte_array())
+        .map_err(|e| ExplorerError::InvalidCheckpoint(format!("{} at height {}: {}", hash, height, e)))
+}
+
+impl Checkpoints {
+    pub fn new(network: Network, settings: &CheckpointSettings) -> Result<Self, ExplorerError> {
+        let builtin = match network {
+            Network::Bitcoin => MAINNET,
+            Network::Testnet => TESTNET,
+            _ => &[],
+        };
+        let mut by_height = BTreeMap::new();
+        by_height.insert(0, bitcoin::constants::genesis_block(network).block_hash().to_byte_array());
+        let custom = settings.custom.iter().map(|checkpoint| (checkpoint.height, checkpoint.hash.as_str()));
+        for (height, hash) in builtin.iter().copied().chain(custom) {
+            by_height.insert(height, parse_hash(height, hash)?);
+        }
+        Ok(Checkpoints { by_height, assume_valid: settings.assume_valid })
+    }
+
+    pub fn last_height(&self) -> Option<Height> {
+        self.by_height.keys().next_back().map(|height| Height(*height))
+    }
+
+    /// Block at checkpoint height must have checkpoint's hash
+    pub fn check(&self, header: &BlockHeader) -> Result<(), ExplorerError> {
+        match self.by_height.get(&header.id.0) {
+            Some(expected) if *expected != header.hash.0 => Err(ExplorerError::CheckpointMismatch {
+                height: header.id.0,
+                hash: bitcoin::BlockHash::from_byte_array(header.hash.0).to_string(),
+                expected: bitcoin::BlockHash::from_byte_array(*expected).to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Blocks at or below the last checkpoint are final, they cannot be replaced by a fork
+    pub fn check_reorg(&self, first_replaced: &Height) -> Result<(), ExplorerError> {
+        match self.last_height() {
+            Some(checkpoint) if first_replaced.0 <= checkpoint.0 => {
+                Err(ExplorerError::ReorgBelowCheckpoint { height: first_replaced.0, checkpoint: checkpoint.0 })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Like Core's assume-valid, headers of blocks leading to the last checkpoint need not be validated,
+    /// their prev_hash links are still checked so the chain cannot deviate from checkpoints.
+    pub fn is_assumed_valid(&self, height: &Height) -> bool {
+        self.assume_valid && self.last_height().is_some_and(|checkpoint| height.0 <= checkpoint.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CustomCheckpoint;
+    use crate::model::{BlockHash, BlockTimestamp, BlockVersion, Bits, MerkleRoot, Nonce};
+
+    fn header(height: u32, hash: [u8; 32]) -> BlockHeader {
+        BlockHeader {
+            id: Height(height),
+            hash: BlockHash(hash),
+            prev_hash: BlockHash([0; 32]),
+            timestamp: BlockTimestamp(0),
+            merkle_root: MerkleRoot([0; 32]),
+            bits: Bits(0),
+            version: BlockVersion(1),
+            nonce: Nonce(0),
+        }
+    }
+
+    #[test]
+    fn test_builtin_checkpoints() {
+        for (network, table) in [(Network::Bitcoin, MAINNET), (Network::Testnet, TESTNET)] {
+            let checkpoints = Checkpoints::new(network, &CheckpointSettings::default()).unwrap();
+            assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            assert_eq!(checkpoints.last_height(), table.last().map(|(height, _)| Height(*height)));
+            let (height, hash) = table[0];
+            checkpoints.check(&header(height, parse_hash(height, hash).unwrap())).unwrap();
+            assert!(checkpoints.check(&header(height, [1; 32])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_genesis_of_configured_network() {
+        let checkpoints = Checkpoints::new(Network::Regtest, &CheckpointSettings::default()).unwrap();
+        let genesis = bitcoin::constants::genesis_block(Network::Regtest).block_hash().to_byte_array();
+        checkpoints.check(&header(0, genesis)).unwrap();
+        let mainnet_genesis = bitcoin::constants::genesis_block(Network::Bitcoin).block_hash().to_byte_array();
+        assert!(matches!(checkpoints.check(&header(0, mainnet_genesis)), Err(ExplorerError::CheckpointMismatch { .. })));
+    }
+
+    #[test]
+    fn test_custom_checkpoints_limit_reorgs() {
+        let custom = CustomCheckpoint { height: 1000, hash: "00".repeat(31) + "01" };
+        let settings = CheckpointSettings { assume_valid: true, custom: vec![custom] };
+        let checkpoints = Checkpoints::new(Network::Signet, &settings).unwrap();
+        assert_eq!(checkpoints.last_height(), Some(Height(1000)));
+        assert!(checkpoints.check_reorg(&Height(1000)).is_err());
+        checkpoints.check_reorg(&Height(1001)).unwrap();
+        assert!(checkpoints.is_assumed_valid(&Height(999)));
+        assert!(!checkpoints.is_assumed_valid(&Height(1001)));
+
+        let invalid = CheckpointSettings { assume_valid: false, custom: vec![CustomCheckpoint { height: 5, hash: "xyz".to_string() }] };
+        assert!(matches!(Checkpoints::new(Network::Signet, &invalid), Err(ExplorerError::InvalidCheckpoint(_))));
+    }
+}
@@ -1,7 +1,14 @@
 use bech32::{hrp, segwit};
+use bitcoin::hashes::Hash;
+use bitcoin::hex::FromHex;
+use bitcoin::opcodes::all::{OP_CHECKSIG, OP_PUSHNUM_1};
+use bitcoin::script::Builder;
+use bitcoin::secp256k1;
+use bitcoin::{PubkeyHash, Script, ScriptBuf, ScriptHash, WPubkeyHash, WScriptHash};
 use redbit::ByteVecColumnSerde;
 use serde::{Deserialize, Deserializer, Serializer};
 use serde_with::{DeserializeAs, SerializeAs};
+use std::str::FromStr;
 
 #[allow(dead_code)]
 pub struct Base58;
@@ -142,19 +149,205 @@ impl<'de> DeserializeAs<'de, Vec<u8>> for BaseOrBech {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
+        decode_address(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+// human readable parts of mainnet, testnet/signet and regtest segwit addresses
+const SEGWIT_HRPS: [&str; 3] = ["bc1", "tb1", "bcrt1"];
+
+/// Messages of bech32 errors only name the failing step, the cause is in their sources
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
 
-        if let Ok((_hrp, _version, program)) = segwit::decode(&s) {
-            return Ok(program);
+/// Decodes an address the way `BaseOrBech` does, into the witness program of a Bech32(m) address or the version byte
+/// and hash of a Base58Check one. Errors tell which encoding was expected, so that a mistyped character shows up
+/// as a checksum failure rather than an unknown format.
+pub fn decode_address(s: &str) -> Result<Vec<u8>, String> {
+    match segwit::decode(s) {
+        Ok((_hrp, _version, program)) => return Ok(program),
+        Err(e) if SEGWIT_HRPS.iter().any(|hrp| s.to_lowercase().starts_with(hrp)) => {
+            return Err(format!("Bech32 decode error: {} (input: {}) - ensure this is a valid Bech32 or Bech32m address", error_chain(&e), s));
         }
+        Err(_) => {}
+    }
+    match bs58::decode(s).with_check(None).into_vec() {
+        Ok(payload) => Ok(payload),
+        Err(bs58::decode::Error::InvalidChecksum { .. }) => {
+            Err(format!("Base58 checksum mismatch (input: {}) - the address is mistyped or truncated", s))
+        }
+        Err(e) => Err(format!(
+            "Invalid Bitcoin address format: {} - could not decode as Bech32 or Base58Check ({}). Expected formats: P2WPKH/P2WSH/P2TR Bech32 or legacy P2PKH/P2SH Base58Check.",
+            s, e
+        )),
+    }
+}
+
+/// Address as it is indexed, Bech32 addresses may be given in upper case
+pub fn normalize_address(s: &str) -> Result<String, String> {
+    decode_address(s)?;
+    Ok(if segwit::decode(s).is_ok() { s.to_lowercase() } else { s.to_string() })
+}
+
+/// Address parsed the way `decode_address` checks it, for lookups that need its output script or key hash
+pub fn parse_address(s: &str) -> Result<bitcoin::Address, String> {
+    let address = normalize_address(s.trim())?;
+    bitcoin::Address::from_str(&address).map(|address| address.assume_checked()).map_err(|e| format!("Invalid Bitcoin address {}: {}", address, e))
+}
+
+/// Hex of a 32 byte block hash or txid, in either byte order, see `byte_orders`
+pub fn parse_hash(s: &str) -> Result<[u8; 32], String> {
+    <[u8; 32]>::from_hex(s.trim()).map_err(|_| format!("{} is not a hex encoded 32 byte block hash or txid", s.trim()))
+}
+
+/// Byte orders a parsed hash is looked up in, the stored order first and then the reversed one that bitcoind,
+/// Electrum and most explorers display
+pub fn byte_orders(hash: [u8; 32]) -> [[u8; 32]; 2] {
+    let mut reversed = hash;
+    reversed.reverse();
+    [hash, reversed]
+}
+
+/// Kind of an explorer search query, told apart by its shape
+#[derive(Debug, PartialEq)]
+pub enum SearchQuery {
+    Height(u32),
+    /// hex of 32 bytes, either a block hash or a txid
+    Hash([u8; 32]),
+    Address(String),
+    Pubkey(Vec<u8>),
+    Script(ScriptBuf),
+}
 
-        if let Ok(vec) = bs58::decode(&s).with_check(None).into_vec() {
-            return Ok(vec);
+pub fn parse_search(q: &str) -> Result<SearchQuery, String> {
+    let q = q.trim();
+    if q.is_empty() {
+        return Err("Empty query".to_string());
+    }
+    if q.len() != 64 && q.bytes().all(|b| b.is_ascii_digit()) {
+        return q.parse().map(SearchQuery::Height).map_err(|_| format!("Height {} is out of range", q));
+    }
+    if let Ok(hash) = parse_hash(q) {
+        return Ok(SearchQuery::Hash(hash));
+    }
+    let address_error = match normalize_address(q) {
+        Ok(address) => return Ok(SearchQuery::Address(address)),
+        Err(e) => e,
+    };
+    match Vec::<u8>::from_hex(q) {
+        Ok(bytes) if secp256k1::PublicKey::from_slice(&bytes).is_ok() => Ok(SearchQuery::Pubkey(bytes)),
+        Ok(bytes) => Ok(SearchQuery::Script(ScriptBuf::from_bytes(bytes))),
+        Err(_) => Err(address_error),
+    }
+}
+
+/// Output scripts are stored compressed like Bitcoin Core's `ScriptCompression` does, standard templates as a type tag
+/// followed by their hash, witness program or x coordinate of their public key, other scripts as a tag and raw bytes.
+/// Api renders and accepts them as hex of the whole script.
+#[allow(dead_code)]
+pub struct ScriptCompression;
+
+const P2PKH: u8 = 0x00;
+const P2SH: u8 = 0x01;
+// 0x02 and 0x03 are P2PK of a compressed key, 0x04 and 0x05 of an uncompressed one with 0x04 | parity of y
+const P2PK_UNCOMPRESSED: u8 = 0x04;
+const P2WPKH: u8 = 0x06;
+const P2WSH: u8 = 0x07;
+const P2TR: u8 = 0x08;
+const RAW: u8 = 0x09;
+
+pub fn compress_script(script: &Script) -> Vec<u8> {
+    let bytes = script.as_bytes();
+    let tagged = |tag: u8, data: &[u8]| [&[tag], data].concat();
+    if script.is_p2pkh() {
+        tagged(P2PKH, &bytes[3..23])
+    } else if script.is_p2sh() {
+        tagged(P2SH, &bytes[2..22])
+    } else if script.is_p2wpkh() {
+        tagged(P2WPKH, &bytes[2..])
+    } else if script.is_p2wsh() {
+        tagged(P2WSH, &bytes[2..])
+    } else if script.is_p2tr() {
+        tagged(P2TR, &bytes[2..])
+    } else if bytes.len() == 35 && bytes[0] == 33 && bytes[34] == OP_CHECKSIG.to_u8() && matches!(bytes[1], 0x02 | 0x03) {
+        bytes[1..34].to_vec()
+    } else if bytes.len() == 67 && bytes[0] == 65 && bytes[66] == OP_CHECKSIG.to_u8() && bytes[1] == 0x04 {
+        // only keys on the curve can be recovered from their x coordinate
+        match secp256k1::PublicKey::from_slice(&bytes[1..66]) {
+            Ok(key) => tagged(P2PK_UNCOMPRESSED | (bytes[65] & 1), &key.serialize()[1..]),
+            Err(_) => tagged(RAW, bytes),
         }
+    } else {
+        tagged(RAW, bytes)
+    }
+}
+
+pub fn decompress_script(compressed: &[u8]) -> ScriptBuf {
+    let Some((&tag, data)) = compressed.split_first() else {
+        return ScriptBuf::new();
+    };
+    let hash20 = || <[u8; 20]>::try_from(data).ok();
+    let hash32 = || <[u8; 32]>::try_from(data).ok();
+    let script = match tag {
+        P2PKH => hash20().map(|hash| ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(hash))),
+        P2SH => hash20().map(|hash| ScriptBuf::new_p2sh(&ScriptHash::from_byte_array(hash))),
+        0x02 | 0x03 => hash32().map(|_| p2pk(compressed)),
+        0x04 | 0x05 => hash32()
+            .and_then(|_| secp256k1::PublicKey::from_slice(&[&[tag - 2], data].concat()).ok())
+            .map(|key| p2pk(&key.serialize_uncompressed())),
+        P2WPKH => hash20().map(|hash| ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(hash))),
+        P2WSH => hash32().map(|hash| ScriptBuf::new_p2wsh(&WScriptHash::from_byte_array(hash))),
+        P2TR => hash32().map(|key| Builder::new().push_opcode(OP_PUSHNUM_1).push_slice(key).into_script()),
+        _ => None,
+    };
+    script.unwrap_or_else(|| ScriptBuf::from_bytes(data.to_vec()))
+}
+
+fn p2pk(key: &[u8]) -> ScriptBuf {
+    let mut script = Vec::with_capacity(key.len() + 2);
+    script.push(key.len() as u8);
+    script.extend_from_slice(key);
+    script.push(OP_CHECKSIG.to_u8());
+    ScriptBuf::from_bytes(script)
+}
+
+impl ByteVecColumnSerde for ScriptCompression {
+    fn decoded_example() -> Vec<u8> {
+        compress_script(&ScriptBuf::from_hex(&Self::encoded_example()).unwrap())
+    }
+
+    fn encoded_example() -> String {
+        "76a914dfcec48bb8491856c353306ab5febeb7e99e4d7888ac".to_string()
+    }
+}
 
-        Err(serde::de::Error::custom(format!(
-            "Invalid Bitcoin address format: {} - could not decode as Bech32 or Base58Check. Expected formats: P2WPKH/P2TR Bech32 or legacy P2PKH/P2SH Base58Check (25 bytes).",
-            s
-        )))
+impl SerializeAs<Vec<u8>> for ScriptCompression {
+    #[inline]
+    fn serialize_as<S>(source: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&decompress_script(source).to_hex_string())
+    }
+}
+
+impl<'de> DeserializeAs<'de, Vec<u8>> for ScriptCompression {
+    fn deserialize_as<D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        ScriptBuf::from_hex(&s)
+            .map(|script| compress_script(&script))
+            .map_err(|e| serde::de::Error::custom(format!("Script hex decode error: {} (input: {}) - expected hex of the whole output script", e, s)))
     }
 }
 
@@ -167,6 +360,7 @@ mod tests {
     use serde::{Deserialize, Serialize};
     use serde_with::serde_as;
     use crate::model::serde_json;
+    use crate::test_support::{genesis_key, GENESIS_OUTPUT};
 
     #[serde_as]
     #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -257,4 +451,101 @@ mod tests {
         assert_eq!(original.0, btc.0);
     }
 
+    fn sample_scripts() -> Vec<ScriptBuf> {
+        [
+            "76a914dfcec48bb8491856c353306ab5febeb7e99e4d7888ac",
+            "a914748284390f9e263a4b766a75d0633c50426eb87587",
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            "2102a1633cafcc01ebfb6d78e39f687a1f0995c62fc95f51ead10a02ee0be551b5dcac",
+            GENESIS_OUTPUT,
+            "6a0b68656c6c6f20776f726c64",
+            "5121022afc20bf379bc96a2f4e9e63ffceb8652b2b6a097f63fbee6ecec2a49a48010e2103a767c7221e9f15f870f1ad9311f5ab937d79fcaeee15bb2c722bca515581b4c052ae",
+            "",
+        ]
+        .iter()
+        .map(|hex| ScriptBuf::from_hex(hex).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_script_compression_roundtrip() {
+        for script in sample_scripts() {
+            assert_eq!(decompress_script(&compress_script(&script)), script, "{}", script.to_hex_string());
+        }
+        // keys off the curve cannot be recovered from their x coordinate and are stored raw
+        let mut invalid = ScriptBuf::from_hex(GENESIS_OUTPUT).unwrap().into_bytes();
+        invalid[2] ^= 1;
+        let invalid = ScriptBuf::from_bytes(invalid);
+        assert_eq!(compress_script(&invalid)[0], RAW);
+        assert_eq!(decompress_script(&compress_script(&invalid)), invalid);
+    }
+
+    #[serde_as]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct ScriptWrap(
+        #[serde_as(as = "ScriptCompression")] Vec<u8>
+    );
+
+    #[test]
+    fn test_script_compression_json() {
+        let json = format!("\"{}\"", GENESIS_OUTPUT);
+        let script: ScriptWrap = serde_json::from_str(&json).unwrap();
+        assert_eq!(script.0.len(), 33);
+        assert_eq!(serde_json::to_string(&script).unwrap(), json);
+        assert_eq!(roundtrip_json(&script), script);
+        assert!(serde_json::from_str::<ScriptWrap>("\"zz\"").is_err());
+    }
+
+    #[test]
+    fn test_script_compression_size() {
+        let sizes: Vec<(usize, usize)> = sample_scripts().iter().map(|script| (script.len(), compress_script(script).len())).collect();
+        assert_eq!(sizes, [(25, 21), (23, 21), (22, 21), (34, 33), (34, 33), (35, 33), (67, 33), (13, 14), (71, 72), (0, 1)]);
+    }
+
+    #[test]
+    fn test_decode_address_errors() {
+        assert_eq!(decode_address("1MNr16FTvjhTAw9GBNxhfirmPt9KzSvgMw").unwrap().len(), 21);
+        assert_eq!(decode_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap().len(), 20);
+        assert_eq!(normalize_address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+
+        let base58 = decode_address("1MNr16FTvjhTAw9GBNxhfirmPt9KzSvgMx").unwrap_err();
+        assert!(base58.starts_with("Base58 checksum mismatch"), "{}", base58);
+        let bech32 = decode_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5").unwrap_err();
+        assert!(bech32.starts_with("Bech32 decode error") && bech32.contains("checksum"), "{}", bech32);
+        assert!(decode_address("hello").unwrap_err().starts_with("Invalid Bitcoin address format"));
+
+        let json = serde_json::from_str::<BtcWrap>("\"1MNr16FTvjhTAw9GBNxhfirmPt9KzSvgMx\"").unwrap_err().to_string();
+        assert!(json.contains("checksum"), "{}", json);
+    }
+
+    #[test]
+    fn test_parse_search() {
+        assert_eq!(parse_search(" 170 ").unwrap(), SearchQuery::Height(170));
+        assert!(parse_search("99999999999").unwrap_err().contains("out of range"));
+        let txid = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+        assert_eq!(parse_search(txid).unwrap(), SearchQuery::Hash(<[u8; 32]>::from_hex(txid).unwrap()));
+        assert_eq!(parse_search("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap(), SearchQuery::Address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string()));
+        assert_eq!(parse_search(&GENESIS_OUTPUT[2..132]).unwrap(), SearchQuery::Pubkey(genesis_key()));
+        let p2pkh = "76a914dfcec48bb8491856c353306ab5febeb7e99e4d7888ac";
+        assert_eq!(parse_search(p2pkh).unwrap(), SearchQuery::Script(ScriptBuf::from_hex(p2pkh).unwrap()));
+        assert!(parse_search("1MNr16FTvjhTAw9GBNxhfirmPt9KzSvgMx").unwrap_err().contains("checksum"));
+        assert!(parse_search("").is_err());
+    }
+
+    #[test]
+    fn test_parse_hash_and_address() {
+        let txid = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+        let hash = parse_hash(txid).unwrap();
+        let [stored, reversed] = byte_orders(hash);
+        assert_eq!(stored, hash);
+        assert_eq!(reversed.to_vec(), hash.iter().rev().copied().collect::<Vec<u8>>());
+        assert!(parse_hash(&txid[2..]).unwrap_err().contains("not a hex encoded 32 byte"));
+
+        let address = parse_address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap();
+        assert_eq!(address.to_string(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert!(address.witness_program().is_some());
+        assert!(parse_address("1MNr16FTvjhTAw9GBNxhfirmPt9KzSvgMx").unwrap_err().contains("checksum"));
+    }
 }
@@ -7,6 +7,12 @@ pub struct BitcoinConfig {
     pub api_host: String,
     pub api_username: String,
     pub api_password: String,
+    #[serde(default = "default_network")]
+    pub network: bitcoin::Network,
+}
+
+fn default_network() -> bitcoin::Network {
+    bitcoin::Network::Bitcoin
 }
 
 impl BitcoinConfig {
@@ -24,3 +30,111 @@ impl BitcoinConfig {
         }
     }
 }
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatusSettings {
+    pub ready_max_lag: u32, // how many blocks behind node tip we still report as ready
+}
+
+impl Default for StatusSettings {
+    fn default() -> Self {
+        StatusSettings { ready_max_lag: 3 }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ApiSettings {
+    pub query_threads: usize,
+    pub query_queue: usize, // queries waiting for a thread, more get 503
+    pub request_timeout_ms: u64,
+    pub throttle_latency_ms: u64, // 0 disables write throttling
+    pub throttle_pause_ms: u64,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        ApiSettings { query_threads: 4, query_queue: 256, request_timeout_ms: 10_000, throttle_latency_ms: 500, throttle_pause_ms: 50 }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomCheckpoint {
+    pub height: u32,
+    pub hash: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct CheckpointSettings {
+    pub assume_valid: bool, // skip header validation of blocks up to the last checkpoint
+    pub custom: Vec<CustomCheckpoint>,
+}
+
+impl Default for CheckpointSettings {
+    fn default() -> Self {
+        CheckpointSettings { assume_valid: true, custom: Vec::new() }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct FinalitySettings {
+    pub depth: u32, // confirmations after which a block is considered final
+}
+
+impl Default for FinalitySettings {
+    fn default() -> Self {
+        FinalitySettings { depth: 6 }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RawBlockSettings {
+    pub cache: bool, // keep compressed raw blocks next to the database during ingest
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct BroadcastSettings {
+    pub min_fee_rate: f64, // sat/vB below which POST /tx refuses to relay
+    pub max_fee_rate: f64, // sat/vB above which the fee is considered a mistake
+    pub mempool_sync_secs: u64, // pending transactions are compared with the node's mempool at most this often
+}
+
+impl Default for BroadcastSettings {
+    fn default() -> Self {
+        BroadcastSettings { min_fee_rate: 1.0, max_fee_rate: 10_000.0, mempool_sync_secs: 30 }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SchemaSettings {
+    pub rebuild_outdated: bool, // drop tables of a database of an older schema version and reindex it from the node in place
+}
+
+/// Explorer specific sections of `config/settings.toml` that chain-syncer's `AppConfig` does not know about
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ExplorerSettings {
+    pub status: StatusSettings,
+    pub api: ApiSettings,
+    pub checkpoints: CheckpointSettings,
+    pub finality: FinalitySettings,
+    pub raw_blocks: RawBlockSettings,
+    pub broadcast: BroadcastSettings,
+    pub schema: SchemaSettings,
+}
+
+impl ExplorerSettings {
+    pub fn new(path: &str) -> Result<Self, ConfigError> {
+        Config::builder()
+            .add_source(File::with_name(path).required(true))
+            .add_source(Environment::with_prefix("EXPLORER").try_parsing(true).separator("__"))
+            .build()?
+            .try_deserialize()
+    }
+}
@@ -0,0 +1,298 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::model::{InputRef, ScriptType, Transaction, TransactionPointer, TxHash, Utxo};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::post;
+use axum::{Extension, Json};
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::{OutPoint, Psbt, TxOut};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+#[derive(Debug, Deserialize)]
+pub struct DecodeRequest {
+    /// hex of a raw transaction, or a PSBT as base64 or hex
+    pub tx: String,
+}
+
+/// Transaction of a raw transaction or PSBT, the PSBT is returned too as it may carry prevouts
+pub fn parse(input: &str) -> Result<(bitcoin::Transaction, Option<Psbt>), String> {
+    let input = input.trim();
+    match Vec::<u8>::from_hex(input) {
+        Ok(bytes) if bytes.starts_with(PSBT_MAGIC) => {
+            let psbt = Psbt::deserialize(&bytes).map_err(|e| format!("Invalid PSBT: {}", e))?;
+            Ok((psbt.unsigned_tx.clone(), Some(psbt)))
+        }
+        Ok(bytes) => deserialize(&bytes).map(|tx| (tx, None)).map_err(|e| format!("Invalid raw transaction: {}", e)),
+        Err(_) => {
+            let psbt = Psbt::from_str(input).map_err(|e| format!("Neither hex of a transaction nor a base64 PSBT: {}", e))?;
+            Ok((psbt.unsigned_tx.clone(), Some(psbt)))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodedInput {
+    pub tx_hash: String,
+    pub vout: u32,
+    pub amount: Option<u64>,
+    pub address: Option<String>,
+    pub script_type: Option<&'static str>,
+    /// whether the prevout was found in the index rather than in the PSBT
+    pub indexed: bool,
+    pub spent: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodedOutput {
+    pub amount: u64,
+    pub address: Option<String>,
+    pub script_type: &'static str,
+    pub script: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodedTx {
+    pub tx_hash: String,
+    pub version: i32,
+    pub lock_time: u32,
+    pub weight: u64,
+    pub vsize: u64,
+    pub inputs: Vec<DecodedInput>,
+    pub outputs: Vec<DecodedOutput>,
+    /// known only when every prevout is
+    pub fee: Option<u64>,
+    /// sat/vB, unsigned transactions are smaller than they will be, so theirs is an upper bound
+    pub fee_rate: Option<f64>,
+}
+
+fn address_of(script: &bitcoin::Script) -> Option<String> {
+    bitcoin::Address::from_script(script, bitcoin::Network::Bitcoin).ok().map(|address| address.to_string())
+}
+
+/// Output that the input spends, taken from the PSBT when it is not indexed
+fn psbt_prevout(psbt: &Psbt, index: usize, vout: u32) -> Option<TxOut> {
+    let input = psbt.inputs.get(index)?;
+    input.witness_utxo.clone().or_else(|| input.non_witness_utxo.as_ref().and_then(|tx| tx.output.get(vout as usize).cloned()))
+}
+
+/// Indexed output that an outpoint refers to
+pub fn find_prevout(read_tx: &ReadTransaction, outpoint: &OutPoint) -> Result<Option<(TransactionPointer, Utxo)>, AppError> {
+    let Some(tx_pointer) = Transaction::get_ids_by_hash(read_tx, &TxHash(*outpoint.txid.as_ref()))?.into_iter().next() else {
+        return Ok(None);
+    };
+    let pointer = TransactionPointer::from_parent(tx_pointer, outpoint.vout as u16);
+    Ok(Utxo::get(read_tx, &pointer)?.map(|utxo| (pointer, utxo)))
+}
+
+/// Whether an indexed output is spent, inputs are stored under the output they spend
+pub fn is_spent(read_tx: &ReadTransaction, pointer: &TransactionPointer) -> Result<bool, AppError> {
+    Ok(InputRef::get(read_tx, pointer)?.is_some())
+}
+
+/// Indexed outputs that inputs of a decoded transaction spend
+pub trait PrevoutSource {
+    fn find_prevout(&self, outpoint: &OutPoint) -> Result<Option<(TransactionPointer, Utxo)>, AppError>;
+    fn is_spent(&self, pointer: &TransactionPointer) -> Result<bool, AppError>;
+}
+
+impl PrevoutSource for ReadTransaction {
+    fn find_prevout(&self, outpoint: &OutPoint) -> Result<Option<(TransactionPointer, Utxo)>, AppError> {
+        find_prevout(self, outpoint)
+    }
+
+    fn is_spent(&self, pointer: &TransactionPointer) -> Result<bool, AppError> {
+        is_spent(self, pointer)
+    }
+}
+
+fn decode_input(source: &impl PrevoutSource, tx_in: &bitcoin::TxIn, psbt_prevout: Option<TxOut>) -> Result<DecodedInput, AppError> {
+    let prevout = source.find_prevout(&tx_in.previous_output)?;
+    let mut input = DecodedInput {
+        tx_hash: tx_in.previous_output.txid.to_byte_array().to_lower_hex_string(),
+        vout: tx_in.previous_output.vout,
+        amount: None,
+        address: None,
+        script_type: None,
+        indexed: prevout.is_some(),
+        spent: match &prevout {
+            Some((pointer, _)) => source.is_spent(pointer)?,
+            None => false,
+        },
+    };
+    match (prevout.map(|(_, utxo)| utxo), psbt_prevout) {
+        (Some(utxo), _) => {
+            input.amount = Some(utxo.amount);
+            input.address = utxo.address.map(|address| String::from_utf8_lossy(&address.address.0).into_owned());
+            input.script_type = Some(utxo.script_type.name());
+        }
+        (None, Some(prevout)) => {
+            input.amount = Some(prevout.value.to_sat());
+            input.address = address_of(&prevout.script_pubkey);
+            input.script_type = Some(ScriptType::of(&prevout.script_pubkey).name());
+        }
+        (None, None) => {}
+    }
+    Ok(input)
+}
+
+/// Transaction with its inputs enriched by the outputs they spend
+pub fn decode_tx(source: &impl PrevoutSource, tx: &bitcoin::Transaction, psbt: Option<&Psbt>) -> Result<DecodedTx, AppError> {
+    let mut inputs = Vec::with_capacity(tx.input.len());
+    for (index, tx_in) in tx.input.iter().enumerate() {
+        let prevout = psbt.and_then(|psbt| psbt_prevout(psbt, index, tx_in.previous_output.vout));
+        inputs.push(decode_input(source, tx_in, prevout)?);
+    }
+    let outputs: Vec<DecodedOutput> = tx
+        .output
+        .iter()
+        .map(|output| DecodedOutput {
+            amount: output.value.to_sat(),
+            address: address_of(&output.script_pubkey),
+            script_type: ScriptType::of(&output.script_pubkey).name(),
+            script: output.script_pubkey.as_bytes().to_lower_hex_string(),
+        })
+        .collect();
+    let input_sum: Option<u64> = inputs.iter().map(|input| input.amount).sum();
+    let output_sum: u64 = outputs.iter().map(|output| output.amount).sum();
+    let fee = input_sum.and_then(|input_sum| input_sum.checked_sub(output_sum));
+    let vsize = tx.vsize() as u64;
+    Ok(DecodedTx {
+        tx_hash: tx.compute_txid().to_byte_array().to_lower_hex_string(),
+        version: tx.version.0,
+        lock_time: tx.lock_time.to_consensus_u32(),
+        weight: tx.weight().to_wu(),
+        vsize,
+        inputs,
+        outputs,
+        fee,
+        fee_rate: fee.map(|fee| fee as f64 / vsize as f64),
+    })
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/tx/decode", post(decode)).layer(Extension(pool))
+}
+
+async fn decode(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Json(request): Json<DecodeRequest>) -> Response {
+    let (tx, psbt) = match parse(&request.tx) {
+        Ok(parsed) => parsed,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    json_response(pool.run(move || decode_tx(&state.db.begin_read()?, &tx, psbt.as_ref())).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Address, BlockPointer, Height, ScriptHash, UtxoAddress};
+    use crate::test_support::TX_F4184;
+    use bitcoin::consensus::serialize;
+
+    /// Outputs of the index by outpoint, with whether they are spent
+    #[derive(Default)]
+    struct MemoryIndex {
+        outputs: Vec<(OutPoint, TransactionPointer, Utxo, bool)>,
+    }
+
+    impl PrevoutSource for MemoryIndex {
+        fn find_prevout(&self, outpoint: &OutPoint) -> Result<Option<(TransactionPointer, Utxo)>, AppError> {
+            Ok(self.outputs.iter().find(|(indexed, ..)| indexed == outpoint).map(|(_, pointer, utxo, _)| (pointer.clone(), utxo.clone())))
+        }
+
+        fn is_spent(&self, pointer: &TransactionPointer) -> Result<bool, AppError> {
+            Ok(self.outputs.iter().any(|(_, indexed, _, spent)| indexed == pointer && *spent))
+        }
+    }
+
+    #[test]
+    fn test_parse_raw_tx_and_psbt() {
+        let (tx, psbt) = parse(TX_F4184).unwrap();
+        assert!(psbt.is_none());
+        assert_eq!(tx.compute_txid().to_string(), "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16");
+
+        let mut unsigned = tx.clone();
+        unsigned.input[0].script_sig = bitcoin::ScriptBuf::new();
+        let psbt = Psbt::from_unsigned_tx(unsigned.clone()).unwrap();
+        let (from_base64, _) = parse(&psbt.to_string()).unwrap();
+        let (from_hex, _) = parse(&psbt.serialize().to_lower_hex_string()).unwrap();
+        assert_eq!(from_base64, unsigned);
+        assert_eq!(from_hex, unsigned);
+
+        assert!(parse("00").is_err());
+        assert!(parse("not a transaction").is_err());
+        assert_eq!(serialize(&tx).to_lower_hex_string(), TX_F4184);
+    }
+
+    #[test]
+    fn test_psbt_prevout() {
+        let (tx, _) = parse(TX_F4184).unwrap();
+        let mut unsigned = tx.clone();
+        unsigned.input[0].script_sig = bitcoin::ScriptBuf::new();
+        let mut psbt = Psbt::from_unsigned_tx(unsigned).unwrap();
+        assert_eq!(psbt_prevout(&psbt, 0, 0), None);
+        let prevout = TxOut { value: bitcoin::Amount::from_sat(5_000_000_000), script_pubkey: tx.output[1].script_pubkey.clone() };
+        psbt.inputs[0].witness_utxo = Some(prevout.clone());
+        assert_eq!(psbt_prevout(&psbt, 0, 0), Some(prevout));
+    }
+
+    #[test]
+    fn test_decode_tx_enrichment() {
+        let (tx, _) = parse(TX_F4184).unwrap();
+        let mut unsigned = tx.clone();
+        unsigned.input[0].script_sig = bitcoin::ScriptBuf::new();
+        // the second input spends an output the index does not have
+        let mut unindexed = unsigned.input[0].clone();
+        unindexed.previous_output = OutPoint { txid: tx.compute_txid(), vout: 1 };
+        unsigned.input.push(unindexed);
+
+        // coinbase output of block 9 that the first input spends, it is spent by block 170 already
+        let coinbase = tx.output[1].script_pubkey.clone();
+        let pointer = TransactionPointer::from_parent(BlockPointer::from_parent(Height(9), 0), 0);
+        let utxo = Utxo {
+            id: pointer.clone(),
+            amount: 5_000_000_000,
+            script_hash: ScriptHash::from_script(&coinbase),
+            address: Some(UtxoAddress { id: pointer.clone(), address: Address(b"12cbQLTFMXRnSzktFkuoG3eHoMeFtpTu3S".to_vec()) }),
+            script_type: ScriptType::of(&coinbase),
+            pubkeys: Vec::new(),
+        };
+        let index = MemoryIndex { outputs: vec![(unsigned.input[0].previous_output, pointer, utxo, true)] };
+
+        let decoded = decode_tx(&index, &unsigned, None).unwrap();
+        let indexed = &decoded.inputs[0];
+        assert_eq!((indexed.indexed, indexed.spent, indexed.amount), (true, true, Some(5_000_000_000)));
+        assert_eq!((indexed.address.as_deref(), indexed.script_type), (Some("12cbQLTFMXRnSzktFkuoG3eHoMeFtpTu3S"), Some("p2pk")));
+        let unknown = &decoded.inputs[1];
+        assert_eq!((unknown.indexed, unknown.spent, unknown.amount, unknown.address.as_ref()), (false, false, None, None));
+        // the fee is unknown while any prevout is
+        assert_eq!((decoded.fee, decoded.fee_rate), (None, None));
+
+        // the PSBT supplies the missing prevout
+        let mut psbt = Psbt::from_unsigned_tx(unsigned.clone()).unwrap();
+        let witness_utxo = TxOut {
+            value: bitcoin::Amount::from_sat(1000),
+            script_pubkey: bitcoin::ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([1; 20])),
+        };
+        psbt.inputs[1].witness_utxo = Some(witness_utxo.clone());
+        // an indexed prevout is preferred over the PSBT's
+        psbt.inputs[0].witness_utxo = Some(witness_utxo.clone());
+        let decoded = decode_tx(&index, &unsigned, Some(&psbt)).unwrap();
+        assert_eq!(decoded.inputs[0].amount, Some(5_000_000_000));
+        let from_psbt = &decoded.inputs[1];
+        assert_eq!((from_psbt.indexed, from_psbt.spent, from_psbt.amount), (false, false, Some(1000)));
+        assert_eq!(from_psbt.address, address_of(&witness_utxo.script_pubkey));
+        assert_eq!(from_psbt.script_type, Some("p2wpkh"));
+        // outputs of block 170 spend all of the coinbase, so the fee is what the second input adds
+        assert_eq!(decoded.fee, Some(1000));
+        assert_eq!(decoded.fee_rate, Some(1000.0 / unsigned.vsize() as f64));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec;
+use crate::model::{Block, BlockFilter, BlockHeader, FilterHeaderHash, GcsFilter, Height};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::bip158::GcsFilterWriter;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::hex::DisplayHex;
+use bitcoin::{ScriptBuf, TxOut};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// golomb-rice parameters of the basic filter
+const P: u8 = 19;
+const M: u64 = 784931;
+/// As many filter headers as `getcfheaders` returns at once
+pub const MAX_HEADERS: u32 = 2000;
+
+/// BIP158 basic filter of output scripts and scripts spent by the block, except empty and OP_RETURN outputs like Bitcoin Core does
+pub fn basic_filter(block_hash: &[u8; 32], outputs: &[ScriptBuf], spent: &[ScriptBuf]) -> Vec<u8> {
+    let k0 = u64::from_le_bytes(block_hash[0..8].try_into().expect("8 byte slice"));
+    let k1 = u64::from_le_bytes(block_hash[8..16].try_into().expect("8 byte slice"));
+    let mut content = Vec::new();
+    {
+        let mut writer = GcsFilterWriter::new(&mut content, k0, k1, M, P);
+        for script in outputs.iter().filter(|script| !script.is_empty() && !script.is_op_return()) {
+            writer.add_element(script.as_bytes());
+        }
+        for script in spent.iter().filter(|script| !script.is_empty()) {
+            writer.add_element(script.as_bytes());
+        }
+        writer.finish().expect("writing to a vec cannot fail");
+    }
+    content
+}
+
+/// Filter header commits to the filter and all previous ones, the one before genesis is zero
+pub fn filter_header(filter: &[u8], previous: &[u8; 32]) -> [u8; 32] {
+    let filter_hash = sha256d::Hash::hash(filter);
+    sha256d::Hash::hash(&[filter_hash.as_byte_array().as_slice(), previous].concat()).to_byte_array()
+}
+
+/// Header of the filter before the one of `height`, a missing one would silently fork the header chain
+fn previous_header(read_tx: &ReadTransaction, height: u32) -> Result<[u8; 32], AppError> {
+    match height.checked_sub(1) {
+        Some(parent) => BlockFilter::get(read_tx, &Height(parent))?
+            .map(|parent| parent.header.0)
+            .ok_or_else(|| AppError::Internal(format!("Filter of block {} is missing, filter headers cannot continue", parent))),
+        None => Ok([0; 32]),
+    }
+}
+
+/// Scripts spent by a block, every prevout must be known as a filter missing any would be wrong for good
+fn spent_scripts(height: u32, prevouts: &[Vec<Option<TxOut>>]) -> Result<Vec<ScriptBuf>, AppError> {
+    prevouts
+        .iter()
+        .flatten()
+        .map(|prevout| prevout.as_ref().map(|prevout| prevout.script_pubkey.clone()))
+        .collect::<Option<Vec<ScriptBuf>>>()
+        .ok_or_else(|| AppError::Internal(format!("Block {} spends outputs which are not indexed, its filter cannot be built", height)))
+}
+
+pub fn block_filter(read_tx: &ReadTransaction, block: &Block, prevouts: &[Vec<Option<TxOut>>]) -> Result<BlockFilter, AppError> {
+    let spent = spent_scripts(block.id.0, prevouts)?;
+    let previous = previous_header(read_tx, block.id.0)?;
+    let outputs: Vec<ScriptBuf> = block.transactions.iter().flat_map(|tx| tx.utxos.iter()).map(|utxo| utxo.script_hash.to_script()).collect();
+    let filter = basic_filter(&block.header.hash.0, &outputs, &spent);
+    let header = filter_header(&filter, &previous);
+    Ok(BlockFilter { id: Height(block.id.0), filter: GcsFilter(filter), header: FilterHeaderHash(header) })
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterView {
+    pub block_hash: String,
+    pub height: u32,
+    pub filter: String,
+    pub header: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterHeadersView {
+    pub stop_hash: String,
+    pub previous_filter_header: String,
+    /// headers of blocks `start_height..=stop height` in chain order
+    pub filter_headers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeadersParams {
+    pub start_height: Option<u32>,
+}
+
+pub fn filter_of_block(read_tx: &ReadTransaction, hash: &[u8; 32]) -> Result<Option<FilterView>, AppError> {
+    let Some(header) = BlockHeader::find_by_hash_in_any_order(read_tx, hash)? else {
+        return Ok(None);
+    };
+    Ok(BlockFilter::get(read_tx, &header.id)?.map(|filter| FilterView {
+        block_hash: header.hash.0.to_lower_hex_string(),
+        height: header.id.0,
+        filter: filter.filter.0.to_lower_hex_string(),
+        header: filter.header.0.to_lower_hex_string(),
+    }))
+}
+
+/// Filter headers from `start_height` up to the block of `stop_hash`, like `getcfheaders`
+pub fn filter_headers(read_tx: &ReadTransaction, stop_hash: &[u8; 32], start_height: Option<u32>) -> Result<Option<FilterHeadersView>, AppError> {
+    let Some(stop) = BlockHeader::find_by_hash_in_any_order(read_tx, stop_hash)? else {
+        return Ok(None);
+    };
+    let start = start_height.unwrap_or(stop.id.0).min(stop.id.0).max(stop.id.0.saturating_sub(MAX_HEADERS - 1));
+    let previous = previous_header(read_tx, start)?;
+    let mut filter_headers = Vec::with_capacity((stop.id.0 - start + 1) as usize);
+    for height in start..=stop.id.0 {
+        if let Some(filter) = BlockFilter::get(read_tx, &Height(height))? {
+            filter_headers.push(filter.header.0.to_lower_hex_string());
+        }
+    }
+    Ok(Some(FilterHeadersView { stop_hash: stop.hash.0.to_lower_hex_string(), previous_filter_header: previous.to_lower_hex_string(), filter_headers }))
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/block/{hash}/filter", get(filter))
+        .route("/block/{hash}/filter-headers", get(headers))
+        .layer(Extension(pool))
+}
+
+async fn filter(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(hash): Path<String>) -> Response {
+    let block_hash = match codec::parse_hash(&hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    match pool.run(move || filter_of_block(&state.db.begin_read()?, &block_hash)).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Block {} not found", hash)),
+        result => json_response(result),
+    }
+}
+
+async fn headers(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(hash): Path<String>,
+    Query(params): Query<HeadersParams>,
+) -> Response {
+    let block_hash = match codec::parse_hash(&hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    match pool.run(move || filter_headers(&state.db.begin_read()?, &block_hash, params.start_height)).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Block {} not found", hash)),
+        result => json_response(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::serde_json;
+    use crate::test_support::GENESIS_OUTPUT;
+    use bitcoin::bip158::GcsFilterReader;
+    use bitcoin::hex::FromHex;
+
+    // genesis block of testnet3, the first BIP158 test vector
+    const TESTNET_GENESIS: &str = "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943";
+
+    fn reversed(hex: &str) -> [u8; 32] {
+        let mut bytes = <[u8; 32]>::from_hex(hex).unwrap();
+        bytes.reverse();
+        bytes
+    }
+
+    #[test]
+    fn test_genesis_filter() {
+        let filter = basic_filter(&reversed(TESTNET_GENESIS), &[ScriptBuf::from_hex(GENESIS_OUTPUT).unwrap()], &[]);
+        assert_eq!(filter.to_lower_hex_string(), "019dfca8");
+        // filter headers are displayed in reverse byte order like block hashes
+        assert_eq!(filter_header(&filter, &[0; 32]), reversed("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750"));
+    }
+
+    #[test]
+    fn test_filter_elements() {
+        let output = ScriptBuf::from_hex(GENESIS_OUTPUT).unwrap();
+        let op_return = ScriptBuf::from_hex("6a0b68656c6c6f20776f726c64").unwrap();
+        let spent = ScriptBuf::from_hex("76a914dfcec48bb8491856c353306ab5febeb7e99e4d7888ac").unwrap();
+        let block_hash = reversed(TESTNET_GENESIS);
+        let filter = basic_filter(&block_hash, &[output.clone(), op_return.clone(), ScriptBuf::new()], &[spent.clone(), ScriptBuf::new()]);
+
+        let k0 = u64::from_le_bytes(block_hash[0..8].try_into().unwrap());
+        let k1 = u64::from_le_bytes(block_hash[8..16].try_into().unwrap());
+        let reader = GcsFilterReader::new(k0, k1, M, P);
+        let matches = |script: &ScriptBuf| reader.match_any(&mut filter.as_slice(), std::iter::once(script.as_bytes())).unwrap();
+        assert!(matches(&output));
+        assert!(matches(&spent));
+        assert!(!matches(&op_return));
+        // two elements, empty and OP_RETURN scripts are left out
+        assert_eq!(filter[0], 2);
+    }
+
+    #[test]
+    fn test_unindexed_prevouts_fail() {
+        let spent = TxOut { value: bitcoin::Amount::from_sat(1), script_pubkey: ScriptBuf::from_hex(GENESIS_OUTPUT).unwrap() };
+        // coinbase spends nothing
+        let prevouts = vec![Vec::new(), vec![Some(spent.clone()), Some(spent.clone())]];
+        assert_eq!(spent_scripts(1, &prevouts).unwrap(), vec![spent.script_pubkey.clone(), spent.script_pubkey.clone()]);
+        assert!(spent_scripts(1, &[Vec::new(), vec![Some(spent), None]]).is_err());
+    }
+
+    // rows of the official https://github.com/bitcoin/bips/blob/master/bip-0158/testnet-19.json, the whole file can replace it as is
+    const BIP158_VECTORS: &str = include_str!("testdata/testnet-19.json");
+
+    #[test]
+    fn test_bip158_vectors() {
+        let rows: Vec<serde_json::Value> = serde_json::from_str(BIP158_VECTORS).unwrap();
+        // the first row names the columns
+        for row in rows.iter().skip(1) {
+            let notes = row[7].as_str().unwrap();
+            let block: bitcoin::Block = bitcoin::consensus::deserialize(&Vec::<u8>::from_hex(row[2].as_str().unwrap()).unwrap()).unwrap();
+            let outputs: Vec<ScriptBuf> = block.txdata.iter().flat_map(|tx| tx.output.iter()).map(|output| output.script_pubkey.clone()).collect();
+            let spent: Vec<ScriptBuf> =
+                row[3].as_array().unwrap().iter().map(|script| ScriptBuf::from_hex(script.as_str().unwrap()).unwrap()).collect();
+            let filter = basic_filter(&reversed(row[1].as_str().unwrap()), &outputs, &spent);
+            assert_eq!(filter.to_lower_hex_string(), row[5].as_str().unwrap(), "{}", notes);
+            assert_eq!(filter_header(&filter, &reversed(row[4].as_str().unwrap())), reversed(row[6].as_str().unwrap()), "{}", notes);
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::config::FinalitySettings;
+use crate::model::{Block, BlockHash, BlockHeader, EventId, EventKind, FeedEvent, Height};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hex::DisplayHex;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+pub const MAX_LIMIT: usize = 1000;
+// confirmed-only pages skip tentative events, so they scan more of the feed to fill the page
+const CONFIRMED_SCAN_FACTOR: usize = 4;
+pub const FINAL_HEIGHT_HEADER: &str = "x-final-height";
+// probes and scrapes are not about chain data and run often, they go without the header
+const WITHOUT_FINAL_HEIGHT: [&str; 2] = ["/health", "/metrics"];
+
+/// Blocks with at least `finality.depth` confirmations are final. The event feed reports every block twice,
+/// tentatively when it is stored and confirmed once it gets final, so that consumers who cannot handle rollbacks
+/// may follow confirmed events only.
+pub struct Finality {
+    depth: u32,
+    // height of the last stored block plus one, 0 while there is none, so that responses get the final height without a query
+    stored_tip: AtomicU32,
+}
+
+/// Events to be appended to the feed, ids continue from the last stored event
+struct FeedAppender {
+    next_id: u32,
+    finalized_height: Option<u32>,
+    events: Vec<FeedEvent>,
+}
+
+impl FeedAppender {
+    fn load(read_tx: &ReadTransaction, finality: &Finality) -> Result<Self, AppError> {
+        let Some(last) = FeedEvent::last(read_tx)? else {
+            return Ok(FeedAppender { next_id: 0, finalized_height: None, events: Vec::new() });
+        };
+        let mut feed = FeedAppender { next_id: last.id.0 + 1, finalized_height: None, events: Vec::new() };
+        // finalized height is given by the latest confirmed or unconfirmed event which cannot precede confirmation
+        // of the block that is final at current tip, so only events since then are replayed
+        let from = Self::final_block_confirmation(read_tx, finality)?.unwrap_or(0);
+        for id in from..=last.id.0 {
+            if let Some(event) = FeedEvent::get(read_tx, &EventId(id))? {
+                feed.track(&event.kind, event.height);
+            }
+        }
+        Ok(feed)
+    }
+
+    /// Id of the event that confirmed the block which is final at current tip, looked up by its hash
+    fn final_block_confirmation(read_tx: &ReadTransaction, finality: &Finality) -> Result<Option<u32>, AppError> {
+        let Some(final_height) = BlockHeader::last(read_tx)?.and_then(|tip| finality.final_height(tip.id.0)) else {
+            return Ok(None);
+        };
+        let Some(final_header) = BlockHeader::get(read_tx, &Height(final_height))? else {
+            return Ok(None);
+        };
+        let mut confirmation = None;
+        for id in FeedEvent::get_ids_by_hash(read_tx, &final_header.hash)? {
+            if let Some(event) = FeedEvent::get(read_tx, &id)?
+                && event.kind == EventKind::CONFIRMED
+            {
+                confirmation = confirmation.max(Some(id.0));
+            }
+        }
+        Ok(confirmation)
+    }
+
+    /// A confirmed event finalizes its block, an unconfirmed one reverts finality to the block below
+    fn track(&mut self, kind: &EventKind, height: u32) {
+        if *kind == EventKind::CONFIRMED {
+            self.finalized_height = Some(height);
+        } else if *kind == EventKind::UNCONFIRMED {
+            self.finalized_height = height.checked_sub(1);
+        }
+    }
+
+    fn push(&mut self, kind: EventKind, height: u32, hash: BlockHash) {
+        self.track(&kind, height);
+        self.events.push(FeedEvent { id: EventId(self.next_id), kind, height, hash });
+        self.next_id += 1;
+    }
+
+    /// `final_hash` provides hashes of stored blocks that get final
+    fn connect(
+        &mut self,
+        finality: &Finality,
+        header: &BlockHeader,
+        final_hash: impl Fn(u32) -> Result<Option<BlockHash>, AppError>,
+    ) -> Result<(), AppError> {
+        self.push(EventKind::CONNECTED, header.id.0, header.hash.clone());
+        if let Some(final_height) = finality.final_height(header.id.0) {
+            let from = self.finalized_height.map_or(final_height, |finalized| finalized + 1);
+            for height in from..=final_height {
+                let hash = if height == header.id.0 { Some(header.hash.clone()) } else { final_hash(height)? };
+                if let Some(hash) = hash {
+                    self.push(EventKind::CONFIRMED, height, hash);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self, stale: &[Block]) {
+        for block in stale.iter().rev() {
+            self.push(EventKind::DISCONNECTED, block.id.0, block.header.hash.clone());
+        }
+        let finalized_height = self.finalized_height;
+        for block in stale.iter().rev().filter(|block| finalized_height.is_some_and(|finalized| block.id.0 <= finalized)) {
+            self.push(EventKind::UNCONFIRMED, block.id.0, block.header.hash.clone());
+        }
+    }
+}
+
+impl Finality {
+    pub fn new(settings: &FinalitySettings) -> Self {
+        Finality { depth: settings.depth.max(1), stored_tip: AtomicU32::new(0) }
+    }
+
+    /// Called once a block at `tip` is committed, or the chain is cut down to `tip` by a reorg
+    pub fn record_tip(&self, tip: u32) {
+        self.stored_tip.store(tip + 1, Ordering::Release);
+    }
+
+    /// Final height of the stored chain as last recorded by `record_tip`
+    pub fn stored_final_height(&self) -> Option<u32> {
+        self.stored_tip.load(Ordering::Acquire).checked_sub(1).and_then(|tip| self.final_height(tip))
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Highest final height of chain ending at `tip`, `None` while the chain is shorter than finality depth
+    pub fn final_height(&self, tip: u32) -> Option<u32> {
+        (tip + 1).checked_sub(self.depth)
+    }
+
+    pub fn is_final(&self, height: u32, tip: u32) -> bool {
+        self.final_height(tip).is_some_and(|final_height| height <= final_height)
+    }
+
+    /// Feed events of a block stored on top of the chain, followed by the block that it made final.
+    /// A fresh feed starts at the current final height instead of replaying the whole chain.
+    pub fn connected_events(&self, read_tx: &ReadTransaction, header: &BlockHeader) -> Result<Vec<FeedEvent>, AppError> {
+        let mut feed = FeedAppender::load(read_tx, self)?;
+        feed.connect(self, header, |height| Ok(BlockHeader::get(read_tx, &Height(height))?.map(|final_header| final_header.hash)))?;
+        Ok(feed.events)
+    }
+
+    /// Feed events of `stale` blocks being replaced by a fork, from the tip down.
+    /// Only a reorg deeper than finality depth disconnects confirmed blocks.
+    pub fn disconnected_events(&self, read_tx: &ReadTransaction, stale: &[Block]) -> Result<Vec<FeedEvent>, AppError> {
+        let mut feed = FeedAppender::load(read_tx, self)?;
+        feed.disconnect(stale);
+        Ok(feed.events)
+    }
+}
+
+/// Adds `X-Final-Height` to responses of every endpoint, the redbit generated ones included, so that blocks at or below it
+/// can be told final and those above tentative. It is read before the request runs, so it never exceeds the final height
+/// of the snapshot the response comes from, and it is left out while the chain is shorter than finality depth.
+/// The tip is kept in memory by the persistence, so the header costs no query.
+pub async fn final_height_header(State(finality): State<Arc<Finality>>, request: Request, next: Next) -> Response {
+    if WITHOUT_FINAL_HEIGHT.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let final_height = finality.stored_final_height();
+    let mut response = next.run(request).await;
+    if let Some(final_height) = final_height {
+        response.headers_mut().insert(FINAL_HEIGHT_HEADER, HeaderValue::from(final_height));
+    }
+    response
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventView {
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub height: u32,
+    pub hash: String,
+    pub confirmed: bool,
+}
+
+impl From<&FeedEvent> for EventView {
+    fn from(event: &FeedEvent) -> Self {
+        EventView {
+            id: event.id.0,
+            kind: event.kind.name(),
+            height: event.height,
+            hash: event.hash.0.to_lower_hex_string(),
+            confirmed: event.kind.is_confirmed(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventPage {
+    pub events: Vec<EventView>,
+    pub next_cursor: Option<u32>,
+    pub final_height: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventParams {
+    pub cursor: Option<u32>,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub confirmed_only: bool,
+}
+
+/// Events following `cursor` which is the id of the last event a consumer has seen, `next_cursor` is to be passed
+/// to the next request. It advances past skipped tentative events too, so an empty confirmed page still moves on.
+pub fn list_events(read_tx: &ReadTransaction, finality: &Finality, params: &EventParams) -> Result<EventPage, AppError> {
+    let limit = params.limit.unwrap_or(100).min(MAX_LIMIT);
+    let scan = if params.confirmed_only { limit * CONFIRMED_SCAN_FACTOR } else { limit };
+    let from = params.cursor.map_or(0, |cursor| cursor + 1);
+    let until = FeedEvent::last(read_tx)?.map(|last| last.id.0 + 1).unwrap_or(0);
+    let mut page = EventPage { events: Vec::new(), next_cursor: params.cursor, final_height: None };
+    for id in (from..until).take(scan) {
+        if page.events.len() >= limit {
+            break;
+        }
+        page.next_cursor = Some(id);
+        if let Some(event) = FeedEvent::get(read_tx, &EventId(id))?
+            && (!params.confirmed_only || event.kind.is_confirmed())
+        {
+            page.events.push(EventView::from(&event));
+        }
+    }
+    page.final_height = BlockHeader::last(read_tx)?.and_then(|tip| finality.final_height(tip.id.0));
+    Ok(page)
+}
+
+#[derive(Debug, Serialize)]
+pub struct FinalityView {
+    pub depth: u32,
+    pub tip_height: Option<u32>,
+    pub final_height: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockFinality {
+    pub height: u32,
+    pub hash: String,
+    pub confirmations: u32,
+    pub status: &'static str,
+}
+
+pub fn block_finality(read_tx: &ReadTransaction, finality: &Finality, height: Height) -> Result<Option<BlockFinality>, AppError> {
+    let (Some(header), Some(tip)) = (BlockHeader::get(read_tx, &height)?, BlockHeader::last(read_tx)?) else {
+        return Ok(None);
+    };
+    Ok(Some(BlockFinality {
+        height: header.id.0,
+        hash: header.hash.0.to_lower_hex_string(),
+        confirmations: (tip.id.0 + 1).saturating_sub(header.id.0),
+        status: if finality.is_final(header.id.0, tip.id.0) { "final" } else { "tentative" },
+    }))
+}
+
+pub fn routes(finality: Arc<Finality>, pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/finality", get(chain_finality))
+        .route("/finality/{height}", get(height_finality))
+        .route("/events", get(events))
+        .layer(Extension(finality))
+        .layer(Extension(pool))
+}
+
+async fn chain_finality(
+    State(state): State<RequestState>,
+    Extension(finality): Extension<Arc<Finality>>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+) -> Response {
+    let result = pool
+        .run(move || {
+            let tip_height = BlockHeader::last(&state.db.begin_read()?)?.map(|tip| tip.id.0);
+            Ok::<_, AppError>(FinalityView { depth: finality.depth(), tip_height, final_height: tip_height.and_then(|tip| finality.final_height(tip)) })
+        })
+        .await;
+    json_response(result)
+}
+
+async fn height_finality(
+    State(state): State<RequestState>,
+    Extension(finality): Extension<Arc<Finality>>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(height): Path<u32>,
+) -> Response {
+    match pool.run(move || block_finality(&state.db.begin_read()?, &finality, Height(height))).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Block at height {} is not indexed", height)),
+        result => json_response(result),
+    }
+}
+
+/// Replayable feed of block connections and disconnections, optionally of confirmed ones only
+async fn events(
+    State(state): State<RequestState>,
+    Extension(finality): Extension<Arc<Finality>>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Query(params): Query<EventParams>,
+) -> Response {
+    json_response(pool.run(move || list_events(&state.db.begin_read()?, &finality, &params)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_final_height() {
+        let finality = Finality::new(&FinalitySettings { depth: 6 });
+        assert_eq!(finality.final_height(4), None);
+        assert_eq!(finality.final_height(5), Some(0));
+        assert_eq!(finality.final_height(100), Some(95));
+        assert!(finality.is_final(95, 100));
+        assert!(!finality.is_final(96, 100));
+
+        // depth 0 would make blocks final before they are stored
+        let tip_only = Finality::new(&FinalitySettings { depth: 0 });
+        assert_eq!(tip_only.final_height(100), Some(100));
+    }
+
+    #[tokio::test]
+    async fn test_final_height_header() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let finality = Arc::new(Finality::new(&FinalitySettings { depth: 6 }));
+        let app = axum::Router::new()
+            .route("/block", get(|| async { "" }))
+            .route("/health", get(|| async { "" }))
+            .layer(axum::middleware::from_fn_with_state(Arc::clone(&finality), final_height_header));
+        let header = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                response.headers().get(FINAL_HEIGHT_HEADER).map(|value| value.to_str().unwrap().to_string())
+            }
+        };
+        assert_eq!(header("/block").await, None);
+        finality.record_tip(4);
+        assert_eq!(header("/block").await, None);
+        finality.record_tip(100);
+        assert_eq!(header("/block").await.as_deref(), Some("95"));
+        assert_eq!(header("/health").await, None);
+        // a reorg cutting the chain down lowers it until the new blocks are stored
+        finality.record_tip(90);
+        assert_eq!(header("/block").await.as_deref(), Some("85"));
+    }
+
+    #[test]
+    fn test_event_views() {
+        let event = |kind| FeedEvent { id: EventId(7), kind, height: 10, hash: BlockHash([0; 32]) };
+        let cases = [
+            (EventKind::CONNECTED, "block_connected", false),
+            (EventKind::DISCONNECTED, "block_disconnected", false),
+            (EventKind::CONFIRMED, "block_connected", true),
+            (EventKind::UNCONFIRMED, "block_disconnected", true),
+        ];
+        for (kind, name, confirmed) in cases {
+            let view = EventView::from(&event(kind));
+            assert_eq!((view.kind, view.confirmed), (name, confirmed));
+        }
+    }
+
+    fn block(height: u32) -> Block {
+        let mut block = Block::sample();
+        block.id = Height(height);
+        block.header.id = Height(height);
+        block.header.hash = BlockHash([height as u8; 32]);
+        block
+    }
+
+    fn kinds(events: &[FeedEvent]) -> Vec<(u32, EventKind, u32)> {
+        events.iter().map(|event| (event.id.0, event.kind, event.height)).collect()
+    }
+
+    fn stored_hash(height: u32) -> Result<Option<BlockHash>, AppError> {
+        Ok(Some(BlockHash([height as u8; 32])))
+    }
+
+    #[test]
+    fn test_connected_events() {
+        let finality = Finality::new(&FinalitySettings { depth: 3 });
+        let mut feed = FeedAppender { next_id: 5, finalized_height: None, events: Vec::new() };
+        feed.connect(&finality, &block(1).header, stored_hash).unwrap();
+        feed.connect(&finality, &block(2).header, stored_hash).unwrap();
+        feed.connect(&finality, &block(3).header, stored_hash).unwrap();
+        assert_eq!(
+            kinds(&feed.events),
+            vec![
+                (5, EventKind::CONNECTED, 1),
+                (6, EventKind::CONNECTED, 2),
+                (7, EventKind::CONFIRMED, 0),
+                (8, EventKind::CONNECTED, 3),
+                (9, EventKind::CONFIRMED, 1)
+            ]
+        );
+        assert_eq!(feed.events[4].hash, BlockHash([1; 32]));
+        assert_eq!(feed.finalized_height, Some(1));
+
+        // a fresh feed of a long chain starts at its final height
+        let mut fresh = FeedAppender { next_id: 0, finalized_height: None, events: Vec::new() };
+        fresh.connect(&finality, &block(100).header, stored_hash).unwrap();
+        assert_eq!(kinds(&fresh.events), vec![(0, EventKind::CONNECTED, 100), (1, EventKind::CONFIRMED, 98)]);
+
+        // with depth 1 a block is final as soon as it is stored, its hash is not looked up
+        let tip_only = Finality::new(&FinalitySettings { depth: 1 });
+        let mut feed = FeedAppender { next_id: 0, finalized_height: Some(99), events: Vec::new() };
+        feed.connect(&tip_only, &block(100).header, |_| Ok(None)).unwrap();
+        assert_eq!(kinds(&feed.events), vec![(0, EventKind::CONNECTED, 100), (1, EventKind::CONFIRMED, 100)]);
+    }
+
+    #[test]
+    fn test_disconnected_events() {
+        let finality = Finality::new(&FinalitySettings { depth: 3 });
+        let stale: Vec<Block> = (4..=7).map(block).collect();
+
+        // shallow reorg leaves confirmed events alone
+        let mut feed = FeedAppender { next_id: 0, finalized_height: Some(5), events: Vec::new() };
+        feed.disconnect(&stale[2..]);
+        assert_eq!(kinds(&feed.events), vec![(0, EventKind::DISCONNECTED, 7), (1, EventKind::DISCONNECTED, 6)]);
+        assert_eq!(feed.finalized_height, Some(5));
+
+        // deep reorg unconfirms final blocks from the tip down
+        let mut feed = FeedAppender { next_id: 0, finalized_height: Some(5), events: Vec::new() };
+        feed.disconnect(&stale);
+        assert_eq!(
+            kinds(&feed.events),
+            vec![
+                (0, EventKind::DISCONNECTED, 7),
+                (1, EventKind::DISCONNECTED, 6),
+                (2, EventKind::DISCONNECTED, 5),
+                (3, EventKind::DISCONNECTED, 4),
+                (4, EventKind::UNCONFIRMED, 5),
+                (5, EventKind::UNCONFIRMED, 4)
+            ]
+        );
+        assert_eq!(feed.finalized_height, Some(3));
+
+        // blocks of the new fork get confirmed again only once they are deep enough
+        feed.connect(&finality, &block(4).header, stored_hash).unwrap();
+        feed.connect(&finality, &block(5).header, stored_hash).unwrap();
+        feed.connect(&finality, &block(6).header, stored_hash).unwrap();
+        assert_eq!(
+            kinds(&feed.events[6..]),
+            vec![(6, EventKind::CONNECTED, 4), (7, EventKind::CONNECTED, 5), (8, EventKind::CONNECTED, 6), (9, EventKind::CONFIRMED, 4)]
+        );
+    }
+}
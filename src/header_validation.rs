@@ -0,0 +1,240 @@
+use crate::model::{BlockHeader, ExplorerError, Height};
+use bitcoin::hashes::Hash;
+use bitcoin::params::Params;
+use bitcoin::{CompactTarget, Network, Target};
+
+/// Consensus checks of block headers that chain-syncer's fork competition does not do,
+/// so that a misbehaving or misconfigured node cannot feed us blocks of another chain.
+pub struct HeaderValidator {
+    network: Network,
+    params: Params,
+}
+
+impl HeaderValidator {
+    pub fn new(network: Network) -> Self {
+        HeaderValidator { network, params: Params::new(network) }
+    }
+
+    fn interval(&self) -> u32 {
+        self.params.difficulty_adjustment_interval() as u32
+    }
+
+    fn pow_limit_bits(&self) -> u32 {
+        self.params.max_attainable_target.to_compact_lossy().to_consensus()
+    }
+
+    /// Block hash must meet the target encoded in its own `bits` which must not exceed network's pow limit
+    pub fn check_pow(&self, header: &BlockHeader) -> Result<(), ExplorerError> {
+        let target = Target::from_compact(CompactTarget::from_consensus(header.bits.0));
+        let hash = bitcoin::BlockHash::from_byte_array(header.hash.0);
+        if target > self.params.max_attainable_target || !target.is_met_by(hash) {
+            return Err(ExplorerError::InvalidProofOfWork { height: header.id.0, hash: hash.to_string(), bits: header.bits.0 });
+        }
+        Ok(())
+    }
+
+    /// Required `bits` of a block following `parent` as in Core's `GetNextWorkRequired`,
+    /// `None` if headers needed for the calculation are not stored.
+    pub fn expected_bits<F>(&self, header: &BlockHeader, parent: &BlockHeader, mut lookup: F) -> Result<Option<u32>, ExplorerError>
+    where
+        F: FnMut(Height) -> Result<Option<BlockHeader>, ExplorerError>,
+    {
+        let interval = self.interval();
+        let height = header.id.0;
+        if !height.is_multiple_of(interval) {
+            if !self.params.allow_min_difficulty_blocks {
+                return Ok(Some(parent.bits.0));
+            }
+            // testnet's 20-minute rule, block may have min difficulty if it comes twice the target spacing after its parent
+            let pow_limit_bits = self.pow_limit_bits();
+            if header.timestamp.0 as u64 > parent.timestamp.0 as u64 + self.params.pow_target_spacing * 2 {
+                return Ok(Some(pow_limit_bits));
+            }
+            // otherwise it must have difficulty of the last block which was not mined under the 20-minute rule
+            let mut last = parent.clone();
+            while !last.id.0.is_multiple_of(interval) && last.bits.0 == pow_limit_bits {
+                match lookup(Height(last.id.0 - 1))? {
+                    Some(previous) => last = previous,
+                    None => return Ok(None),
+                }
+            }
+            return Ok(Some(last.bits.0));
+        }
+        if self.params.no_pow_retargeting {
+            return Ok(Some(parent.bits.0));
+        }
+        let Some(first) = lookup(Height(height - interval))? else {
+            return Ok(None);
+        };
+        let timespan = (parent.timestamp.0 as i64 - first.timestamp.0 as i64).max(0) as u64;
+        // BIP94 retargets from the first block of the period, so that it is not affected by 20-minute rule blocks
+        let last_bits = if self.network == Network::Testnet4 { first.bits.0 } else { parent.bits.0 };
+        let next = CompactTarget::from_next_work_required(CompactTarget::from_consensus(last_bits), timespan, &self.params);
+        Ok(Some(next.to_consensus()))
+    }
+
+    pub fn check_difficulty<F>(&self, header: &BlockHeader, parent: &BlockHeader, lookup: F) -> Result<(), ExplorerError>
+    where
+        F: FnMut(Height) -> Result<Option<BlockHeader>, ExplorerError>,
+    {
+        match self.expected_bits(header, parent, lookup)? {
+            Some(expected) if expected != header.bits.0 => {
+                Err(ExplorerError::UnexpectedDifficulty { height: header.id.0, bits: header.bits.0, expected })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Difficulty is checked only if parent is stored, ie. not when storing arbitrary blocks in benchmarks
+    pub fn validate<F>(&self, header: &BlockHeader, parent: Option<&BlockHeader>, lookup: F) -> Result<(), ExplorerError>
+    where
+        F: FnMut(Height) -> Result<Option<BlockHeader>, ExplorerError>,
+    {
+        self.check_pow(header)?;
+        match parent {
+            Some(parent) => self.check_difficulty(header, parent, lookup),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, BlockTimestamp, BlockVersion, Bits, MerkleRoot, Nonce};
+    use bitcoin::consensus::encode::deserialize_hex;
+    use std::collections::HashMap;
+
+    // mainnet headers of blocks 0, 1 and 2
+    const HEADERS: [&str; 3] = [
+        "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c",
+        "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299",
+        "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61",
+    ];
+
+    fn from_raw(height: u32, raw: &bitcoin::block::Header) -> BlockHeader {
+        BlockHeader {
+            id: Height(height),
+            hash: BlockHash(*raw.block_hash().as_ref()),
+            prev_hash: BlockHash(*raw.prev_blockhash.as_ref()),
+            timestamp: BlockTimestamp(raw.time),
+            merkle_root: MerkleRoot(*raw.merkle_root.as_ref()),
+            bits: Bits(raw.bits.to_consensus()),
+            version: BlockVersion(raw.version.to_consensus()),
+            nonce: Nonce(raw.nonce),
+        }
+    }
+
+    fn fixture(height: usize) -> BlockHeader {
+        from_raw(height as u32, &deserialize_hex(HEADERS[height]).unwrap())
+    }
+
+    // headers of which only height, timestamp and bits matter for difficulty checks
+    fn header(height: u32, timestamp: u32, bits: u32) -> BlockHeader {
+        BlockHeader {
+            id: Height(height),
+            hash: BlockHash([0; 32]),
+            prev_hash: BlockHash([0; 32]),
+            timestamp: BlockTimestamp(timestamp),
+            merkle_root: MerkleRoot([0; 32]),
+            bits: Bits(bits),
+            version: BlockVersion(1),
+            nonce: Nonce(0),
+        }
+    }
+
+    fn lookup(headers: &[BlockHeader]) -> impl FnMut(Height) -> Result<Option<BlockHeader>, ExplorerError> {
+        let by_height: HashMap<u32, BlockHeader> = headers.iter().map(|h| (h.id.0, h.clone())).collect();
+        move |height| Ok(by_height.get(&height.0).cloned())
+    }
+
+    #[test]
+    fn test_historical_headers_are_valid() {
+        let validator = HeaderValidator::new(Network::Bitcoin);
+        let headers: Vec<BlockHeader> = (0..HEADERS.len()).map(fixture).collect();
+        validator.validate(&headers[0], None, lookup(&headers)).unwrap();
+        for pair in headers.windows(2) {
+            assert_eq!(pair[1].prev_hash, pair[0].hash);
+            validator.validate(&pair[1], Some(&pair[0]), lookup(&headers)).unwrap();
+        }
+        for header in &headers {
+            assert_eq!(header.to_raw().block_hash().as_byte_array(), &header.hash.0);
+        }
+    }
+
+    #[test]
+    fn test_tampered_header_fails_pow() {
+        let validator = HeaderValidator::new(Network::Bitcoin);
+        let mut raw: bitcoin::block::Header = deserialize_hex(HEADERS[1]).unwrap();
+        raw.nonce += 1;
+        assert!(matches!(validator.check_pow(&from_raw(1, &raw)), Err(ExplorerError::InvalidProofOfWork { .. })));
+
+        let mut easier = fixture(1);
+        easier.bits = Bits(0x207fffff); // regtest pow limit is above mainnet's
+        assert!(validator.check_pow(&easier).is_err());
+    }
+
+    #[test]
+    fn test_difficulty_must_not_change_between_retargets() {
+        let validator = HeaderValidator::new(Network::Bitcoin);
+        let parent = header(100, 1_000_000, 0x1d00ffff);
+        validator.check_difficulty(&header(101, 1_000_600, 0x1d00ffff), &parent, lookup(&[])).unwrap();
+        assert!(validator.check_difficulty(&header(101, 1_000_600, 0x1d00d86a), &parent, lookup(&[])).is_err());
+        // no 20-minute rule on mainnet
+        assert!(validator.check_difficulty(&header(101, 1_010_000, 0x1d00ffff), &header(100, 1_000_000, 0x1c05a3f4), lookup(&[])).is_err());
+    }
+
+    // vectors from Bitcoin Core's pow_tests.cpp, timestamps and bits of real mainnet blocks
+    #[test]
+    fn test_mainnet_retargets() {
+        let validator = HeaderValidator::new(Network::Bitcoin);
+        let cases = [
+            (1231006505, 2015, 1233061996, 0x1d00ffff, 0x1d00ffff), // capped by pow limit
+            (1261130161, 32255, 1262152739, 0x1d00ffff, 0x1d00d86a), // no constraints
+            (1279008237, 68543, 1279297671, 0x1c05a3f4, 0x1c0168fd), // lower bound of actual timespan
+            (1263163443, 46367, 1269211443, 0x1c387f6f, 0x1d00e1fd), // upper bound of actual timespan
+        ];
+        for (first_time, last_height, last_time, last_bits, expected_bits) in cases {
+            let first = header(last_height - 2015, first_time, last_bits);
+            let parent = header(last_height, last_time, last_bits);
+            let next = header(last_height + 1, last_time + 600, expected_bits);
+            validator.check_difficulty(&next, &parent, lookup(std::slice::from_ref(&first))).unwrap();
+            let wrong = header(last_height + 1, last_time + 600, last_bits + 1);
+            assert!(validator.check_difficulty(&wrong, &parent, lookup(&[first])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_retarget_is_skipped_without_period_start() {
+        let validator = HeaderValidator::new(Network::Bitcoin);
+        let parent = header(32255, 1262152739, 0x1d00ffff);
+        assert_eq!(validator.expected_bits(&header(32256, 1262153000, 0x1d00d86a), &parent, lookup(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn test_testnet_20_minute_rule() {
+        let validator = HeaderValidator::new(Network::Testnet);
+        let normal_bits = 0x1c0ffff0;
+        let min_bits = 0x1d00ffff;
+        let anchor = header(4033, 1_000_000, normal_bits);
+
+        // more than 20 minutes after parent, min difficulty is allowed
+        validator.check_difficulty(&header(4034, 1_001_201, min_bits), &anchor, lookup(&[])).unwrap();
+        // within 20 minutes it is not
+        assert!(validator.check_difficulty(&header(4034, 1_000_600, min_bits), &anchor, lookup(&[])).is_err());
+
+        // after a min difficulty block, difficulty returns to the last regular one
+        let special = header(4034, 1_001_201, min_bits);
+        let headers = [anchor.clone(), special.clone()];
+        validator.check_difficulty(&header(4035, 1_001_500, normal_bits), &special, lookup(&headers)).unwrap();
+        assert!(validator.check_difficulty(&header(4035, 1_001_500, min_bits), &special, lookup(&headers)).is_err());
+    }
+
+    #[test]
+    fn test_regtest_never_retargets() {
+        let validator = HeaderValidator::new(Network::Regtest);
+        let parent = header(2015, 1_000_000, 0x207fffff);
+        let first = header(0, 1, 0x207fffff);
+        validator.check_difficulty(&header(2016, 1_000_600, 0x207fffff), &parent, lookup(&[first])).unwrap();
+    }
+}
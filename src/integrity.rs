@@ -0,0 +1,106 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::merkle;
+use crate::model::{Block, BlockHeader, ExplorerError, Height, TxHash};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hashes::Hash;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const MAX_VERIFY_RANGE: u32 = 1000;
+
+fn display_block_hash(hash: [u8; 32]) -> String {
+    bitcoin::BlockHash::from_byte_array(hash).to_string()
+}
+
+/// Recomputes merkle root from tx hashes so that corrupt or misordered transactions are caught
+pub fn check_merkle_root(header: &BlockHeader, tx_hashes: &[TxHash]) -> Result<(), ExplorerError> {
+    let computed = merkle::merkle_root(tx_hashes);
+    if computed.as_ref() != Some(&header.merkle_root) {
+        return Err(ExplorerError::MerkleRootMismatch {
+            height: header.id.0,
+            expected: bitcoin::TxMerkleNode::from_byte_array(header.merkle_root.0).to_string(),
+            computed: computed.map_or("nothing".to_string(), |root| bitcoin::TxMerkleNode::from_byte_array(root.0).to_string()),
+        });
+    }
+    Ok(())
+}
+
+/// Parent may be missing only if it was never indexed, ie. when storing arbitrary blocks in benchmarks
+pub fn check_parent(header: &BlockHeader, parent: Option<&BlockHeader>) -> Result<(), ExplorerError> {
+    match parent {
+        Some(parent) if parent.hash != header.prev_hash => Err(ExplorerError::ParentMismatch {
+            height: header.id.0,
+            prev_hash: display_block_hash(header.prev_hash.0),
+            parent_hash: display_block_hash(parent.hash.0),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    pub height: u32,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationReport {
+    pub from: u32,
+    pub until: u32,
+    pub verified: u32,
+    pub issues: Vec<Issue>,
+}
+
+/// Verifies merkle roots and parent links of stored blocks in range `from..=until`
+pub fn verify_stored(read_tx: &ReadTransaction, from: Height, until: Height) -> Result<VerificationReport, AppError> {
+    let mut report = VerificationReport { from: from.0, until: until.0, verified: 0, issues: Vec::new() };
+    let mut parent = if from.0 > 0 { BlockHeader::get(read_tx, &Height(from.0 - 1))? } else { None };
+    for height in from.0..=until.0 {
+        match Block::get(read_tx, &Height(height))? {
+            None => {
+                report.issues.push(Issue { height, error: "Block is missing".to_string() });
+                parent = None;
+            }
+            Some(block) => {
+                let tx_hashes: Vec<TxHash> = block.transactions.iter().map(|tx| tx.hash.clone()).collect();
+                let checks = [check_merkle_root(&block.header, &tx_hashes), check_parent(&block.header, parent.as_ref())];
+                for error in checks.into_iter().filter_map(Result::err) {
+                    report.issues.push(Issue { height, error: error.to_string() });
+                }
+                report.verified += 1;
+                parent = Some(block.header);
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyParams {
+    pub from: u32,
+    pub until: Option<u32>,
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/verify", get(verify)).layer(Extension(pool))
+}
+
+async fn verify(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<VerifyParams>) -> Response {
+    let until = params.until.unwrap_or(params.from);
+    if until < params.from || until - params.from >= MAX_VERIFY_RANGE {
+        return error_response(StatusCode::BAD_REQUEST, format!("Range must be ascending and span at most {} blocks", MAX_VERIFY_RANGE));
+    }
+    let result = pool
+        .run(move || {
+            let read_tx = state.db.begin_read()?;
+            verify_stored(&read_tx, Height(params.from), Height(until))
+        })
+        .await;
+    json_response(result)
+}
@@ -1,10 +1,41 @@
 #![feature(test)]
 extern crate test;
 
+pub mod backpressure;
 pub mod block_persistence;
 pub mod block_provider;
+#[cfg(feature = "brc20")]
+pub mod brc20;
+pub mod broadcast;
 pub mod btc_client;
+pub mod checkpoints;
 pub mod config;
+pub mod decode;
+pub mod filters;
+pub mod finality;
+pub mod header_validation;
+pub mod integrity;
+pub mod merkle;
 pub mod model;
 pub mod storage;
 pub mod codec;
+pub mod notifier;
+pub mod op_return;
+pub mod pubkeys;
+#[cfg(feature = "ordinals")]
+pub mod ordinals;
+pub mod raw_blocks;
+pub mod reorgs;
+#[cfg(feature = "runes")]
+pub mod runes;
+#[cfg(feature = "script_verification")]
+pub mod script_verification;
+pub mod search;
+pub mod server;
+#[cfg(feature = "silent_payments")]
+pub mod silent_payments;
+pub mod status;
+pub mod telemetry;
+#[cfg(test)]
+mod test_support;
+pub mod ws;
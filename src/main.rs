@@ -8,23 +8,82 @@ use redbit::redb::Database;
 use redbit::*;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::watch;
 use tower_http::cors;
+use bitcoin_explorer::backpressure::{Guard, QueryPool, WriteThrottle};
 use bitcoin_explorer::block_persistence::BtcBlockPersistence;
+use bitcoin_explorer::broadcast::{self, Mempool};
 use bitcoin_explorer::block_provider::BtcBlockProvider;
+use bitcoin_explorer::header_validation::HeaderValidator;
 use bitcoin_explorer::btc_client::{BtcBlock, BtcClient};
-use bitcoin_explorer::config::BitcoinConfig;
-use bitcoin_explorer::model::Block;
-use bitcoin_explorer::storage;
+use bitcoin_explorer::checkpoints::Checkpoints;
+use bitcoin_explorer::config::{BitcoinConfig, BroadcastSettings, ExplorerSettings};
+use bitcoin_explorer::finality::Finality;
+use bitcoin_explorer::model::{Block, BlockHeader};
+use bitcoin_explorer::notifier::Notifier;
+use bitcoin_explorer::raw_blocks::{self, RawBlocks};
+#[cfg(feature = "brc20")]
+use bitcoin_explorer::brc20;
+#[cfg(feature = "ordinals")]
+use bitcoin_explorer::ordinals::{self, Ordinals};
+#[cfg(feature = "runes")]
+use bitcoin_explorer::runes::{self, Runes};
+#[cfg(feature = "script_verification")]
+use bitcoin_explorer::script_verification::{self, ScriptVerifier};
+#[cfg(feature = "silent_payments")]
+use bitcoin_explorer::silent_payments;
+use bitcoin_explorer::status::SyncStatus;
+use bitcoin_explorer::{decode, filters, finality, integrity, merkle, op_return, pubkeys, reorgs, search, server, status, storage, telemetry, ws};
+use metrics_exporter_prometheus::PrometheusHandle;
 
-async fn maybe_run_server(http_conf: HttpSettings, db: Arc<Database>, shutdown: watch::Receiver<bool>) -> () {
+async fn maybe_run_server(
+    http_conf: HttpSettings,
+    db: Arc<Database>,
+    notifier: Arc<Notifier>,
+    sync_status: Arc<SyncStatus>,
+    finality: Arc<Finality>,
+    raw_blocks: Arc<RawBlocks>,
+    btc_client: Arc<BtcClient>,
+    broadcast_settings: BroadcastSettings,
+    metrics_handle: PrometheusHandle,
+    pool: Arc<QueryPool>,
+    guard: Guard,
+    shutdown: watch::Receiver<bool>,
+) -> () {
     if http_conf.enable {
         info!("Starting http server at {}", http_conf.bind_address);
         let cors = cors::CorsLayer::new()
             .allow_origin(cors::Any) // or use a specific origin: `AllowOrigin::exact("http://localhost:5173".parse().unwrap())`
             .allow_methods(cors::Any)
-            .allow_headers(cors::Any);
-        serve(RequestState { db: Arc::clone(&db) }, http_conf.bind_address, None, Some(cors), shutdown).await
+            .allow_headers(cors::Any)
+            .expose_headers(cors::Any);
+        let extras = ws::routes(notifier)
+            .merge(status::routes(sync_status, Arc::clone(&pool)))
+            .merge(integrity::routes(Arc::clone(&pool)))
+            .merge(finality::routes(Arc::clone(&finality), Arc::clone(&pool)))
+            .merge(reorgs::routes(Arc::clone(&pool)))
+            .merge(op_return::routes(Arc::clone(&pool)))
+            .merge(pubkeys::routes(Arc::clone(&pool)))
+            .merge(filters::routes(Arc::clone(&pool)))
+            .merge(merkle::routes(Arc::clone(&pool)))
+            .merge(decode::routes(Arc::clone(&pool)))
+            .merge(search::routes(Arc::clone(&pool)))
+            .merge(raw_blocks::routes(raw_blocks, Arc::clone(&btc_client), Arc::clone(&pool)))
+            .merge(broadcast::routes(Arc::new(Mempool::default()), btc_client, broadcast_settings, Arc::clone(&pool)))
+            .merge(telemetry::routes(metrics_handle));
+        #[cfg(feature = "ordinals")]
+        let extras = extras.merge(ordinals::routes(Arc::clone(&pool)));
+        #[cfg(feature = "brc20")]
+        let extras = extras.merge(brc20::routes(Arc::clone(&pool)));
+        #[cfg(feature = "runes")]
+        let extras = extras.merge(runes::routes(Arc::clone(&pool)));
+        #[cfg(feature = "silent_payments")]
+        let extras = extras.merge(silent_payments::routes(Arc::clone(&pool)));
+        #[cfg(feature = "script_verification")]
+        let extras = extras.merge(script_verification::routes(Arc::clone(&pool)));
+        let app = server::app(RequestState { db: Arc::clone(&db) }, extras, pool, guard, finality).layer(cors);
+        server::serve(app, http_conf.bind_address, shutdown).await
     } else {
         ready(()).await
     }
@@ -43,20 +102,69 @@ async fn maybe_run_indexing(index_config: IndexerSettings, scheduler: Scheduler<
 async fn main() -> Result<()> {
     let app_config = AppConfig::new("config/settings")?;
     let btc_config = BitcoinConfig::new("config/bitcoin")?;
+    let explorer_settings = ExplorerSettings::new("config/settings")?;
     let db_path: String = format!("{}/{}/{}", app_config.indexer.db_path, "main", "btc");
     let full_db_path = env::home_dir().unwrap().join(&db_path);
-    let db = Arc::new(storage::get_db(full_db_path)?);
+    let db = Arc::new(storage::get_db(full_db_path.clone(), explorer_settings.schema.rebuild_outdated)?);
     let fetching_par: usize = app_config.indexer.fetching_parallelism.clone().into();
 
+    let metrics_handle = telemetry::install()?;
+    let pool = Arc::new(QueryPool::new(explorer_settings.api.query_threads, explorer_settings.api.query_queue));
+    let throttle = Arc::new(WriteThrottle::new(&explorer_settings.api));
+    let guard = Guard { timeout: Duration::from_millis(explorer_settings.api.request_timeout_ms), throttle: Arc::clone(&throttle) };
+    let notifier = Arc::new(Notifier::new());
+    let sync_status = Arc::new(SyncStatus::new(app_config.indexer.enable, app_config.http.enable, explorer_settings.status));
+    let finality = Arc::new(Finality::new(&explorer_settings.finality));
+    if let Some(tip) = BlockHeader::last(&db.begin_read()?)? {
+        finality.record_tip(tip.id.0);
+    }
+    let checkpoints = Arc::new(Checkpoints::new(btc_config.network, &explorer_settings.checkpoints)?);
     let btc_client = Arc::new(BtcClient::new(&btc_config)?);
-    let block_provider: Arc<dyn BlockProvider<BtcBlock, Block>> = Arc::new(BtcBlockProvider::new(btc_client, fetching_par)?);
-    let block_persistence: Arc<dyn BlockPersistence<Block>> = Arc::new(BtcBlockPersistence { db: Arc::clone(&db) });
+    let raw_blocks =
+        Arc::new(if explorer_settings.raw_blocks.cache { RawBlocks::new(full_db_path.join("raw_blocks"))? } else { RawBlocks::disabled() });
+    let block_provider: Arc<dyn BlockProvider<BtcBlock, Block>> = Arc::new(BtcBlockProvider::new(
+        Arc::clone(&btc_client),
+        fetching_par,
+        Arc::clone(&sync_status),
+        Arc::clone(&checkpoints),
+        Arc::clone(&raw_blocks),
+    )?);
+    let block_persistence: Arc<dyn BlockPersistence<Block>> =
+        Arc::new(BtcBlockPersistence {
+            db: Arc::clone(&db),
+            notifier: Arc::clone(&notifier),
+            status: Arc::clone(&sync_status),
+            throttle,
+            header_validator: HeaderValidator::new(btc_config.network),
+            checkpoints,
+            finality: Arc::clone(&finality),
+            raw_blocks: Arc::clone(&raw_blocks),
+            #[cfg(feature = "ordinals")]
+            ordinals: Ordinals::new(btc_config.network),
+            #[cfg(feature = "runes")]
+            runes: Runes::new(btc_config.network),
+            #[cfg(feature = "script_verification")]
+            script_verifier: ScriptVerifier::new(btc_config.network),
+        });
     let scheduler: Scheduler<BtcBlock, Block> = Scheduler::new(block_provider, block_persistence);
 
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     let indexing_f = maybe_run_indexing(app_config.indexer, scheduler, shutdown_rx.clone());
-    let server_f = maybe_run_server(app_config.http, Arc::clone(&db), shutdown_rx.clone());
+    let server_f = maybe_run_server(
+        app_config.http,
+        Arc::clone(&db),
+        notifier,
+        sync_status,
+        finality,
+        raw_blocks,
+        btc_client,
+        explorer_settings.broadcast,
+        metrics_handle,
+        pool,
+        guard,
+        shutdown_rx.clone(),
+    );
     combine::futures(indexing_f, server_f, shutdown_tx).await;
     Ok(())
 }
@@ -0,0 +1,247 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec;
+use crate::model::{BlockHeader, BlockPointer, Height, MerkleRoot, Transaction, TxHash};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::hex::DisplayHex;
+use bitcoin::{MerkleBlock, Txid};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::Serialize;
+use std::sync::Arc;
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(left);
+    engine.input(right);
+    sha256d::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Bitcoin merkle root of txids in internal byte order, odd levels duplicate their last node (hence CVE-2012-2459)
+pub fn merkle_root(tx_hashes: &[TxHash]) -> Option<MerkleRoot> {
+    if tx_hashes.is_empty() {
+        return None;
+    }
+    let mut level: Vec<[u8; 32]> = tx_hashes.iter().map(|h| h.0).collect();
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0]))).collect();
+    }
+    Some(MerkleRoot(level[0]))
+}
+
+/// Siblings on the path from the tx at `position` up to the root, like Electrum's `blockchain.transaction.get_merkle`
+pub fn merkle_branch(tx_hashes: &[TxHash], position: usize) -> Vec<[u8; 32]> {
+    let mut level: Vec<[u8; 32]> = tx_hashes.iter().map(|h| h.0).collect();
+    let mut index = position;
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        branch.push(*level.get(index ^ 1).unwrap_or(&level[index]));
+        level = level.chunks(2).map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0]))).collect();
+        index /= 2;
+    }
+    branch
+}
+
+/// Root that a branch of the tx at `position` commits to
+pub fn branch_root(tx_hash: &TxHash, branch: &[[u8; 32]], position: usize) -> MerkleRoot {
+    let root = branch.iter().enumerate().fold(tx_hash.0, |node, (depth, sibling)| {
+        if (position >> depth) & 1 == 0 { hash_pair(&node, sibling) } else { hash_pair(sibling, &node) }
+    });
+    MerkleRoot(root)
+}
+
+/// Header and partial merkle tree matching only the tx at `position`, serialized like `gettxoutproof` does
+pub fn txout_proof(header: &BlockHeader, tx_hashes: &[TxHash], position: usize) -> Vec<u8> {
+    let txids: Vec<Txid> = tx_hashes.iter().map(|h| Txid::from_byte_array(h.0)).collect();
+    let merkle_block = MerkleBlock::from_header_txids_with_predicate(&header.to_raw(), &txids, |txid| *txid == txids[position]);
+    bitcoin::consensus::serialize(&merkle_block)
+}
+
+/// Hex of a hash in the reversed byte order that bitcoind and Electrum display
+fn display_hex(hash: &[u8; 32]) -> String {
+    let mut reversed = *hash;
+    reversed.reverse();
+    reversed.to_lower_hex_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct MerkleProofView {
+    /// in display byte order like `merkle`
+    pub block_hash: String,
+    pub block_height: u32,
+    pub pos: usize,
+    /// branch in display byte order, as `blockchain.transaction.get_merkle` returns it
+    pub merkle: Vec<String>,
+    /// hex of the serialized header and partial merkle tree, as `gettxoutproof` returns it
+    pub proof: String,
+}
+
+/// Hashes of the transactions of a block in block order, read one transaction at a time instead of the whole block with its filter
+fn block_tx_hashes(read_tx: &ReadTransaction, height: u32) -> Result<Vec<TxHash>, AppError> {
+    let mut tx_hashes = Vec::new();
+    while let Some(tx) = Transaction::get(read_tx, &BlockPointer::from_parent(Height(height), tx_hashes.len() as u16))? {
+        tx_hashes.push(tx.hash);
+    }
+    Ok(tx_hashes)
+}
+
+/// Proof of the tx of `txid` given in display byte order like Electrum does or in stored byte order
+pub fn merkle_proof(read_tx: &ReadTransaction, txid: &[u8; 32]) -> Result<Option<MerkleProofView>, AppError> {
+    let Some(pointer) = Transaction::find_pointer_in_any_order(read_tx, txid)? else {
+        return Ok(None);
+    };
+    let Some(header) = BlockHeader::get(read_tx, &pointer.parent)? else {
+        return Ok(None);
+    };
+    let tx_hashes = block_tx_hashes(read_tx, header.id.0)?;
+    Ok(Some(proof_view(&header, &tx_hashes, pointer.index as usize)))
+}
+
+fn proof_view(header: &BlockHeader, tx_hashes: &[TxHash], pos: usize) -> MerkleProofView {
+    MerkleProofView {
+        block_hash: display_hex(&header.hash.0),
+        block_height: header.id.0,
+        pos,
+        merkle: merkle_branch(tx_hashes, pos).iter().map(display_hex).collect(),
+        proof: txout_proof(header, tx_hashes, pos).to_lower_hex_string(),
+    }
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/tx/{txid}/merkle-proof", get(proof)).layer(Extension(pool))
+}
+
+async fn proof(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(txid): Path<String>) -> Response {
+    let tx_hash = match codec::parse_hash(&txid) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    match pool.run(move || merkle_proof(&state.db.begin_read()?, &tx_hash)).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Transaction {} not found", txid)),
+        result => json_response(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, BlockTimestamp, BlockVersion, Bits, Height, Nonce};
+    use std::str::FromStr;
+
+    fn tx_hash(txid: &str) -> TxHash {
+        TxHash(*bitcoin::Txid::from_str(txid).unwrap().as_ref())
+    }
+
+    fn root(merkle_root: &str) -> MerkleRoot {
+        MerkleRoot(*bitcoin::TxMerkleNode::from_str(merkle_root).unwrap().as_ref())
+    }
+
+    #[test]
+    fn test_genesis_merkle_root() {
+        let coinbase = tx_hash("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        assert_eq!(merkle_root(&[coinbase]), Some(root("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b")));
+    }
+
+    #[test]
+    fn test_block_100000_merkle_root() {
+        let txs = [
+            tx_hash("8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87"),
+            tx_hash("fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4"),
+            tx_hash("6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4"),
+            tx_hash("e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d"),
+        ];
+        assert_eq!(merkle_root(&txs), Some(root("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766")));
+        assert_ne!(merkle_root(&[txs[1].clone(), txs[0].clone(), txs[2].clone(), txs[3].clone()]), merkle_root(&txs));
+    }
+
+    #[test]
+    fn test_odd_level_duplicates_last() {
+        let txs = [tx_hash(&"11".repeat(32)), tx_hash(&"22".repeat(32)), tx_hash(&"33".repeat(32))];
+        let padded = [txs[0].clone(), txs[1].clone(), txs[2].clone(), txs[2].clone()];
+        assert_eq!(merkle_root(&txs), merkle_root(&padded));
+        assert_eq!(merkle_root(&[]), None);
+    }
+
+    #[test]
+    fn test_merkle_branch() {
+        let txs: Vec<TxHash> = (1..=5u8).map(|i| TxHash([i; 32])).collect();
+        let root = merkle_root(&txs).unwrap();
+        for position in 0..txs.len() {
+            let branch = merkle_branch(&txs, position);
+            assert_eq!(branch.len(), 3);
+            assert_eq!(branch_root(&txs[position], &branch, position), root);
+        }
+        assert!(merkle_branch(&txs[..1], 0).is_empty());
+    }
+
+    #[test]
+    fn test_display_order_branch() {
+        let txs = [
+            tx_hash("8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87"),
+            tx_hash("fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4"),
+        ];
+        // electrum clients get siblings the way txids are displayed
+        let branch: Vec<String> = merkle_branch(&txs, 0).iter().map(display_hex).collect();
+        assert_eq!(branch, vec!["fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4"]);
+        assert_eq!(display_hex(&merkle_root(&txs).unwrap().0), bitcoin::TxMerkleNode::from_byte_array(merkle_root(&txs).unwrap().0).to_string());
+    }
+
+    #[test]
+    fn test_proof_view_of_block_100000() {
+        let txs = [
+            tx_hash("8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87"),
+            tx_hash("fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4"),
+            tx_hash("6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4"),
+            tx_hash("e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d"),
+        ];
+        let block_hash = "000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506";
+        let stored = |hash: &str| BlockHash(bitcoin::BlockHash::from_str(hash).unwrap().to_byte_array());
+        let header = BlockHeader {
+            id: Height(100000),
+            hash: stored(block_hash),
+            prev_hash: stored("000000000002d01c1fccc21636b607dfd930d31d01c3a62104612a1719011250"),
+            timestamp: BlockTimestamp(1293623863),
+            merkle_root: merkle_root(&txs).unwrap(),
+            bits: Bits(0x1b04864c),
+            version: BlockVersion(1),
+            nonce: Nonce(274148111),
+        };
+        assert_eq!(header.to_raw().block_hash().to_byte_array(), header.hash.0);
+        let view = proof_view(&header, &txs, 1);
+        // block hash is displayed like the branch, as bitcoind and Electrum show it
+        assert_eq!(view.block_hash, block_hash);
+        assert_eq!(view.block_height, 100000);
+        assert_eq!(view.merkle[0], "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87");
+        assert!(view.proof.starts_with(&bitcoin::consensus::encode::serialize_hex(&header.to_raw())));
+    }
+
+    #[test]
+    fn test_txout_proof() {
+        let txs = [
+            tx_hash("8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87"),
+            tx_hash("fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4"),
+            tx_hash("6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4"),
+        ];
+        let header = BlockHeader {
+            id: Height(100000),
+            hash: BlockHash([0; 32]),
+            prev_hash: BlockHash([0; 32]),
+            timestamp: BlockTimestamp(1293623863),
+            merkle_root: merkle_root(&txs).unwrap(),
+            bits: Bits(0x1b04864c),
+            version: BlockVersion(1),
+            nonce: Nonce(274148111),
+        };
+        let proof: MerkleBlock = bitcoin::consensus::deserialize(&txout_proof(&header, &txs, 2)).unwrap();
+        assert_eq!(proof.header, header.to_raw());
+        let (mut matches, mut indexes) = (Vec::new(), Vec::new());
+        let root = proof.txn.extract_matches(&mut matches, &mut indexes).unwrap();
+        assert_eq!(root.to_byte_array(), header.merkle_root.0);
+        assert_eq!(matches, vec![Txid::from_byte_array(txs[2].0)]);
+        assert_eq!(indexes, vec![2]);
+    }
+}
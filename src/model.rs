@@ -1,4 +1,6 @@
 use bitcoin::block::Bip34Error;
+use bitcoin::hashes::Hash as _;
+use bitcoin::{Script, ScriptBuf};
 use chain_syncer::api::{BlockHeaderLike, BlockLike, ChainSyncError};
 use chrono::DateTime;
 pub use redbit::*;
@@ -10,21 +12,216 @@ use std::fmt;
 #[pointer_key(u16)] pub struct TransactionPointer(BlockPointer);
 #[pointer_key(u8)] pub struct UtxoPointer(TransactionPointer);
 
+#[root_key] pub struct ReorgId(pub u32);
+#[pointer_key(u16)] pub struct StaleBlockPointer(ReorgId);
+#[pointer_key(u16)] pub struct StaleTxPointer(StaleBlockPointer);
+
+#[root_key] pub struct EventId(pub u32);
+
+#[cfg(feature = "ordinals")]
+#[root_key] pub struct InscriptionSequence(pub u32);
+#[cfg(feature = "ordinals")]
+#[root_key] pub struct TransferId(pub u32);
+
+#[cfg(feature = "brc20")]
+#[root_key] pub struct Brc20TokenId(pub u32);
+#[cfg(feature = "brc20")]
+#[root_key] pub struct Brc20EventId(pub u32);
+#[cfg(feature = "brc20")]
+#[root_key] pub struct Brc20BalanceId(pub u32);
+#[cfg(feature = "brc20")]
+#[root_key] pub struct Brc20SupplyId(pub u32);
+
+#[cfg(feature = "runes")]
+#[root_key] pub struct RuneNumber(pub u32);
+#[cfg(feature = "runes")]
+#[root_key] pub struct RuneBalanceId(pub u32);
+#[cfg(feature = "runes")]
+#[root_key] pub struct RuneSpendId(pub u32);
+#[cfg(feature = "runes")]
+#[root_key] pub struct RuneMintId(pub u32);
+#[cfg(feature = "runes")]
+#[root_key] pub struct RuneHoldingId(pub u32);
+
+#[cfg(feature = "silent_payments")]
+#[root_key] pub struct SpTweakId(pub u32);
+
 #[column] pub struct Hash(pub String);
 #[column("hex")] pub struct BlockHash(pub [u8; 32]);
 #[column("hex")] pub struct MerkleRoot(pub [u8; 32]);
 #[column("hex")] pub struct TxHash(pub [u8; 32]);
-#[column("hex")] pub struct ScriptHash(pub Vec<u8>);
+#[column("hex")] pub struct OpReturnData(pub Vec<u8>);
+#[column("hex")] pub struct Pubkey(pub Vec<u8>);
+#[column("hex")] pub struct PubkeyHash(pub [u8; 20]);
+#[column("hex")] pub struct GcsFilter(pub Vec<u8>);
+#[column("hex")] pub struct FilterHeaderHash(pub [u8; 32]);
 
 #[column("crate::codec::BaseOrBech")]
 pub struct Address(pub Vec<u8>);
 
+/// Output script, stored compressed
+#[column("crate::codec::ScriptCompression")]
+pub struct ScriptHash(pub Vec<u8>);
+
+impl ScriptHash {
+    pub fn from_script(script: &Script) -> Self {
+        ScriptHash(crate::codec::compress_script(script))
+    }
+
+    pub fn to_script(&self) -> ScriptBuf {
+        crate::codec::decompress_script(&self.0)
+    }
+}
+
 #[column]
 pub struct TempInputRef {
     pub tx_hash: TxHash,
     pub index: u32,
 }
 
+#[column]
+pub struct OutPoint {
+    pub tx_hash: TxHash,
+    pub vout: u32,
+}
+
+/// Inscription envelope of a taproot script-path spend, `pointer` is little endian as in the envelope, empty if absent.
+/// `cursed` if the envelope itself is, by a malformed field, pushnum opcode or stutter.
+#[column]
+pub struct Envelope {
+    pub input: u32,
+    pub content_type: Vec<u8>,
+    pub content_encoding: Vec<u8>,
+    pub pointer: Vec<u8>,
+    pub body: Vec<u8>,
+    pub cursed: bool,
+}
+
+/// Small data push of a taproot leaf script, candidate for a rune name commitment
+#[column]
+pub struct TapscriptPush {
+    pub input: u32,
+    pub data: Vec<u8>,
+}
+
+/// What silent payments need of an input before its prevout is known, `witness_key` is the last witness item
+/// if it is a compressed key, empty otherwise
+#[column]
+pub struct SpInput {
+    pub script_sig: Vec<u8>,
+    pub witness_key: Vec<u8>,
+    /// taproot script path spend with the unspendable NUMS internal key, such inputs are not eligible
+    pub nums_script_path: bool,
+}
+
+#[cfg(feature = "silent_payments")]
+#[column("hex")] pub struct TweakPoint(pub Vec<u8>);
+
+#[cfg(feature = "runes")]
+#[column]
+#[derive(Copy, Hash)]
+pub struct RuneKey {
+    pub block: u32,
+    pub tx: u32,
+}
+
+#[cfg(feature = "runes")]
+#[column]
+#[derive(Copy, Hash)]
+pub struct RuneName(pub u128);
+
+#[cfg(feature = "runes")]
+#[column]
+#[derive(Copy)]
+pub struct RuneAmount(pub u128);
+
+/// Rune holding of an address, outputs without an address are not aggregated
+#[cfg(feature = "runes")]
+#[column]
+#[derive(Hash)]
+pub struct RuneHolder {
+    pub rune: u32,
+    pub address: Vec<u8>,
+}
+
+#[cfg(feature = "runes")]
+#[column]
+#[derive(Copy)]
+pub struct RuneTerms {
+    pub amount: Option<u128>,
+    pub cap: Option<u128>,
+    pub height_start: Option<u64>,
+    pub height_end: Option<u64>,
+    pub offset_start: Option<u64>,
+    pub offset_end: Option<u64>,
+}
+
+/// Etching parameters, all defaults for runes etched by a cenotaph
+#[cfg(feature = "runes")]
+#[column]
+#[derive(Copy)]
+pub struct RuneEtching {
+    pub divisibility: u8,
+    pub premine: u128,
+    pub spacers: u32,
+    pub symbol: Option<char>,
+    pub terms: Option<RuneTerms>,
+    pub turbo: bool,
+}
+
+/// Lowercase ticker, BRC-20 ticks are case insensitive
+#[cfg(feature = "brc20")]
+#[column]
+#[derive(Hash)]
+pub struct Tick(pub String);
+
+/// BRC-20 amount scaled to 18 decimals regardless of token decimals
+#[cfg(feature = "brc20")]
+#[column]
+#[derive(Copy)]
+pub struct Brc20Amount(pub u128);
+
+/// Token balance holder identified by its output script as addresses do not cover all scripts
+#[cfg(feature = "brc20")]
+#[column]
+#[derive(Hash)]
+pub struct Brc20Holder {
+    pub token: u32,
+    pub script: Vec<u8>,
+}
+
+#[cfg(feature = "brc20")]
+#[column]
+#[derive(Copy, Hash)]
+pub struct Brc20Op(pub u8);
+#[cfg(feature = "brc20")]
+impl Brc20Op {
+    pub const DEPLOY: Brc20Op = Brc20Op(0);
+    pub const MINT: Brc20Op = Brc20Op(1);
+    // transfer inscription moving available balance into transferable one
+    pub const INSCRIBE_TRANSFER: Brc20Op = Brc20Op(2);
+    // first move of a transfer inscription, transferable balance goes to receiver
+    pub const TRANSFER: Brc20Op = Brc20Op(3);
+
+    pub fn name(&self) -> &'static str {
+        match self.0 {
+            0 => "deploy",
+            1 => "mint",
+            2 => "inscribe_transfer",
+            _ => "transfer",
+        }
+    }
+}
+
+#[column("hex")] pub struct ContentType(pub Vec<u8>);
+#[column("hex")] pub struct ContentEncoding(pub Vec<u8>);
+#[column("hex")] pub struct Content(pub Vec<u8>);
+
+/// `ord`'s inscription number, cursed inscriptions are numbered downwards from -1
+#[column]
+#[derive(Copy, Hash)]
+pub struct InscriptionNumber(pub i32);
+
 #[column]
 #[derive(Copy, Hash)]
 pub struct BlockTimestamp(pub u32);
@@ -36,14 +233,179 @@ impl fmt::Display for BlockTimestamp {
     }
 }
 
+#[column]
+#[derive(Copy, Hash)]
+pub struct Bits(pub u32);
+
+#[column]
+#[derive(Copy, Hash)]
+pub struct BlockVersion(pub i32);
+
+#[column]
+#[derive(Copy, Hash)]
+pub struct Nonce(pub u32);
+
+#[column]
+#[derive(Copy, Hash)]
+pub struct EventKind(pub u8);
+impl EventKind {
+    pub const CONNECTED: EventKind = EventKind(0);
+    pub const DISCONNECTED: EventKind = EventKind(1);
+    // the same block events repeated once the block is at or below finality depth
+    pub const CONFIRMED: EventKind = EventKind(2);
+    pub const UNCONFIRMED: EventKind = EventKind(3);
+
+    pub fn is_confirmed(&self) -> bool {
+        *self == EventKind::CONFIRMED || *self == EventKind::UNCONFIRMED
+    }
+
+    pub fn name(&self) -> &'static str {
+        if *self == EventKind::CONNECTED || *self == EventKind::CONFIRMED { "block_connected" } else { "block_disconnected" }
+    }
+}
+
+/// Type of an output script, anything unrecognized is non-standard
+#[column]
+#[derive(Copy, Hash)]
+pub struct ScriptType(pub u8);
+impl ScriptType {
+    pub const NON_STANDARD: ScriptType = ScriptType(0);
+    pub const P2PK: ScriptType = ScriptType(1);
+    pub const P2PKH: ScriptType = ScriptType(2);
+    pub const P2SH: ScriptType = ScriptType(3);
+    pub const P2WPKH: ScriptType = ScriptType(4);
+    pub const P2WSH: ScriptType = ScriptType(5);
+    pub const P2TR: ScriptType = ScriptType(6);
+    // pay-to-anchor, keyless output for fee bumping by CPFP
+    pub const P2A: ScriptType = ScriptType(7);
+    pub const MULTISIG: ScriptType = ScriptType(8);
+    pub const OP_RETURN: ScriptType = ScriptType(9);
+    pub const ALL: [ScriptType; 10] = [
+        ScriptType::NON_STANDARD,
+        ScriptType::P2PK,
+        ScriptType::P2PKH,
+        ScriptType::P2SH,
+        ScriptType::P2WPKH,
+        ScriptType::P2WSH,
+        ScriptType::P2TR,
+        ScriptType::P2A,
+        ScriptType::MULTISIG,
+        ScriptType::OP_RETURN,
+    ];
+
+    pub fn of(script: &bitcoin::Script) -> ScriptType {
+        if script.is_p2pkh() {
+            ScriptType::P2PKH
+        } else if script.is_p2wpkh() {
+            ScriptType::P2WPKH
+        } else if script.is_p2tr() {
+            ScriptType::P2TR
+        } else if script.is_p2sh() {
+            ScriptType::P2SH
+        } else if script.is_p2wsh() {
+            ScriptType::P2WSH
+        } else if script.is_op_return() {
+            ScriptType::OP_RETURN
+        } else if script.as_bytes() == [0x51, 0x02, 0x4e, 0x73] {
+            ScriptType::P2A
+        } else if script.is_p2pk() {
+            ScriptType::P2PK
+        } else if script.is_multisig() {
+            ScriptType::MULTISIG
+        } else {
+            ScriptType::NON_STANDARD
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.0 {
+            1 => "p2pk",
+            2 => "p2pkh",
+            3 => "p2sh",
+            4 => "p2wpkh",
+            5 => "p2wsh",
+            6 => "p2tr",
+            7 => "p2a",
+            8 => "multisig",
+            9 => "op_return",
+            _ => "nonstandard",
+        }
+    }
+}
+
+/// Protocol of an OP_RETURN payload recognized by its prefix
+#[column]
+#[derive(Copy, Hash)]
+pub struct Protocol(pub u8);
+impl Protocol {
+    pub const UNKNOWN: Protocol = Protocol(0);
+    pub const OMNI: Protocol = Protocol(1);
+    pub const COUNTERPARTY: Protocol = Protocol(2);
+    pub const RUNES: Protocol = Protocol(3);
+    // a lone 32 byte hash, like OpenTimestamps calendar commitments and any other bare hash commitment
+    pub const HASH32: Protocol = Protocol(4);
+    pub const STACKS: Protocol = Protocol(5);
+    pub const OPEN_ASSETS: Protocol = Protocol(6);
+    pub const PROOF_OF_EXISTENCE: Protocol = Protocol(7);
+    pub const ALL: [Protocol; 8] = [
+        Protocol::UNKNOWN,
+        Protocol::OMNI,
+        Protocol::COUNTERPARTY,
+        Protocol::RUNES,
+        Protocol::HASH32,
+        Protocol::STACKS,
+        Protocol::OPEN_ASSETS,
+        Protocol::PROOF_OF_EXISTENCE,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self.0 {
+            1 => "omni",
+            2 => "counterparty",
+            3 => "runes",
+            4 => "hash32",
+            5 => "stacks",
+            6 => "openassets",
+            7 => "proofofexistence",
+            _ => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Protocol> {
+        Protocol::ALL.into_iter().find(|protocol| protocol.name() == name)
+    }
+}
+
+/// Protocol in the high and height in the low 32 bits, so that a range of it covers heights of one protocol
+#[column]
+#[derive(Copy, Hash)]
+pub struct OpReturnKey(pub u64);
+impl OpReturnKey {
+    pub fn new(protocol: Protocol, height: u32) -> Self {
+        OpReturnKey((protocol.0 as u64) << 32 | height as u64)
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        Protocol((self.0 >> 32) as u8)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.0 as u32
+    }
+}
+
 #[entity]
 pub struct Block {
     #[pk]
     pub id: Height,
     pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
+    pub filter: BlockFilter,
     #[column(transient)]
     pub weight: u32,
+    /// serialized block for the raw block cache, empty unless it is enabled
+    #[column(transient)]
+    pub raw: Vec<u8>,
 }
 
 #[entity]
@@ -58,6 +420,23 @@ pub struct BlockHeader {
     pub timestamp: BlockTimestamp,
     #[column(index)]
     pub merkle_root: MerkleRoot,
+    #[column]
+    pub bits: Bits,
+    #[column]
+    pub version: BlockVersion,
+    #[column]
+    pub nonce: Nonce,
+}
+
+/// BIP158 basic filter of the block and its header chained over all previous filters
+#[entity]
+pub struct BlockFilter {
+    #[fk(one2one)]
+    pub id: Height,
+    #[column]
+    pub filter: GcsFilter,
+    #[column]
+    pub header: FilterHeaderHash,
 }
 
 #[entity]
@@ -70,6 +449,16 @@ pub struct Transaction {
     pub inputs: Vec<InputRef>,
     #[column(transient)]
     pub transient_inputs: Vec<TempInputRef>,
+    #[column(transient)]
+    pub envelopes: Vec<Envelope>,
+    #[column(transient)]
+    pub tapscript_pushes: Vec<TapscriptPush>,
+    #[column(transient)]
+    pub sp_inputs: Vec<SpInput>,
+    /// serialized transaction for script verification, empty unless it is enabled
+    #[column(transient)]
+    pub raw: Vec<u8>,
+    pub op_returns: Vec<OpReturn>,
 }
 
 #[entity]
@@ -80,16 +469,324 @@ pub struct Utxo {
     pub amount: u64,
     #[column(dictionary)]
     pub script_hash: ScriptHash,
+    pub address: Option<UtxoAddress>,
+    #[column]
+    pub script_type: ScriptType,
+    pub pubkeys: Vec<UtxoPubkey>,
+}
+
+/// Address of an output, outputs without one (bare multisig, OP_RETURN, non-standard) have none
+/// instead of all sharing a single dictionary entry
+#[entity]
+pub struct UtxoAddress {
+    #[fk(one2opt)]
+    pub id: TransactionPointer,
     #[column(dictionary)]
     pub address: Address,
 }
 
+/// Public key of a P2PK or bare multisig output, with its hash160 so that the P2PKH address of the key finds the output too
+#[entity]
+pub struct UtxoPubkey {
+    #[fk(one2many)]
+    pub id: UtxoPointer,
+    #[column(index)]
+    pub pubkey: Pubkey,
+    #[column(index)]
+    pub pubkey_hash: PubkeyHash,
+}
+
+/// Data pushed by an OP_RETURN output, its pointer is the one of the output
+#[entity]
+pub struct OpReturn {
+    #[fk(one2many)]
+    pub id: TransactionPointer,
+    #[column(range)]
+    pub key: OpReturnKey,
+    #[column]
+    pub data: OpReturnData,
+}
+
 #[entity]
 pub struct InputRef {
     #[fk(one2many)]
     pub id: TransactionPointer,
 }
 
+/// Inscription keyed by its sequence in order of discovery, `genesis_index` is the envelope index within its reveal transaction
+#[cfg(feature = "ordinals")]
+#[entity]
+pub struct Inscription {
+    #[pk]
+    pub id: InscriptionSequence,
+    #[column(index)]
+    pub number: InscriptionNumber,
+    #[column(index)]
+    pub genesis_tx: TxHash,
+    #[column]
+    pub genesis_index: u32,
+    #[column(index)]
+    pub height: u32,
+    #[column(dictionary)]
+    pub content_type: ContentType,
+    #[column(dictionary)]
+    pub content_encoding: ContentEncoding,
+    #[column]
+    pub content: Content,
+    /// cursed by `ord`'s rules, which apply only before the jubilee height
+    #[column]
+    pub cursed: bool,
+}
+
+/// Inscription of `inscription` sequence landing on `offset` sat of `outpoint`, the latest transfer of an inscription is its current location
+#[cfg(feature = "ordinals")]
+#[entity]
+pub struct InscriptionTransfer {
+    #[pk]
+    pub id: TransferId,
+    #[column(index)]
+    pub inscription: u32,
+    #[column(index)]
+    pub height: u32,
+    #[column(index)]
+    pub outpoint: OutPoint,
+    #[column]
+    pub offset: u64,
+}
+
+#[cfg(feature = "brc20")]
+#[entity]
+pub struct Brc20Token {
+    #[pk]
+    pub id: Brc20TokenId,
+    #[column(index)]
+    pub tick: Tick,
+    #[column]
+    pub max: Brc20Amount,
+    #[column]
+    pub lim: Brc20Amount,
+    #[column]
+    pub dec: u8,
+    #[column(index)]
+    pub inscription: u32,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// Valid BRC-20 operation, invalid ones are not stored
+#[cfg(feature = "brc20")]
+#[entity]
+pub struct Brc20Event {
+    #[pk]
+    pub id: Brc20EventId,
+    #[column(index)]
+    pub token: u32,
+    #[column(index)]
+    pub op: Brc20Op,
+    #[column(index)]
+    pub inscription: u32,
+    #[column(dictionary)]
+    pub from: ScriptHash,
+    #[column(dictionary)]
+    pub to: ScriptHash,
+    #[column]
+    pub amount: Brc20Amount,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// Balance snapshot of a holder, the latest one is current and a reorg deleting it makes the previous one current again
+#[cfg(feature = "brc20")]
+#[entity]
+pub struct Brc20Balance {
+    #[pk]
+    pub id: Brc20BalanceId,
+    #[column(index)]
+    pub holder: Brc20Holder,
+    #[column(index)]
+    pub token: u32,
+    #[column(dictionary)]
+    pub script: ScriptHash,
+    #[column]
+    pub available: Brc20Amount,
+    #[column]
+    pub transferable: Brc20Amount,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// Minted supply snapshot of a token, the latest one is current
+#[cfg(feature = "brc20")]
+#[entity]
+pub struct Brc20Supply {
+    #[pk]
+    pub id: Brc20SupplyId,
+    #[column(index)]
+    pub token: u32,
+    #[column]
+    pub minted: Brc20Amount,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// Rune numbered in order of etching, `mints` is updated in place and recounted from `RuneMint`s on reorg
+#[cfg(feature = "runes")]
+#[entity]
+pub struct Rune {
+    #[pk]
+    pub id: RuneNumber,
+    #[column(index)]
+    pub rune_id: RuneKey,
+    #[column(index)]
+    pub name: RuneName,
+    #[column]
+    pub etching: RuneEtching,
+    #[column(index)]
+    pub etching_tx: TxHash,
+    #[column(index)]
+    pub height: u32,
+    #[column]
+    pub mints: RuneAmount,
+}
+
+/// Rune balance of an output, it is unspent as long as there is no `RuneSpend` of its `outpoint`
+#[cfg(feature = "runes")]
+#[entity]
+pub struct RuneBalance {
+    #[pk]
+    pub id: RuneBalanceId,
+    #[column(index)]
+    pub outpoint: OutPoint,
+    #[column(index)]
+    pub rune: u32,
+    #[column]
+    pub amount: RuneAmount,
+    pub address: Option<RuneBalanceAddress>,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// Address of a rune balance output, outputs without one have none like `UtxoAddress`
+#[cfg(feature = "runes")]
+#[entity]
+pub struct RuneBalanceAddress {
+    #[fk(one2opt)]
+    pub id: RuneBalanceId,
+    #[column(dictionary)]
+    pub address: Address,
+}
+
+#[cfg(feature = "runes")]
+#[entity]
+pub struct RuneSpend {
+    #[pk]
+    pub id: RuneSpendId,
+    #[column(index)]
+    pub outpoint: OutPoint,
+    #[column(index)]
+    pub height: u32,
+}
+
+#[cfg(feature = "runes")]
+#[entity]
+pub struct RuneMint {
+    #[pk]
+    pub id: RuneMintId,
+    #[column(index)]
+    pub rune: u32,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// Unspent amount of a rune held by an address, the latest snapshot is current and a reorg deleting it makes the previous one current again
+#[cfg(feature = "runes")]
+#[entity]
+pub struct RuneHolding {
+    #[pk]
+    pub id: RuneHoldingId,
+    #[column(index)]
+    pub holder: RuneHolder,
+    #[column(index)]
+    pub rune: u32,
+    #[column(dictionary)]
+    pub address: Address,
+    #[column]
+    pub amount: RuneAmount,
+    #[column(index)]
+    pub height: u32,
+}
+
+/// BIP352 tweak of a transaction eligible for silent payments, the sum of its input keys times its input hash
+#[cfg(feature = "silent_payments")]
+#[entity]
+pub struct SpTweak {
+    #[pk]
+    pub id: SpTweakId,
+    #[column(index)]
+    pub height: u32,
+    #[column(index)]
+    pub tx_hash: TxHash,
+    #[column]
+    pub tweak: TweakPoint,
+}
+
+/// Reorg log entry, blocks superseded by a fork are archived under it instead of being forgotten
+#[entity]
+pub struct Reorg {
+    #[pk]
+    pub id: ReorgId,
+    #[column(range)]
+    pub timestamp: BlockTimestamp,
+    #[column]
+    pub fork_height: u32,
+    #[column]
+    pub depth: u32,
+    #[column(index)]
+    pub old_tip: BlockHash,
+    #[column(index)]
+    pub new_tip: BlockHash,
+    pub stale_blocks: Vec<StaleBlock>,
+}
+
+#[entity]
+pub struct StaleBlock {
+    #[fk(one2many)]
+    pub id: StaleBlockPointer,
+    #[column]
+    pub height: u32,
+    #[column(index)]
+    pub hash: BlockHash,
+    #[column]
+    pub prev_hash: BlockHash,
+    #[column]
+    pub timestamp: BlockTimestamp,
+    #[column]
+    pub merkle_root: MerkleRoot,
+    #[column]
+    pub bits: Bits,
+    pub txs: Vec<StaleTx>,
+}
+
+#[entity]
+pub struct StaleTx {
+    #[fk(one2many)]
+    pub id: StaleTxPointer,
+    #[column(index)]
+    pub hash: TxHash,
+}
+
+#[entity]
+pub struct FeedEvent {
+    #[pk]
+    pub id: EventId,
+    #[column(index)]
+    pub kind: EventKind,
+    #[column]
+    pub height: u32,
+    #[column(index)]
+    pub hash: BlockHash,
+}
+
 impl BlockHeaderLike for BlockHeader {
     fn height(&self) -> u32 {
         self.id.0
@@ -105,6 +802,47 @@ impl BlockHeaderLike for BlockHeader {
     }
 }
 
+impl BlockHeader {
+    /// Header of an indexed block hash, block hashes are unique
+    pub fn find_by_hash(read_tx: &redbit::redb::ReadTransaction, hash: &BlockHash) -> Result<Option<BlockHeader>, AppError> {
+        Ok(BlockHeader::get_by_hash(read_tx, hash)?.into_iter().next())
+    }
+
+    /// Header of a block hash given by a user, in either of `codec::byte_orders`
+    pub fn find_by_hash_in_any_order(read_tx: &redbit::redb::ReadTransaction, hash: &[u8; 32]) -> Result<Option<BlockHeader>, AppError> {
+        for hash in crate::codec::byte_orders(*hash) {
+            if let Some(header) = BlockHeader::find_by_hash(read_tx, &BlockHash(hash))? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The header as it is hashed and relayed
+    pub fn to_raw(&self) -> bitcoin::block::Header {
+        bitcoin::block::Header {
+            version: bitcoin::block::Version::from_consensus(self.version.0),
+            prev_blockhash: bitcoin::BlockHash::from_byte_array(self.prev_hash.0),
+            merkle_root: bitcoin::TxMerkleNode::from_byte_array(self.merkle_root.0),
+            time: self.timestamp.0,
+            bits: bitcoin::CompactTarget::from_consensus(self.bits.0),
+            nonce: self.nonce.0,
+        }
+    }
+}
+
+impl Transaction {
+    /// Position of a txid given by a user, in either of `codec::byte_orders`
+    pub fn find_pointer_in_any_order(read_tx: &redbit::redb::ReadTransaction, txid: &[u8; 32]) -> Result<Option<BlockPointer>, AppError> {
+        for txid in crate::codec::byte_orders(*txid) {
+            if let Some(pointer) = Transaction::get_ids_by_hash(read_tx, &TxHash(txid))?.into_iter().next() {
+                return Ok(Some(pointer));
+            }
+        }
+        Ok(None)
+    }
+}
+
 impl BlockLike for Block {
     type Header = BlockHeader;
     fn header(&self) -> &Self::Header {
@@ -122,6 +860,36 @@ pub enum ExplorerError {
 
     #[error("Height decoding error: {0}")]
     Bip34(#[from] Bip34Error),
+
+    #[error("Merkle root mismatch at height {height}: header has {expected}, txs hash to {computed}")]
+    MerkleRootMismatch { height: u32, expected: String, computed: String },
+
+    #[error("Block {hash} at height {height} does not meet its target {bits:#010x}")]
+    InvalidProofOfWork { height: u32, hash: String, bits: u32 },
+
+    #[error("Block at height {height} has difficulty bits {bits:#010x} but {expected:#010x} is required")]
+    UnexpectedDifficulty { height: u32, bits: u32, expected: u32 },
+
+    #[error("Block {hash} at height {height} does not match checkpoint {expected}")]
+    CheckpointMismatch { height: u32, hash: String, expected: String },
+
+    #[error("Refusing reorg replacing block at height {height} which is not above the last checkpoint {checkpoint}")]
+    ReorgBelowCheckpoint { height: u32, checkpoint: u32 },
+
+    #[error("Invalid checkpoint: {0}")]
+    InvalidCheckpoint(String),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] AppError),
+
+    #[error("Input {input} of tx {txid} at height {height} fails script verification: {error}")]
+    ScriptVerification { height: u32, txid: String, input: usize, error: String },
+
+    #[error("Raw block cache error: {0}")]
+    RawBlockCache(#[from] std::io::Error),
+
+    #[error("Block at height {height} links to parent {prev_hash} but stored parent is {parent_hash}")]
+    ParentMismatch { height: u32, prev_hash: String, parent_hash: String },
 }
 
 impl From<ExplorerError> for ChainSyncError {
@@ -129,3 +897,48 @@ impl From<ExplorerError> for ChainSyncError {
         ChainSyncError::new(&err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::script::Builder;
+    use bitcoin::{PubkeyHash, WPubkeyHash, WScriptHash};
+
+    // compressed form of the secp256k1 generator point
+    const KEY: [u8; 33] = [
+        0x02, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce,
+        0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+    ];
+
+    #[test]
+    fn test_script_type_of() {
+        let p2pk = Builder::new().push_slice(KEY).push_opcode(bitcoin::opcodes::all::OP_CHECKSIG).into_script();
+        let multisig =
+            Builder::new().push_int(1).push_slice(KEY).push_slice(KEY).push_int(2).push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG).into_script();
+        let mut p2tr = vec![0x51, 0x20];
+        p2tr.extend_from_slice(&KEY[1..]);
+        let cases = [
+            (p2pk, ScriptType::P2PK),
+            (ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros()), ScriptType::P2PKH),
+            (ScriptBuf::new_p2sh(&bitcoin::ScriptHash::all_zeros()), ScriptType::P2SH),
+            (ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()), ScriptType::P2WPKH),
+            (ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()), ScriptType::P2WSH),
+            (ScriptBuf::from_bytes(p2tr), ScriptType::P2TR),
+            (ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73]), ScriptType::P2A),
+            (multisig, ScriptType::MULTISIG),
+            (ScriptBuf::new_op_return([1u8; 4]), ScriptType::OP_RETURN),
+            // a witness v1 program other than the anchor one
+            (ScriptBuf::from_bytes(vec![0x51, 0x02, 0x00, 0x00]), ScriptType::NON_STANDARD),
+            (ScriptBuf::from_bytes(vec![0x51]), ScriptType::NON_STANDARD),
+            (ScriptBuf::new(), ScriptType::NON_STANDARD),
+        ];
+        for (script, expected) in &cases {
+            assert_eq!(ScriptType::of(script), *expected, "{}", script);
+        }
+        // every type is covered
+        for script_type in ScriptType::ALL {
+            assert!(cases.iter().any(|(_, expected)| *expected == script_type), "{}", script_type.name());
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::model::{Block, BlockHeader, Height, TxHash};
+use bitcoin::hex::DisplayHex;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockRef {
+    pub height: u32,
+    pub hash: String,
+}
+
+impl From<&BlockHeader> for BlockRef {
+    fn from(header: &BlockHeader) -> Self {
+        BlockRef { height: header.id.0, hash: header.hash.0.to_lower_hex_string() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectedBlock {
+    pub block: BlockRef,
+    pub timestamp: u32,
+    // in stored byte order
+    pub txids: Vec<[u8; 32]>,
+    // (address, txid) pairs of outputs created and outputs spent within the block
+    pub address_activity: BTreeSet<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    Connected(Arc<ConnectedBlock>),
+    RolledBack { fork_height: u32, rolled_back: Arc<Vec<BlockRef>> },
+}
+
+/// Fans out chain changes from `BtcBlockPersistence` to live subscribers (ie. websocket clients).
+pub struct Notifier {
+    sender: broadcast::Sender<ChainEvent>,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Notifier { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Building notifications is not for free, persistence skips it when nobody listens
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn block_connected(&self, block: &Block, spent_addresses: Vec<(Vec<u8>, TxHash)>) {
+        let mut txids = Vec::with_capacity(block.transactions.len());
+        let mut address_activity = BTreeSet::new();
+        for tx in &block.transactions {
+            let txid = tx.hash.0.to_lower_hex_string();
+            // outputs without an address (P2PK, bare multisig, OP_RETURN, non-standard) have no address activity
+            for address in tx.utxos.iter().filter_map(|utxo| utxo.address.as_ref()).filter(|address| !address.address.0.is_empty()) {
+                address_activity.insert((String::from_utf8_lossy(&address.address.0).into_owned(), txid.clone()));
+            }
+            txids.push(tx.hash.0);
+        }
+        for (address, tx_hash) in spent_addresses.into_iter().filter(|(address, _)| !address.is_empty()) {
+            address_activity.insert((String::from_utf8_lossy(&address).into_owned(), tx_hash.0.to_lower_hex_string()));
+        }
+        let connected = ConnectedBlock { block: BlockRef::from(&block.header), timestamp: block.header.timestamp.0, txids, address_activity };
+        // error only means there are no receivers at the moment
+        let _ = self.sender.send(ChainEvent::Connected(Arc::new(connected)));
+    }
+
+    pub fn blocks_rolled_back(&self, fork_height: Height, rolled_back: Vec<BlockRef>) {
+        if !rolled_back.is_empty() {
+            let _ = self.sender.send(ChainEvent::RolledBack { fork_height: fork_height.0, rolled_back: Arc::new(rolled_back) });
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::model::{BlockHeader, BlockPointer, OpReturn, OpReturnData, OpReturnKey, Protocol, Transaction, TransactionPointer, TxHash};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hex::DisplayHex;
+use bitcoin::opcodes::all::OP_PUSHNUM_13;
+use bitcoin::script::Instruction;
+use bitcoin::Script;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const MAX_LIMIT: usize = 100;
+// heights covered by one range query, so that a page of a rare protocol does not load a whole chain of a common one
+const SCAN_WINDOW: u64 = 10_000;
+const COUNTERPARTY_PREFIX: &[u8] = b"CNTRPRTY";
+
+/// Data pushes after OP_RETURN (and the runestone magic number), or raw bytes if the script is not push only
+fn payload(script: &Script) -> Vec<u8> {
+    let skip = if script.as_bytes().get(1) == Some(&OP_PUSHNUM_13.to_u8()) { 2 } else { 1 };
+    let mut data = Vec::new();
+    for instruction in Script::from_bytes(&script.as_bytes()[skip..]).instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(push)) => data.extend_from_slice(push.as_bytes()),
+            _ => return script.as_bytes()[1..].to_vec(),
+        }
+    }
+    data
+}
+
+/// RC4 stream cipher, Counterparty obfuscates its payloads with the first input's txid as key
+fn arc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+fn is_counterparty(data: &[u8], first_input: Option<&TxHash>) -> bool {
+    if data.starts_with(COUNTERPARTY_PREFIX) {
+        return true;
+    }
+    // key is the txid in its displayed byte order
+    first_input.is_some_and(|tx_hash| {
+        let key: Vec<u8> = tx_hash.0.iter().rev().copied().collect();
+        data.len() >= COUNTERPARTY_PREFIX.len() && arc4(&key, &data[..COUNTERPARTY_PREFIX.len()]) == COUNTERPARTY_PREFIX
+    })
+}
+
+/// Protocol and payload of an OP_RETURN script, `None` for other scripts.
+/// A lone 32 byte hash carries nothing to tell OpenTimestamps calendar commitments from other bare hash commitments.
+pub fn classify(script: &Script, first_input: Option<&TxHash>) -> Option<(Protocol, Vec<u8>)> {
+    if !script.is_op_return() {
+        return None;
+    }
+    let data = payload(script);
+    let protocol = if script.as_bytes().get(1) == Some(&OP_PUSHNUM_13.to_u8()) {
+        Protocol::RUNES
+    } else if data.starts_with(b"omni") {
+        Protocol::OMNI
+    } else if data.starts_with(b"DOCPROOF") {
+        Protocol::PROOF_OF_EXISTENCE
+    } else if data.starts_with(&[0x4f, 0x41, 0x01, 0x00]) {
+        Protocol::OPEN_ASSETS
+    } else if data.len() > 2 && data.starts_with(b"X2") {
+        Protocol::STACKS
+    } else if is_counterparty(&data, first_input) {
+        Protocol::COUNTERPARTY
+    } else if data.len() == 32 && script.len() == 34 {
+        Protocol::HASH32
+    } else {
+        Protocol::UNKNOWN
+    };
+    Some((protocol, data))
+}
+
+pub fn op_returns(tx: &bitcoin::Transaction, tx_pointer: &BlockPointer) -> Vec<OpReturn> {
+    let first_input = tx.input.first().filter(|_| !tx.is_coinbase()).map(|input| TxHash(*input.previous_output.txid.as_ref()));
+    tx.output
+        .iter()
+        .enumerate()
+        .filter_map(|(vout, output)| {
+            let (protocol, data) = classify(&output.script_pubkey, first_input.as_ref())?;
+            Some(OpReturn {
+                id: TransactionPointer::from_parent(tx_pointer.clone(), vout as u16),
+                key: OpReturnKey::new(protocol, tx_pointer.parent.0),
+                data: OpReturnData(data),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpReturnView {
+    pub tx_hash: String,
+    pub vout: u16,
+    pub height: u32,
+    pub protocol: &'static str,
+    pub data: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpReturnPage {
+    pub payloads: Vec<OpReturnView>,
+    /// `height:tx_index:vout` of the last payload, to be passed as `cursor` for the next page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub from_height: Option<u32>,
+    pub until_height: Option<u32>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Height, transaction index and output index
+pub type Position = (u32, u16, u16);
+
+fn position(pointer: &TransactionPointer) -> Position {
+    (pointer.parent.parent.0, pointer.parent.index, pointer.index)
+}
+
+fn parse_cursor(cursor: &str) -> Option<Position> {
+    let mut parts = cursor.split(':');
+    let position = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+    parts.next().is_none().then_some(position)
+}
+
+/// Payloads of `protocol` in height range `from_height..=until_height` in chain order, following `cursor` if given
+pub fn search(read_tx: &ReadTransaction, protocol: Protocol, params: &SearchParams, cursor: Option<Position>) -> Result<OpReturnPage, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let Some(tip) = BlockHeader::last(read_tx)?.map(|tip| tip.id.0) else {
+        return Ok(OpReturnPage { payloads: Vec::new(), next_cursor: None });
+    };
+    let from = cursor.map_or(0, |(height, _, _)| height).max(params.from_height.unwrap_or(0));
+    let until = params.until_height.unwrap_or(tip).min(tip);
+    let protocol_start = OpReturnKey::new(protocol, 0).0;
+    let until_key = protocol_start + until as u64 + 1;
+    let mut start_key = protocol_start + from as u64;
+    let mut found: Vec<OpReturn> = Vec::new();
+    while start_key < until_key && found.len() < limit {
+        let end_key = (start_key + SCAN_WINDOW).min(until_key);
+        let mut window = OpReturn::range_by_key(read_tx, &OpReturnKey(start_key), &OpReturnKey(end_key))?;
+        window.retain(|op_return| cursor.is_none_or(|cursor| position(&op_return.id) > cursor));
+        window.sort_by_key(|op_return| position(&op_return.id));
+        found.extend(window.into_iter().take(limit - found.len()));
+        start_key = end_key;
+    }
+    let next_cursor = found.last().filter(|_| found.len() == limit).map(|last| {
+        let (height, tx_index, vout) = position(&last.id);
+        format!("{}:{}:{}", height, tx_index, vout)
+    });
+    let mut payloads = Vec::with_capacity(found.len());
+    for op_return in found {
+        let Some(tx) = Transaction::get(read_tx, &op_return.id.parent)? else {
+            continue;
+        };
+        payloads.push(OpReturnView {
+            tx_hash: tx.hash.0.to_lower_hex_string(),
+            vout: op_return.id.index,
+            height: op_return.key.height(),
+            protocol: op_return.key.protocol().name(),
+            data: op_return.data.0.to_lower_hex_string(),
+        });
+    }
+    Ok(OpReturnPage { payloads, next_cursor })
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/op-returns/{protocol}", get(op_returns_of_protocol)).layer(Extension(pool))
+}
+
+/// Payloads of one protocol, `unknown` covers everything not recognized
+async fn op_returns_of_protocol(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(protocol): Path<String>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    let Some(protocol) = Protocol::from_name(&protocol) else {
+        let known: Vec<&str> = Protocol::ALL.iter().map(|protocol| protocol.name()).collect();
+        return error_response(StatusCode::BAD_REQUEST, format!("Unknown protocol {}, expected one of {}", protocol, known.join(", ")));
+    };
+    let cursor = match params.cursor.as_deref() {
+        Some(cursor) => match parse_cursor(cursor) {
+            Some(position) => Some(position),
+            None => return error_response(StatusCode::BAD_REQUEST, format!("Cursor {} is not height:tx_index:vout", cursor)),
+        },
+        None => None,
+    };
+    json_response(pool.run(move || search(&state.db.begin_read()?, protocol, &params, cursor)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::opcodes::all::OP_RETURN;
+    use bitcoin::script::{Builder, PushBytesBuf};
+
+    fn op_return(pushes: &[&[u8]]) -> bitcoin::ScriptBuf {
+        pushes.iter().fold(Builder::new().push_opcode(OP_RETURN), |builder, push| builder.push_slice(PushBytesBuf::try_from(push.to_vec()).unwrap())).into_script()
+    }
+
+    #[test]
+    fn test_arc4() {
+        assert_eq!(arc4(b"Key", b"Plaintext").to_lower_hex_string(), "bbf316e8d940af0ad3");
+        assert_eq!(arc4(b"Wiki", b"pedia").to_lower_hex_string(), "1021bf0420");
+    }
+
+    #[test]
+    fn test_classify() {
+        let protocol = |script: &bitcoin::ScriptBuf| classify(script, None).map(|(protocol, _)| protocol);
+        assert_eq!(protocol(&op_return(&[b"omni\x00\x00\x00\x00"])), Some(Protocol::OMNI));
+        assert_eq!(protocol(&op_return(&[b"DOCPROOF0123"])), Some(Protocol::PROOF_OF_EXISTENCE));
+        assert_eq!(protocol(&op_return(&[&[0x4f, 0x41, 0x01, 0x00, 0x01]])), Some(Protocol::OPEN_ASSETS));
+        assert_eq!(protocol(&op_return(&[b"X2[0123"])), Some(Protocol::STACKS));
+        assert_eq!(protocol(&op_return(&[&[7; 32]])), Some(Protocol::HASH32));
+        assert_eq!(Protocol::from_name("hash32"), Some(Protocol::HASH32));
+        assert_eq!(Protocol::from_name("opentimestamps"), None);
+        assert_eq!(protocol(&op_return(&[&[7; 32], b"x"])), Some(Protocol::UNKNOWN));
+        assert_eq!(protocol(&op_return(&[])), Some(Protocol::UNKNOWN));
+        assert_eq!(protocol(&bitcoin::ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros())), None);
+
+        let runestone = Builder::new().push_opcode(OP_RETURN).push_opcode(OP_PUSHNUM_13).push_slice([1, 2]).into_script();
+        assert_eq!(classify(&runestone, None), Some((Protocol::RUNES, vec![1, 2])));
+        // pushes are concatenated
+        assert_eq!(classify(&op_return(&[b"ab", b"cd"]), None), Some((Protocol::UNKNOWN, b"abcd".to_vec())));
+    }
+
+    #[test]
+    fn test_counterparty() {
+        let first_input = TxHash([9; 32]);
+        let key: Vec<u8> = first_input.0.iter().rev().copied().collect();
+        let encrypted = arc4(&key, b"CNTRPRTY\x00\x00\x00\x14");
+        let script = op_return(&[&encrypted]);
+        assert_eq!(classify(&script, Some(&first_input)).map(|(protocol, _)| protocol), Some(Protocol::COUNTERPARTY));
+        assert_eq!(classify(&script, Some(&TxHash([8; 32]))).map(|(protocol, _)| protocol), Some(Protocol::UNKNOWN));
+    }
+
+    #[test]
+    fn test_key_and_cursor() {
+        let key = OpReturnKey::new(Protocol::RUNES, 840000);
+        assert_eq!((key.protocol(), key.height()), (Protocol::RUNES, 840000));
+        assert!(OpReturnKey::new(Protocol::OMNI, u32::MAX).0 < OpReturnKey::new(Protocol::COUNTERPARTY, 0).0);
+        assert_eq!(parse_cursor("840000:12:1"), Some((840000, 12, 1)));
+        assert_eq!(parse_cursor("840000:12"), None);
+        assert_eq!(parse_cursor("840000:12:1:0"), None);
+    }
+}
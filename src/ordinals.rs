@@ -0,0 +1,912 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::model::{
+    Block, Content, ContentEncoding, ContentType, Envelope, Height, Inscription, InscriptionNumber, InscriptionSequence, InscriptionTransfer,
+    OutPoint, TransferId, TxHash,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::{OP_ENDIF, OP_IF, OP_PUSHNUM_1, OP_PUSHNUM_16, OP_PUSHNUM_NEG1};
+use bitcoin::script::Instruction;
+use bitcoin::{Network, TxOut};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub const PROTOCOL_ID: &[u8] = b"ord";
+pub const MAX_LIMIT: usize = 100;
+
+const CONTENT_TYPE_TAG: u8 = 1;
+const POINTER_TAG: u8 = 2;
+const CONTENT_ENCODING_TAG: u8 = 9;
+
+type OutPointKey = ([u8; 32], u32);
+
+/// Envelopes of all script-path spending inputs of `tx`, ie. `OP_FALSE OP_IF "ord" <fields> OP_0 <body> OP_ENDIF`
+pub fn parse_envelopes(tx: &bitcoin::Transaction) -> Vec<Envelope> {
+    let mut envelopes = Vec::new();
+    for (input_index, input) in tx.input.iter().enumerate() {
+        if let Some(leaf) = input.witness.taproot_leaf_script() {
+            envelopes.extend(from_tapscript(leaf.script, input_index as u32));
+        }
+    }
+    envelopes
+}
+
+fn from_tapscript(tapscript: &bitcoin::Script, input: u32) -> Vec<Envelope> {
+    // envelopes are parsed up to the first malformed instruction, like `ord` does
+    let instructions: Vec<Instruction> = tapscript.instructions().map_while(Result::ok).collect();
+    let is_empty_push = |i: usize| matches!(instructions.get(i), Some(Instruction::PushBytes(bytes)) if bytes.is_empty());
+    let mut envelopes = Vec::new();
+    // an attempt to open an envelope that fails right before another empty push curses the following envelopes
+    let mut stuttered = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        if !is_empty_push(i) {
+            i += 1;
+            continue;
+        }
+        if instructions.get(i + 1) != Some(&Instruction::Op(OP_IF)) {
+            stuttered = is_empty_push(i + 1);
+            i += 1;
+            continue;
+        }
+        if !matches!(instructions.get(i + 2), Some(Instruction::PushBytes(bytes)) if bytes.as_bytes() == PROTOCOL_ID) {
+            stuttered = is_empty_push(i + 2);
+            i += 2;
+            continue;
+        }
+        i += 3;
+        let mut payload = Vec::new();
+        let mut pushnum = false;
+        loop {
+            match instructions.get(i).and_then(push_data) {
+                Some(data) => {
+                    pushnum |= matches!(instructions[i], Instruction::Op(_));
+                    payload.push(data);
+                }
+                None if instructions.get(i) == Some(&Instruction::Op(OP_ENDIF)) => {
+                    envelopes.push(from_payload(input, payload, pushnum || stuttered));
+                    break;
+                }
+                // any other opcode or end of script invalidates the envelope
+                None => {
+                    stuttered = false;
+                    break;
+                }
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+    envelopes
+}
+
+fn push_data(instruction: &Instruction) -> Option<Vec<u8>> {
+    match instruction {
+        Instruction::PushBytes(bytes) => Some(bytes.as_bytes().to_vec()),
+        Instruction::Op(op) if *op == OP_PUSHNUM_NEG1 => Some(vec![0x81]),
+        Instruction::Op(op) if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) => {
+            Some(vec![op.to_u8() - OP_PUSHNUM_1.to_u8() + 1])
+        }
+        _ => None,
+    }
+}
+
+/// Payload is a sequence of tag/value pairs terminated by an empty tag that is followed by body chunks.
+/// A tag without value, a repeated tag or an unknown even tag curses the envelope.
+fn from_payload(input: u32, payload: Vec<Vec<u8>>, mut cursed: bool) -> Envelope {
+    let mut envelope =
+        Envelope { input, content_type: Vec::new(), content_encoding: Vec::new(), pointer: Vec::new(), body: Vec::new(), cursed: false };
+    let mut tags = HashSet::new();
+    let mut pushes = payload.into_iter();
+    while let Some(tag) = pushes.next() {
+        if tag.is_empty() {
+            envelope.body = pushes.flatten().collect();
+            break;
+        }
+        let Some(value) = pushes.next() else {
+            cursed = true;
+            break;
+        };
+        cursed |= !tags.insert(tag.clone());
+        // only the first value of a field counts, unknown odd fields are ignored
+        let field = match tag.as_slice() {
+            [CONTENT_TYPE_TAG] => &mut envelope.content_type,
+            [POINTER_TAG] => &mut envelope.pointer,
+            [CONTENT_ENCODING_TAG] => &mut envelope.content_encoding,
+            _ => {
+                cursed |= tag[0] % 2 == 0;
+                continue;
+            }
+        };
+        if field.is_empty() {
+            *field = value;
+        }
+    }
+    envelope.cursed = cursed;
+    envelope
+}
+
+/// Little endian pointer with optional trailing zeros, `None` if absent or it does not fit u64
+pub fn decode_pointer(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.iter().skip(8).any(|byte| *byte != 0) {
+        return None;
+    }
+    let mut le = [0u8; 8];
+    for (target, byte) in le.iter_mut().zip(bytes) {
+        *target = *byte;
+    }
+    Some(u64::from_le_bytes(le))
+}
+
+/// Output index and offset within it of `offset`-th sat of outputs with `values`, `None` if it falls to fees
+pub fn locate(offset: u64, values: &[u64]) -> Option<(u32, u64)> {
+    let mut start = 0;
+    for (vout, value) in values.iter().enumerate() {
+        if offset < start + value {
+            return Some((vout as u32, offset - start));
+        }
+        start += value;
+    }
+    None
+}
+
+/// New inscriptions and transfers of a block, stored in the same write transaction as the block
+#[derive(Default)]
+pub struct OrdinalsBatch {
+    pub inscriptions: Vec<Inscription>,
+    pub transfers: Vec<InscriptionTransfer>,
+    next_transfer: u32,
+}
+
+impl OrdinalsBatch {
+    fn transfer(&mut self, height: u32, inscription: u32, outpoint: OutPoint, offset: u64) {
+        self.transfers.push(InscriptionTransfer { id: TransferId(self.next_transfer), inscription, height, outpoint, offset });
+        self.next_transfer += 1;
+    }
+}
+
+/// Tracks inscriptions through transactions by first-in-first-out sat flow, like `ord` without sat index.
+/// Inscriptions are keyed by sequence in order of discovery and numbered like `ord`, cursed ones downwards from -1.
+pub struct Ordinals {
+    first_height: u32,
+    // inscriptions are not cursed from this height on
+    jubilee_height: u32,
+    halving_interval: u32,
+}
+
+impl Ordinals {
+    pub fn new(network: Network) -> Self {
+        // blocks before the first inscription are skipped, as in `ord`
+        let first_height = match network {
+            Network::Bitcoin => 767430,
+            Network::Testnet => 2413343,
+            Network::Signet => 112402,
+            _ => 0,
+        };
+        let jubilee_height = match network {
+            Network::Bitcoin => 824544,
+            Network::Testnet => 2544192,
+            Network::Signet => 175392,
+            Network::Regtest => 110,
+            _ => 0,
+        };
+        let halving_interval = if network == Network::Regtest { 150 } else { 210_000 };
+        Ordinals { first_height, jubilee_height, halving_interval }
+    }
+
+    fn subsidy(&self, height: u32) -> u64 {
+        let halvings = height / self.halving_interval;
+        if halvings >= 64 { 0 } else { (50 * 100_000_000) >> halvings }
+    }
+
+    /// `prevouts` are outputs spent by every transaction of the block in input order, unindexed ones carry no sats
+    pub fn index_block(&self, state: &impl InscriptionState, block: &Block, prevouts: &[Vec<Option<TxOut>>]) -> Result<OrdinalsBatch, AppError> {
+        let mut batch = OrdinalsBatch::default();
+        if block.id.0 < self.first_height {
+            return Ok(batch);
+        }
+        let (mut next_sequence, mut next_blessed, mut next_cursed) = next_numbers(state.last_inscription()?);
+        batch.next_transfer = state.last_transfer()?.map_or(0, |last| last + 1);
+        // inscriptions moved within this block are not stored yet
+        let mut block_locations: HashMap<OutPointKey, Vec<(u32, u64)>> = HashMap::new();
+        // inscriptions spent to fees, with their offset among all fees of the block
+        let mut flotsam: Vec<(u32, u64)> = Vec::new();
+        let mut fees = 0;
+
+        // coinbase goes last as it collects inscriptions spent to fees
+        for (tx, tx_prevouts) in block.transactions.iter().zip(prevouts).skip(1) {
+            let values: Vec<u64> = tx.utxos.iter().map(|utxo| utxo.amount).collect();
+            let mut floating = Vec::new();
+            // first inscription and count of inscriptions on each sat of inputs
+            let mut inscribed_offsets: HashMap<u64, (u32, u32)> = HashMap::new();
+            let mut input_start = 0;
+            for (input_index, input) in tx.transient_inputs.iter().enumerate() {
+                let key = (input.tx_hash.0, input.index);
+                let spent = match block_locations.remove(&key) {
+                    Some(spent) => spent,
+                    None => state.inscriptions_at(&OutPoint { tx_hash: input.tx_hash.clone(), vout: input.index })?,
+                };
+                for (sequence, offset) in spent {
+                    inscribed_offsets.entry(input_start + offset).or_insert((sequence, 0)).1 += 1;
+                    floating.push((sequence, input_start + offset));
+                }
+                for (envelope_index, envelope) in tx.envelopes.iter().enumerate().filter(|(_, e)| e.input == input_index as u32) {
+                    let pointer = decode_pointer(&envelope.pointer);
+                    // a reinscription is cursed unless the sat carries a single inscription which is cursed itself
+                    let reinscription = match inscribed_offsets.get(&input_start) {
+                        Some((_, count)) if *count > 1 => true,
+                        Some((first, _)) => match batch.inscriptions.iter().find(|inscription| inscription.id.0 == *first) {
+                            Some(inscription) => !inscription.cursed,
+                            None => !state.cursed(*first)?,
+                        },
+                        None => false,
+                    };
+                    let cursed = block.id.0 < self.jubilee_height
+                        && (envelope.cursed
+                            || envelope.input != 0
+                            || tx.envelopes[..envelope_index].iter().any(|earlier| earlier.input == envelope.input)
+                            || pointer.is_some()
+                            || reinscription);
+                    let offset = pointer.filter(|pointer| *pointer < values.iter().sum()).unwrap_or(input_start);
+                    inscribed_offsets.entry(offset).or_insert((next_sequence, 0)).1 += 1;
+                    let number = if cursed { next_cursed } else { next_blessed };
+                    if cursed {
+                        next_cursed -= 1;
+                    } else {
+                        next_blessed += 1;
+                    }
+                    batch.inscriptions.push(Inscription {
+                        id: InscriptionSequence(next_sequence),
+                        number: InscriptionNumber(number),
+                        genesis_tx: tx.hash.clone(),
+                        genesis_index: envelope_index as u32,
+                        height: block.id.0,
+                        content_type: ContentType(envelope.content_type.clone()),
+                        content_encoding: ContentEncoding(envelope.content_encoding.clone()),
+                        content: Content(envelope.body.clone()),
+                        cursed,
+                    });
+                    floating.push((next_sequence, offset));
+                    next_sequence += 1;
+                }
+                input_start += tx_prevouts.get(input_index).and_then(Option::as_ref).map_or(0, |prevout| prevout.value.to_sat());
+            }
+            let output_total: u64 = values.iter().sum();
+            for (sequence, offset) in floating {
+                match locate(offset, &values) {
+                    Some((vout, offset)) => {
+                        batch.transfer(block.id.0, sequence, OutPoint { tx_hash: tx.hash.clone(), vout }, offset);
+                        block_locations.entry((tx.hash.0, vout)).or_default().push((sequence, offset));
+                    }
+                    None => flotsam.push((sequence, fees + offset - output_total)),
+                }
+            }
+            fees += input_start.saturating_sub(output_total);
+        }
+
+        if let Some(coinbase) = block.transactions.first() {
+            let values: Vec<u64> = coinbase.utxos.iter().map(|utxo| utxo.amount).collect();
+            let subsidy = self.subsidy(block.id.0);
+            for (sequence, fee_offset) in flotsam {
+                match locate(subsidy + fee_offset, &values) {
+                    Some((vout, offset)) => batch.transfer(block.id.0, sequence, OutPoint { tx_hash: coinbase.hash.clone(), vout }, offset),
+                    // miner did not claim the fees, the inscription is lost
+                    None => batch.transfer(block.id.0, sequence, OutPoint { tx_hash: TxHash([0; 32]), vout: 0 }, 0),
+                }
+            }
+        }
+        Ok(batch)
+    }
+}
+
+/// Next sequence, blessed and cursed number after the `(sequence, number)` of the last inscription. Sequences have no gaps
+/// and every inscription is either blessed or cursed, so the last one tells how many there are of each.
+fn next_numbers(last: Option<(u32, i32)>) -> (u32, i32, i32) {
+    match last {
+        None => (0, 0, -1),
+        Some((sequence, number)) if number >= 0 => (sequence + 1, number + 1, number - sequence as i32 - 1),
+        Some((sequence, number)) => (sequence + 1, sequence as i32 + number + 1, number - 1),
+    }
+}
+
+/// Inscriptions of stored blocks that a new block is indexed against
+pub trait InscriptionState {
+    fn last_inscription(&self) -> Result<Option<(u32, i32)>, AppError>;
+    fn last_transfer(&self) -> Result<Option<u32>, AppError>;
+    fn inscriptions_at(&self, outpoint: &OutPoint) -> Result<Vec<(u32, u64)>, AppError>;
+    fn cursed(&self, sequence: u32) -> Result<bool, AppError>;
+}
+
+impl InscriptionState for ReadTransaction {
+    fn last_inscription(&self) -> Result<Option<(u32, i32)>, AppError> {
+        Ok(Inscription::last(self)?.map(|last| (last.id.0, last.number.0)))
+    }
+
+    fn last_transfer(&self) -> Result<Option<u32>, AppError> {
+        Ok(InscriptionTransfer::last(self)?.map(|last| last.id.0))
+    }
+
+    fn inscriptions_at(&self, outpoint: &OutPoint) -> Result<Vec<(u32, u64)>, AppError> {
+        inscriptions_at(self, outpoint)
+    }
+
+    fn cursed(&self, sequence: u32) -> Result<bool, AppError> {
+        Ok(Inscription::get(self, &InscriptionSequence(sequence))?.is_some_and(|inscription| inscription.cursed))
+    }
+}
+
+/// `ord`'s number of the inscription of `sequence`
+pub fn inscription_number(read_tx: &ReadTransaction, sequence: u32) -> Result<Option<i32>, AppError> {
+    Ok(Inscription::get(read_tx, &InscriptionSequence(sequence))?.map(|inscription| inscription.number.0))
+}
+
+fn latest_transfer(read_tx: &ReadTransaction, sequence: u32) -> Result<Option<InscriptionTransfer>, AppError> {
+    match InscriptionTransfer::get_ids_by_inscription(read_tx, &sequence)?.into_iter().max() {
+        Some(id) => InscriptionTransfer::get(read_tx, &id),
+        None => Ok(None),
+    }
+}
+
+/// Inscriptions currently sitting on `outpoint` with their offsets, transfers that were followed by another one are history
+pub fn inscriptions_at(read_tx: &ReadTransaction, outpoint: &OutPoint) -> Result<Vec<(u32, u64)>, AppError> {
+    let mut result = Vec::new();
+    for id in InscriptionTransfer::get_ids_by_outpoint(read_tx, outpoint)? {
+        if let Some(transfer) = InscriptionTransfer::get(read_tx, &id)?
+            && latest_transfer(read_tx, transfer.inscription)?.is_some_and(|latest| latest.id == id)
+        {
+            result.push((transfer.inscription, transfer.offset));
+        }
+    }
+    Ok(result)
+}
+
+/// Inscriptions and transfers of blocks at `heights` that are about to be deleted by a reorg
+pub fn rollback_ids(read_tx: &ReadTransaction, heights: &[Height]) -> Result<(Vec<InscriptionSequence>, Vec<TransferId>), AppError> {
+    let mut inscriptions = Vec::new();
+    let mut transfers = Vec::new();
+    for height in heights {
+        inscriptions.extend(Inscription::get_ids_by_height(read_tx, &height.0)?);
+        transfers.extend(InscriptionTransfer::get_ids_by_height(read_tx, &height.0)?);
+    }
+    Ok((inscriptions, transfers))
+}
+
+fn display_txid(tx_hash: &TxHash) -> String {
+    bitcoin::Txid::from_byte_array(tx_hash.0).to_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct InscriptionView {
+    pub number: i32,
+    pub sequence: u32,
+    pub id: String,
+    pub height: u32,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_length: usize,
+    pub cursed: bool,
+    pub satpoint: Option<String>,
+}
+
+impl InscriptionView {
+    fn new(inscription: &Inscription, location: Option<&InscriptionTransfer>) -> Self {
+        let text = |bytes: &[u8]| (!bytes.is_empty()).then(|| String::from_utf8_lossy(bytes).into_owned());
+        InscriptionView {
+            number: inscription.number.0,
+            sequence: inscription.id.0,
+            id: format!("{}i{}", display_txid(&inscription.genesis_tx), inscription.genesis_index),
+            height: inscription.height,
+            content_type: text(&inscription.content_type.0),
+            content_encoding: text(&inscription.content_encoding.0),
+            content_length: inscription.content.0.len(),
+            cursed: inscription.cursed,
+            satpoint: location
+                .filter(|transfer| transfer.outpoint.tx_hash.0 != [0; 32])
+                .map(|transfer| format!("{}:{}:{}", display_txid(&transfer.outpoint.tx_hash), transfer.outpoint.vout, transfer.offset)),
+        }
+    }
+}
+
+/// Inscription by `ord`'s number or by `ord`'s `<txid>i<index>` id
+pub fn find_inscription(read_tx: &ReadTransaction, id: &str) -> Result<Option<Inscription>, AppError> {
+    if let Ok(number) = id.parse::<i32>() {
+        return match Inscription::get_ids_by_number(read_tx, &InscriptionNumber(number))?.first() {
+            Some(sequence) => Inscription::get(read_tx, sequence),
+            None => Ok(None),
+        };
+    }
+    let Some((txid, index)) = id.split_once('i') else {
+        return Ok(None);
+    };
+    let (Ok(txid), Ok(index)) = (bitcoin::Txid::from_str(txid), index.parse::<u32>()) else {
+        return Ok(None);
+    };
+    for sequence in Inscription::get_ids_by_genesis_tx(read_tx, &TxHash(txid.to_byte_array()))? {
+        if let Some(inscription) = Inscription::get(read_tx, &sequence)?.filter(|inscription| inscription.genesis_index == index) {
+            return Ok(Some(inscription));
+        }
+    }
+    Ok(None)
+}
+
+pub fn inscription_view(read_tx: &ReadTransaction, id: &str) -> Result<Option<InscriptionView>, AppError> {
+    match find_inscription(read_tx, id)? {
+        Some(inscription) => Ok(Some(InscriptionView::new(&inscription, latest_transfer(read_tx, inscription.id.0)?.as_ref()))),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub before: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+/// Latest inscriptions first, starting below `before` sequence if given
+pub fn list_inscriptions(read_tx: &ReadTransaction, params: &PageParams) -> Result<Vec<InscriptionView>, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let until = match params.before {
+        Some(before) => before,
+        None => Inscription::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+    };
+    let mut result = Vec::with_capacity(limit);
+    for sequence in (0..until).rev() {
+        if result.len() >= limit {
+            break;
+        }
+        if let Some(inscription) = Inscription::get(read_tx, &InscriptionSequence(sequence))? {
+            result.push(InscriptionView::new(&inscription, latest_transfer(read_tx, sequence)?.as_ref()));
+        }
+    }
+    Ok(result)
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/inscriptions", get(inscriptions))
+        .route("/inscription/{id}", get(inscription))
+        .route("/inscription/{id}/content", get(content))
+        .layer(Extension(pool))
+}
+
+async fn inscriptions(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<PageParams>) -> Response {
+    json_response(pool.run(move || list_inscriptions(&state.db.begin_read()?, &params)).await)
+}
+
+/// Inscription metadata with its current satpoint, `None` once it was lost to unclaimed fees
+async fn inscription(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(id): Path<String>) -> Response {
+    let query_id = id.clone();
+    match pool.run(move || inscription_view(&state.db.begin_read()?, &query_id)).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Inscription {} not found", id)),
+        result => json_response(result),
+    }
+}
+
+/// Raw inscription body served with its own content type and encoding. Inscriptions are arbitrary user content, so
+/// it must not run scripts, load resources or be sniffed into another type within explorer's origin.
+fn content_response(inscription: Inscription) -> Response {
+    let content_type = Some(&inscription.content_type.0)
+        .filter(|content_type| !content_type.is_empty())
+        .and_then(|content_type| HeaderValue::from_bytes(content_type).ok())
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    let encoding =
+        Some(&inscription.content_encoding.0).filter(|encoding| !encoding.is_empty()).and_then(|encoding| HeaderValue::from_bytes(encoding).ok());
+    let mut response = inscription.content.0.into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type);
+    if let Some(encoding) = encoding {
+        headers.insert(header::CONTENT_ENCODING, encoding);
+    }
+    headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("default-src 'none'; sandbox"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    response
+}
+
+async fn content(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(id): Path<String>) -> Response {
+    let query_id = id.clone();
+    match pool.run(move || find_inscription(&state.db.begin_read()?, &query_id)).await {
+        Ok(Ok(Some(inscription))) => content_response(inscription),
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Inscription {} not found", id)),
+        Ok(Err(e)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockHash, TempInputRef, Transaction, Utxo};
+    use bitcoin::opcodes::all::OP_CHECKSIG;
+    use bitcoin::opcodes::OP_FALSE;
+    use bitcoin::script::{Builder, PushBytesBuf};
+    use bitcoin::{Amount, ScriptBuf, TxIn, Witness};
+
+    fn push(builder: Builder, data: &[u8]) -> Builder {
+        builder.push_slice(PushBytesBuf::try_from(data.to_vec()).unwrap())
+    }
+
+    fn reveal_tx(tapscript: bitcoin::ScriptBuf) -> bitcoin::Transaction {
+        let mut witness = Witness::new();
+        witness.push([1u8; 64]); // signature
+        witness.push(tapscript.as_bytes());
+        witness.push([0xc0u8; 33]); // control block
+        bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![TxIn { witness, ..TxIn::default() }],
+            output: vec![],
+        }
+    }
+
+    fn envelope_script(fields: &[(&[u8], &[u8])], body: &[&[u8]]) -> bitcoin::ScriptBuf {
+        let mut builder = push(Builder::new(), &[2u8; 32]).push_opcode(OP_CHECKSIG).push_opcode(OP_FALSE).push_opcode(OP_IF);
+        builder = push(builder, PROTOCOL_ID);
+        for (tag, value) in fields {
+            builder = push(push(builder, tag), value);
+        }
+        builder = builder.push_opcode(OP_FALSE);
+        for chunk in body {
+            builder = push(builder, chunk);
+        }
+        builder.push_opcode(OP_ENDIF).into_script()
+    }
+
+    #[test]
+    fn test_parse_envelope() {
+        let script = envelope_script(&[(&[1], b"text/plain;charset=utf-8"), (&[9], b"br"), (&[5], b"ignored")], &[b"Hello, ", b"world!"]);
+        let envelopes = parse_envelopes(&reveal_tx(script));
+        assert_eq!(envelopes.len(), 1);
+        assert_eq!(envelopes[0].input, 0);
+        assert_eq!(envelopes[0].content_type, b"text/plain;charset=utf-8");
+        assert_eq!(envelopes[0].content_encoding, b"br");
+        assert_eq!(envelopes[0].body, b"Hello, world!");
+        assert!(envelopes[0].pointer.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_envelopes_are_ignored() {
+        // wrong protocol id
+        let script = push(Builder::new().push_opcode(OP_FALSE).push_opcode(OP_IF), b"xyz").push_opcode(OP_ENDIF).into_script();
+        assert!(parse_envelopes(&reveal_tx(script)).is_empty());
+        // not terminated by OP_ENDIF
+        let script = push(Builder::new().push_opcode(OP_FALSE).push_opcode(OP_IF), PROTOCOL_ID).push_opcode(OP_CHECKSIG).into_script();
+        assert!(parse_envelopes(&reveal_tx(script)).is_empty());
+        // key path spend has no tapscript
+        let mut tx = reveal_tx(envelope_script(&[], &[b"body"]));
+        tx.input[0].witness = Witness::from_slice(&[[1u8; 64]]);
+        assert!(parse_envelopes(&tx).is_empty());
+    }
+
+    #[test]
+    fn test_pushnum_and_multiple_envelopes() {
+        let mut script = envelope_script(&[], &[b"first"]).into_bytes();
+        script.extend(envelope_script(&[], &[b"second"]).into_bytes());
+        let envelopes = parse_envelopes(&reveal_tx(bitcoin::ScriptBuf::from_bytes(script)));
+        assert_eq!(envelopes.iter().map(|e| e.body.as_slice()).collect::<Vec<_>>(), vec![b"first".as_slice(), b"second".as_slice()]);
+
+        let script = Builder::new().push_opcode(OP_FALSE).push_opcode(OP_IF);
+        let script = push(script, PROTOCOL_ID).push_opcode(OP_FALSE).push_opcode(OP_PUSHNUM_1).push_opcode(OP_ENDIF).into_script();
+        assert_eq!(parse_envelopes(&reveal_tx(script))[0].body, vec![1]);
+    }
+
+    #[test]
+    fn test_envelope_curses() {
+        let cursed = |script: bitcoin::ScriptBuf| parse_envelopes(&reveal_tx(script)).iter().map(|e| e.cursed).collect::<Vec<_>>();
+        assert_eq!(cursed(envelope_script(&[(&[1], b"text/plain"), (&[5], b"odd tag is ignored")], &[b"body"])), vec![false]);
+        assert_eq!(cursed(envelope_script(&[(&[1], b"text/plain"), (&[1], b"text/html")], &[b"body"])), vec![true]);
+        assert_eq!(cursed(envelope_script(&[(&[4], b"unknown even tag")], &[b"body"])), vec![true]);
+        // tag without value
+        let script = push(push(Builder::new().push_opcode(OP_FALSE).push_opcode(OP_IF), PROTOCOL_ID), &[1]).push_opcode(OP_ENDIF).into_script();
+        assert_eq!(cursed(script), vec![true]);
+        // pushnum in body
+        let script = push(Builder::new().push_opcode(OP_FALSE).push_opcode(OP_IF), PROTOCOL_ID).push_opcode(OP_FALSE).push_opcode(OP_PUSHNUM_1);
+        assert_eq!(cursed(script.push_opcode(OP_ENDIF).into_script()), vec![true]);
+        // stutter, an empty push right before the envelope or after a failed OP_IF
+        let mut script = Builder::new().push_opcode(OP_FALSE).into_script().into_bytes();
+        script.extend(envelope_script(&[], &[b"body"]).into_bytes());
+        assert_eq!(cursed(bitcoin::ScriptBuf::from_bytes(script)), vec![false]);
+        let script = push(Builder::new().push_opcode(OP_FALSE).push_opcode(OP_FALSE).push_opcode(OP_IF), PROTOCOL_ID).push_opcode(OP_ENDIF);
+        assert_eq!(cursed(script.into_script()), vec![true]);
+        let script = Builder::new().push_opcode(OP_FALSE).push_opcode(OP_IF).push_opcode(OP_FALSE).push_opcode(OP_IF);
+        assert_eq!(cursed(push(script, PROTOCOL_ID).push_opcode(OP_ENDIF).into_script()), vec![true]);
+    }
+
+    #[test]
+    fn test_decode_pointer() {
+        assert_eq!(decode_pointer(&[]), None);
+        assert_eq!(decode_pointer(&[1]), Some(1));
+        assert_eq!(decode_pointer(&[0, 1]), Some(256));
+        assert_eq!(decode_pointer(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Some(1));
+        assert_eq!(decode_pointer(&[1, 0, 0, 0, 0, 0, 0, 0, 1]), None);
+    }
+
+    #[test]
+    fn test_sat_flow() {
+        let outputs = [1000, 500, 250];
+        assert_eq!(locate(0, &outputs), Some((0, 0)));
+        assert_eq!(locate(999, &outputs), Some((0, 999)));
+        assert_eq!(locate(1000, &outputs), Some((1, 0)));
+        assert_eq!(locate(1600, &outputs), Some((2, 100)));
+        assert_eq!(locate(1750, &outputs), None);
+        assert_eq!(locate(0, &[]), None);
+    }
+
+    #[test]
+    fn test_subsidy() {
+        let ordinals = Ordinals::new(Network::Bitcoin);
+        assert_eq!(ordinals.subsidy(0), 5_000_000_000);
+        assert_eq!(ordinals.subsidy(840_000), 312_500_000);
+        assert_eq!(Ordinals::new(Network::Regtest).subsidy(150), 2_500_000_000);
+    }
+
+    /// Stored inscriptions, transfers and output values, a batch is applied as it would be stored with its block
+    #[derive(Default)]
+    struct MemoryState {
+        inscriptions: Vec<Inscription>,
+        transfers: Vec<InscriptionTransfer>,
+        values: HashMap<OutPointKey, u64>,
+    }
+
+    impl MemoryState {
+        fn apply(&mut self, block: &Block, batch: OrdinalsBatch) {
+            self.inscriptions.extend(batch.inscriptions);
+            self.transfers.extend(batch.transfers);
+            for tx in &block.transactions {
+                self.values.extend(tx.utxos.iter().enumerate().map(|(vout, utxo)| ((tx.hash.0, vout as u32), utxo.amount)));
+            }
+        }
+
+        /// What `rollback_ids` selects is deleted along with the stale block
+        fn rollback(&mut self, height: u32) {
+            self.inscriptions.retain(|inscription| inscription.height != height);
+            self.transfers.retain(|transfer| transfer.height != height);
+        }
+
+        /// Outputs spent by `block` as `BlockPersistence` looks them up, from the block itself or from stored blocks
+        fn prevouts(&self, block: &Block) -> Vec<Vec<Option<TxOut>>> {
+            let mut values = self.values.clone();
+            for tx in &block.transactions {
+                values.extend(tx.utxos.iter().enumerate().map(|(vout, utxo)| ((tx.hash.0, vout as u32), utxo.amount)));
+            }
+            let prevout = |input: &TempInputRef| {
+                values.get(&(input.tx_hash.0, input.index)).map(|value| TxOut { value: Amount::from_sat(*value), script_pubkey: ScriptBuf::new() })
+            };
+            block.transactions.iter().map(|tx| tx.transient_inputs.iter().map(prevout).collect()).collect()
+        }
+
+        fn index(&self, ordinals: &Ordinals, block: &Block) -> OrdinalsBatch {
+            ordinals.index_block(self, block, &self.prevouts(block)).unwrap()
+        }
+
+        fn location(&self, sequence: u32) -> Option<(OutPoint, u64)> {
+            self.transfers
+                .iter()
+                .filter(|transfer| transfer.inscription == sequence)
+                .max_by_key(|transfer| transfer.id.0)
+                .map(|t| (t.outpoint.clone(), t.offset))
+        }
+    }
+
+    impl InscriptionState for MemoryState {
+        fn last_inscription(&self) -> Result<Option<(u32, i32)>, AppError> {
+            Ok(self.inscriptions.iter().max_by_key(|inscription| inscription.id.0).map(|last| (last.id.0, last.number.0)))
+        }
+
+        fn last_transfer(&self) -> Result<Option<u32>, AppError> {
+            Ok(self.transfers.iter().map(|transfer| transfer.id.0).max())
+        }
+
+        fn inscriptions_at(&self, outpoint: &OutPoint) -> Result<Vec<(u32, u64)>, AppError> {
+            Ok(self
+                .transfers
+                .iter()
+                .filter(|transfer| {
+                    transfer.outpoint == *outpoint && self.location(transfer.inscription).is_some_and(|(latest, _)| latest == *outpoint)
+                })
+                .map(|transfer| (transfer.inscription, transfer.offset))
+                .collect())
+        }
+
+        fn cursed(&self, sequence: u32) -> Result<bool, AppError> {
+            Ok(self.inscriptions.iter().any(|inscription| inscription.id.0 == sequence && inscription.cursed))
+        }
+    }
+
+    fn envelope(input: u32, pointer: &[u8]) -> Envelope {
+        Envelope {
+            input,
+            content_type: b"text/plain".to_vec(),
+            content_encoding: Vec::new(),
+            pointer: pointer.to_vec(),
+            body: b"body".to_vec(),
+            cursed: false,
+        }
+    }
+
+    fn tx(hash: u8, inputs: &[(u8, u32)], values: &[u64], envelopes: Vec<Envelope>) -> Transaction {
+        let mut tx = Block::sample().transactions[0].clone();
+        let utxo = tx.utxos[0].clone();
+        tx.hash = TxHash([hash; 32]);
+        tx.transient_inputs = inputs.iter().map(|(tx_hash, index)| TempInputRef { tx_hash: TxHash([*tx_hash; 32]), index: *index }).collect();
+        tx.utxos = values.iter().map(|value| Utxo { amount: *value, ..utxo.clone() }).collect();
+        tx.envelopes = envelopes;
+        tx
+    }
+
+    fn block(height: u32, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::sample();
+        block.id = Height(height);
+        block.header.hash = BlockHash([height as u8; 32]);
+        block.transactions = transactions;
+        block
+    }
+
+    fn outpoint(hash: u8, vout: u32) -> OutPoint {
+        OutPoint { tx_hash: TxHash([hash; 32]), vout }
+    }
+
+    const SUBSIDY: u64 = 5_000_000_000;
+
+    #[test]
+    fn test_index_block_sat_flow() {
+        let ordinals = Ordinals::new(Network::Regtest);
+        let mut state = MemoryState::default();
+        state.values.extend([(([1; 32], 0), 1000), (([2; 32], 0), 1000), (([3; 32], 0), 400)]);
+
+        let first = block(
+            10,
+            vec![
+                tx(0xc0, &[], &[SUBSIDY + 200], vec![]),
+                // inscribed on the first sat of its input
+                tx(0xa1, &[(1, 0)], &[600, 300], vec![envelope(0, &[])]),
+                // pointer 700 lands in the second output
+                tx(0xb1, &[(2, 0)], &[500, 500], vec![envelope(0, &[0xbc, 0x02])]),
+                // spends an output of this block along with its inscription
+                tx(0xd1, &[(0xb1, 1)], &[100, 400], vec![]),
+            ],
+        );
+        let batch = state.index(&ordinals, &first);
+        assert_eq!(batch.inscriptions.iter().map(|i| (i.id.0, i.genesis_tx.0[0], i.height)).collect::<Vec<_>>(), vec![(0, 0xa1, 10), (1, 0xb1, 10)]);
+        state.apply(&first, batch);
+        assert_eq!(state.location(0), Some((outpoint(0xa1, 0), 0)));
+        assert_eq!(state.location(1), Some((outpoint(0xd1, 1), 100)));
+        assert_eq!(state.transfers.iter().map(|t| t.id.0).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        // stored inscription follows its sats behind the 400 sats of the first input
+        let second = block(11, vec![tx(0xc1, &[], &[SUBSIDY], vec![]), tx(0xe1, &[(3, 0), (0xa1, 0)], &[450, 550], vec![])]);
+        let batch = state.index(&ordinals, &second);
+        assert!(batch.inscriptions.is_empty());
+        state.apply(&second, batch);
+        assert_eq!(state.location(0), Some((outpoint(0xe1, 0), 400)));
+        assert!(state.inscriptions_at(&outpoint(0xa1, 0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_block_flotsam_goes_to_coinbase() {
+        let ordinals = Ordinals::new(Network::Regtest);
+        let mut state = MemoryState::default();
+        state.values.extend([(([1; 32], 0), 1000), (([2; 32], 0), 1000)]);
+        let txs = |coinbase_values: &[u64]| {
+            vec![
+                tx(0xc0, &[], coinbase_values, vec![]),
+                tx(0xa1, &[(1, 0)], &[900], vec![]),
+                // nothing to land on but fees, behind the 100 sats of fee paid before
+                tx(0xb1, &[(2, 0)], &[], vec![envelope(0, &[])]),
+            ]
+        };
+
+        let claimed = block(10, txs(&[SUBSIDY, 50, 1050]));
+        let batch = state.index(&ordinals, &claimed);
+        state.apply(&claimed, batch);
+        assert_eq!(state.location(0), Some((outpoint(0xc0, 2), 50)));
+
+        // miner did not claim the fees
+        let unclaimed = block(10, txs(&[SUBSIDY]));
+        let batch = MemoryState { values: state.values.clone(), ..MemoryState::default() }.index(&ordinals, &unclaimed);
+        assert_eq!(
+            batch.transfers.iter().map(|t| (t.outpoint.clone(), t.offset)).collect::<Vec<_>>(),
+            vec![(OutPoint { tx_hash: TxHash([0; 32]), vout: 0 }, 0)]
+        );
+    }
+
+    #[test]
+    fn test_index_block_rollback() {
+        let ordinals = Ordinals::new(Network::Regtest);
+        let mut state = MemoryState::default();
+        state.values.extend([(([1; 32], 0), 1000), (([2; 32], 0), 1000), (([3; 32], 0), 1000)]);
+        let first = block(10, vec![tx(0xc0, &[], &[SUBSIDY], vec![]), tx(0xa1, &[(1, 0)], &[1000], vec![envelope(0, &[])])]);
+        let batch = state.index(&ordinals, &first);
+        state.apply(&first, batch);
+
+        // stale block moves inscription 0 and inscribes another one
+        let stale = block(
+            11,
+            vec![tx(0xc1, &[], &[SUBSIDY], vec![]), tx(0xb1, &[(0xa1, 0)], &[1000], vec![]), tx(0xb2, &[(2, 0)], &[1000], vec![envelope(0, &[])])],
+        );
+        let batch = state.index(&ordinals, &stale);
+        state.apply(&stale, batch);
+        assert_eq!(state.location(0), Some((outpoint(0xb1, 0), 0)));
+        assert_eq!(state.location(1), Some((outpoint(0xb2, 0), 0)));
+
+        state.rollback(11);
+        assert_eq!(state.location(0), Some((outpoint(0xa1, 0), 0)));
+        assert_eq!(state.location(1), None);
+
+        // the fork reuses numbers and transfer ids of the stale block
+        let fork = block(11, vec![tx(0xc2, &[], &[SUBSIDY], vec![]), tx(0xf1, &[(3, 0)], &[1000], vec![envelope(0, &[])])]);
+        let batch = state.index(&ordinals, &fork);
+        assert_eq!(batch.inscriptions.iter().map(|i| (i.id.0, i.genesis_tx.0[0])).collect::<Vec<_>>(), vec![(1, 0xf1)]);
+        assert_eq!(batch.transfers.iter().map(|t| (t.id.0, t.inscription)).collect::<Vec<_>>(), vec![(1, 1)]);
+        state.apply(&fork, batch);
+        assert_eq!(state.location(0), Some((outpoint(0xa1, 0), 0)));
+        assert_eq!(state.location(1), Some((outpoint(0xf1, 0), 0)));
+    }
+
+    #[test]
+    fn test_index_block_curses() {
+        let ordinals = Ordinals::new(Network::Regtest);
+        let mut state = MemoryState::default();
+        state.values.extend((1..=4).map(|hash| (([hash; 32], 0), 1000)));
+        let cursed_envelope = Envelope { cursed: true, ..envelope(0, &[]) };
+        let txs = || {
+            vec![
+                tx(0xc0, &[], &[SUBSIDY], vec![]),
+                tx(0xa1, &[(1, 0)], &[1000], vec![envelope(0, &[])]),
+                // numbered by input, the first one of the first input is blessed, then the one not at offset zero,
+                // the one with a pointer and the one not in the first input are cursed
+                tx(0xa2, &[(2, 0), (3, 0)], &[1000, 1000], vec![envelope(1, &[]), envelope(0, &[]), envelope(0, &[]), envelope(0, &[1])]),
+                tx(0xa3, &[(4, 0)], &[1000], vec![cursed_envelope.clone()]),
+            ]
+        };
+        let curses =
+            |batch: &OrdinalsBatch| batch.inscriptions.iter().map(|inscription| (inscription.cursed, inscription.number.0)).collect::<Vec<_>>();
+
+        // cursed ones are numbered downwards like in `ord`
+        let first = block(10, txs());
+        let batch = state.index(&ordinals, &first);
+        assert_eq!(curses(&batch), vec![(false, 0), (false, 1), (true, -1), (true, -2), (true, -3), (true, -4)]);
+        state.apply(&first, batch);
+        // jubilee blesses all of them, numbered on after the blessed ones
+        assert_eq!(curses(&state.index(&ordinals, &block(110, txs()))), (2..8).map(|number| (false, number)).collect::<Vec<_>>());
+
+        // reinscribing a sat with a blessed inscription is cursed, with a single cursed one it is not
+        let second = block(11, vec![tx(0xc1, &[], &[SUBSIDY], vec![]), tx(0xb1, &[(0xa1, 0)], &[1000], vec![envelope(0, &[])])]);
+        let batch = state.index(&ordinals, &second);
+        assert_eq!(curses(&batch), vec![(true, -5)]);
+        let second = block(11, vec![tx(0xc1, &[], &[SUBSIDY], vec![]), tx(0xb3, &[(0xa3, 0)], &[1000], vec![envelope(0, &[])])]);
+        assert_eq!(curses(&state.index(&ordinals, &second)), vec![(false, 2)]);
+    }
+
+    #[test]
+    fn test_content_is_sandboxed() {
+        let inscription = |content_type: &[u8], content_encoding: &[u8]| Inscription {
+            id: InscriptionSequence(0),
+            number: InscriptionNumber(0),
+            genesis_tx: TxHash([1; 32]),
+            genesis_index: 0,
+            height: 10,
+            content_type: ContentType(content_type.to_vec()),
+            content_encoding: ContentEncoding(content_encoding.to_vec()),
+            content: Content(b"<script>alert(1)</script>".to_vec()),
+            cursed: false,
+        };
+        let response = content_response(inscription(b"text/html;charset=utf-8", b"br"));
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/html;charset=utf-8");
+        assert_eq!(headers[header::CONTENT_ENCODING], "br");
+        assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "default-src 'none'; sandbox");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let response = content_response(inscription(b"", b""));
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/octet-stream");
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+}
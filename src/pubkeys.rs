@@ -0,0 +1,211 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec;
+use crate::model::{Address, Pubkey, PubkeyHash, ScriptType, Transaction, TransactionPointer, Utxo, UtxoAddress, UtxoPointer, UtxoPubkey};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::script::Instruction;
+use bitcoin::Script;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const MAX_LIMIT: usize = 1000;
+
+/// Public keys pushed by a P2PK or bare multisig output, empty for other scripts
+pub fn script_pubkeys(script: &Script, script_type: ScriptType) -> Vec<Vec<u8>> {
+    if script_type != ScriptType::P2PK && script_type != ScriptType::MULTISIG {
+        return Vec::new();
+    }
+    script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(push)) if matches!(push.len(), 33 | 65) => Some(push.as_bytes().to_vec()),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn utxo_pubkeys(script: &Script, script_type: ScriptType, utxo_pointer: &TransactionPointer) -> Vec<UtxoPubkey> {
+    script_pubkeys(script, script_type)
+        .into_iter()
+        .enumerate()
+        .map(|(index, pubkey)| UtxoPubkey {
+            id: UtxoPointer::from_parent(utxo_pointer.clone(), index as u8),
+            pubkey_hash: PubkeyHash(bitcoin::PubkeyHash::hash(&pubkey).to_byte_array()),
+            pubkey: Pubkey(pubkey),
+        })
+        .collect()
+}
+
+/// Serializations a key can be found under, early P2PK outputs use uncompressed keys whereas later wallets compressed ones
+fn key_forms(pubkey: &[u8]) -> Vec<Vec<u8>> {
+    match bitcoin::secp256k1::PublicKey::from_slice(pubkey) {
+        Ok(key) => vec![key.serialize().to_vec(), key.serialize_uncompressed().to_vec()],
+        Err(_) => vec![pubkey.to_vec()],
+    }
+}
+
+fn p2pkh_address(pubkey: &[u8]) -> String {
+    bitcoin::Address::p2pkh(bitcoin::PubkeyHash::hash(pubkey), bitcoin::Network::Bitcoin).to_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct PubkeyOutputView {
+    pub tx_hash: String,
+    pub vout: u16,
+    pub height: u32,
+    pub amount: u64,
+    pub script_type: &'static str,
+    /// key the output was found under
+    pub pubkey: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PubkeyOutputs {
+    /// P2PKH addresses of the compressed and uncompressed key
+    pub addresses: Vec<String>,
+    pub outputs: Vec<PubkeyOutputView>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LimitParams {
+    pub limit: Option<usize>,
+}
+
+fn output_views(read_tx: &ReadTransaction, mut found: Vec<(TransactionPointer, Option<Vec<u8>>)>, limit: usize) -> Result<Vec<PubkeyOutputView>, AppError> {
+    found.sort_by_key(|(pointer, _)| (pointer.parent.parent.0, pointer.parent.index, pointer.index));
+    found.dedup_by(|(a, _), (b, _)| a == b);
+    let mut views = Vec::with_capacity(found.len().min(limit));
+    for (pointer, pubkey) in found.into_iter().take(limit) {
+        let (Some(utxo), Some(tx)) = (Utxo::get(read_tx, &pointer)?, Transaction::get(read_tx, &pointer.parent)?) else {
+            continue;
+        };
+        views.push(PubkeyOutputView {
+            tx_hash: tx.hash.0.to_lower_hex_string(),
+            vout: pointer.index,
+            height: pointer.parent.parent.0,
+            amount: utxo.amount,
+            script_type: utxo.script_type.name(),
+            pubkey: pubkey.map(|pubkey| pubkey.to_lower_hex_string()),
+        });
+    }
+    Ok(views)
+}
+
+/// P2PK and multisig outputs of a key in either serialization together with P2PKH outputs paying to the key
+pub fn outputs_of_pubkey(read_tx: &ReadTransaction, pubkey: &[u8], limit: usize) -> Result<PubkeyOutputs, AppError> {
+    let forms = key_forms(pubkey);
+    let addresses: Vec<String> = forms.iter().map(|form| p2pkh_address(form)).collect();
+    let mut found = Vec::new();
+    for form in &forms {
+        for id in UtxoPubkey::get_ids_by_pubkey(read_tx, &Pubkey(form.clone()))? {
+            found.push((id.parent, Some(form.clone())));
+        }
+    }
+    for address in &addresses {
+        for id in UtxoAddress::get_ids_by_address(read_tx, &Address(address.as_bytes().to_vec()))? {
+            found.push((id, None));
+        }
+    }
+    Ok(PubkeyOutputs { addresses, outputs: output_views(read_tx, found, limit)? })
+}
+
+/// P2PK and multisig outputs of the key behind a P2PKH address, `None` for other addresses
+pub fn outputs_of_address(read_tx: &ReadTransaction, address: &bitcoin::Address, limit: usize) -> Result<Option<PubkeyOutputs>, AppError> {
+    let Some(pubkey_hash) = address.pubkey_hash() else {
+        return Ok(None);
+    };
+    let mut found = Vec::new();
+    for id in UtxoPubkey::get_ids_by_pubkey_hash(read_tx, &PubkeyHash(pubkey_hash.to_byte_array()))? {
+        let pubkey = UtxoPubkey::get(read_tx, &id)?.map(|utxo_pubkey| utxo_pubkey.pubkey.0);
+        found.push((id.parent, pubkey));
+    }
+    Ok(Some(PubkeyOutputs { addresses: vec![address.to_string()], outputs: output_views(read_tx, found, limit)? }))
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/pubkey/{pubkey}/outputs", get(pubkey_outputs))
+        .route("/address/{address}/pubkey-outputs", get(address_pubkey_outputs))
+        .layer(Extension(pool))
+}
+
+/// Outputs locked to a hex encoded public key, compressed or not
+async fn pubkey_outputs(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(pubkey): Path<String>,
+    Query(params): Query<LimitParams>,
+) -> Response {
+    let pubkey = match Vec::<u8>::from_hex(&pubkey) {
+        Ok(bytes) if matches!(bytes.len(), 33 | 65) => bytes,
+        _ => return error_response(StatusCode::BAD_REQUEST, format!("{} is not a hex encoded public key", pubkey)),
+    };
+    let limit = params.limit.unwrap_or(100).min(MAX_LIMIT);
+    json_response(pool.run(move || outputs_of_pubkey(&state.db.begin_read()?, &pubkey, limit)).await)
+}
+
+/// P2PK and bare multisig outputs which have no address of their own, found by the legacy address of their key
+async fn address_pubkey_outputs(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(address): Path<String>,
+    Query(params): Query<LimitParams>,
+) -> Response {
+    let query_address = match codec::parse_address(&address) {
+        Ok(address) => address,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let limit = params.limit.unwrap_or(100).min(MAX_LIMIT);
+    match pool.run(move || outputs_of_address(&state.db.begin_read()?, &query_address, limit)).await {
+        Ok(Ok(None)) => error_response(StatusCode::BAD_REQUEST, format!("{} is not a P2PKH address", address)),
+        result => json_response(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockPointer, Height};
+    use crate::test_support::genesis_key;
+    use bitcoin::script::Builder;
+
+    #[test]
+    fn test_p2pk_pubkeys() {
+        let key = genesis_key();
+        let script = Builder::new().push_slice(<[u8; 65]>::try_from(key.as_slice()).unwrap()).push_opcode(bitcoin::opcodes::all::OP_CHECKSIG).into_script();
+        assert_eq!(ScriptType::of(&script), ScriptType::P2PK);
+        let pointer = TransactionPointer::from_parent(BlockPointer::from_parent(Height(0), 0), 0);
+        let pubkeys = utxo_pubkeys(&script, ScriptType::P2PK, &pointer);
+        assert_eq!(pubkeys.len(), 1);
+        assert_eq!(pubkeys[0].pubkey.0, key);
+        assert_eq!(p2pkh_address(&key), "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa");
+        assert_eq!(bitcoin::PubkeyHash::from_byte_array(pubkeys[0].pubkey_hash.0), bitcoin::PubkeyHash::hash(&key));
+        // the same key is found by its compressed form too
+        let forms = key_forms(&key);
+        assert_eq!(forms.len(), 2);
+        assert_eq!(forms[1], key);
+        assert_eq!(key_forms(&forms[0]), forms);
+    }
+
+    #[test]
+    fn test_multisig_pubkeys() {
+        let keys = key_forms(&genesis_key());
+        let script = Builder::new()
+            .push_int(1)
+            .push_slice(<[u8; 33]>::try_from(keys[0].as_slice()).unwrap())
+            .push_slice(<[u8; 65]>::try_from(keys[1].as_slice()).unwrap())
+            .push_int(2)
+            .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(ScriptType::of(&script), ScriptType::MULTISIG);
+        assert_eq!(script_pubkeys(&script, ScriptType::MULTISIG), keys);
+        assert!(script_pubkeys(&script, ScriptType::NON_STANDARD).is_empty());
+    }
+}
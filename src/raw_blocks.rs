@@ -0,0 +1,207 @@
+use crate::backpressure::{error_response, QueryPool};
+use crate::btc_client::BtcClient;
+use crate::codec;
+use crate::model::{BlockHash, BlockHeader, ExplorerError, Transaction};
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hex::DisplayHex;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// default zstd level, higher ones save little on blocks at much higher cost
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Zstd compressed raw blocks by hash, kept next to the database so that raw data outlives a pruned or offline node
+pub struct RawBlocks {
+    dir: Option<PathBuf>,
+}
+
+impl RawBlocks {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(RawBlocks { dir: Some(dir) })
+    }
+
+    /// Cache that stores nothing, raw data then always comes from the node
+    pub fn disabled() -> Self {
+        RawBlocks { dir: None }
+    }
+
+    // files are spread over 256 directories by the first byte of the hash
+    fn path(dir: &std::path::Path, hash: &[u8; 32]) -> PathBuf {
+        dir.join(format!("{:02x}", hash[0])).join(format!("{}.zst", hash.to_lower_hex_string()))
+    }
+
+    /// Whether blocks are cached at all, so that they are not serialized in vain
+    pub fn enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    pub fn store(&self, hash: &[u8; 32], block: &bitcoin::Block) -> io::Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        self.store_serialized(hash, &serialize(block))
+    }
+
+    /// Stores a block in consensus encoding
+    pub fn store_serialized(&self, hash: &[u8; 32], raw: &[u8]) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let path = Self::path(dir, hash);
+        if path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(path.parent().expect("cache file has a parent"))?;
+        let compressed = zstd::encode_all(raw, COMPRESSION_LEVEL)?;
+        // renamed into place so that readers never see a partially written block
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, compressed)?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> io::Result<Option<bitcoin::Block>> {
+        let Some(dir) = &self.dir else {
+            return Ok(None);
+        };
+        let compressed = match fs::read(Self::path(dir, hash)) {
+            Ok(compressed) => compressed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let raw = zstd::decode_all(compressed.as_slice())?;
+        deserialize(&raw).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Block from the cache, otherwise from the node which then fills the cache
+    pub fn block(&self, client: &BtcClient, hash: &BlockHash) -> Result<bitcoin::Block, ExplorerError> {
+        if let Some(block) = self.get(&hash.0)? {
+            return Ok(block);
+        }
+        let block = client.get_raw_block(hash)?;
+        self.store(&hash.0, &block)?;
+        Ok(block)
+    }
+}
+
+/// Hash of the block holding a tx and the tx index within it
+pub fn tx_location(read_tx: &ReadTransaction, txid: &[u8; 32]) -> Result<Option<(BlockHash, usize)>, AppError> {
+    let Some(pointer) = Transaction::find_pointer_in_any_order(read_tx, txid)? else {
+        return Ok(None);
+    };
+    Ok(BlockHeader::get(read_tx, &pointer.parent)?.map(|header| (header.hash, pointer.index as usize)))
+}
+
+/// Serialized block, from the cache or the node
+pub fn raw_block(raw_blocks: &RawBlocks, client: &BtcClient, hash: &BlockHash) -> Result<Vec<u8>, ExplorerError> {
+    Ok(serialize(&raw_blocks.block(client, hash)?))
+}
+
+/// Serialized transaction, taken out of its block
+pub fn raw_tx(raw_blocks: &RawBlocks, client: &BtcClient, hash: &BlockHash, index: usize) -> Result<Option<Vec<u8>>, ExplorerError> {
+    Ok(raw_blocks.block(client, hash)?.txdata.get(index).map(serialize))
+}
+
+/// Cache reads and node calls block on disk or network, they run outside of the query pool so that db queries never wait on the node
+async fn fetch<T: Send + 'static>(job: impl FnOnce() -> Result<T, ExplorerError> + Send + 'static) -> Result<T, Response> {
+    match tokio::task::spawn_blocking(job).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(raw_error(e)),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+pub fn routes(raw_blocks: Arc<RawBlocks>, client: Arc<BtcClient>, pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/tx/{txid}/hex", get(tx_hex))
+        .route("/block/{hash}/raw", get(block_raw))
+        .layer(Extension(raw_blocks))
+        .layer(Extension(client))
+        .layer(Extension(pool))
+}
+
+fn raw_error(e: ExplorerError) -> Response {
+    match e {
+        ExplorerError::Rpc(e) => error_response(StatusCode::SERVICE_UNAVAILABLE, format!("Not cached and node is unavailable: {}", e)),
+        e => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+async fn tx_hex(
+    State(state): State<RequestState>,
+    Extension(raw_blocks): Extension<Arc<RawBlocks>>,
+    Extension(client): Extension<Arc<BtcClient>>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(txid): Path<String>,
+) -> Response {
+    let tx_hash = match codec::parse_hash(&txid) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let (block_hash, index) = match pool.run(move || tx_location(&state.db.begin_read()?, &tx_hash)).await {
+        Ok(Ok(Some(location))) => location,
+        Ok(Ok(None)) => return error_response(StatusCode::NOT_FOUND, format!("Transaction {} not found", txid)),
+        Ok(Err(e)) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => return e.into_response(),
+    };
+    match fetch(move || raw_tx(&raw_blocks, &client, &block_hash, index)).await {
+        Ok(Some(raw)) => ([(header::CONTENT_TYPE, "text/plain")], raw.to_lower_hex_string()).into_response(),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, format!("Transaction {} not found", txid)),
+        Err(response) => response,
+    }
+}
+
+async fn block_raw(
+    State(state): State<RequestState>,
+    Extension(raw_blocks): Extension<Arc<RawBlocks>>,
+    Extension(client): Extension<Arc<BtcClient>>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(hash): Path<String>,
+) -> Response {
+    let block_hash = match codec::parse_hash(&hash) {
+        Ok(hash) => hash,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let block_hash = match pool.run(move || BlockHeader::find_by_hash_in_any_order(&state.db.begin_read()?, &block_hash)).await {
+        Ok(Ok(Some(header))) => header.hash,
+        Ok(Ok(None)) => return error_response(StatusCode::NOT_FOUND, format!("Block {} not found", hash)),
+        Ok(Err(e)) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        Err(e) => return e.into_response(),
+    };
+    match fetch(move || raw_block(&raw_blocks, &client, &block_hash)).await {
+        Ok(raw) => ([(header::CONTENT_TYPE, "application/octet-stream")], raw).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("raw_blocks_{}", std::process::id()));
+        let raw_blocks = RawBlocks::new(dir.clone()).unwrap();
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin);
+        let hash = genesis.block_hash().to_byte_array();
+        assert!(raw_blocks.get(&hash).unwrap().is_none());
+        raw_blocks.store(&hash, &genesis).unwrap();
+        raw_blocks.store(&hash, &genesis).unwrap();
+        assert_eq!(raw_blocks.get(&hash).unwrap(), Some(genesis.clone()));
+
+        let disabled = RawBlocks::disabled();
+        disabled.store(&hash, &genesis).unwrap();
+        assert!(disabled.get(&hash).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec;
+use crate::model::{Block, BlockHash, BlockTimestamp, Height, Reorg, ReorgId, StaleBlock, StaleBlockPointer, StaleTx, StaleTxPointer, TxHash};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hex::DisplayHex;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+pub const MAX_LIMIT: usize = 100;
+
+/// Reorg log entry archiving `stale` blocks that are about to be replaced by a fork ending with `new_tip`
+pub fn archive(id: ReorgId, timestamp: u32, fork_height: Height, stale: &[Block], new_tip: &BlockHash) -> Reorg {
+    let stale_blocks = stale
+        .iter()
+        .enumerate()
+        .map(|(block_index, block)| {
+            let pointer = StaleBlockPointer::from_parent(id.clone(), block_index as u16);
+            StaleBlock {
+                id: pointer.clone(),
+                height: block.id.0,
+                hash: block.header.hash.clone(),
+                prev_hash: block.header.prev_hash.clone(),
+                timestamp: block.header.timestamp,
+                merkle_root: block.header.merkle_root.clone(),
+                bits: block.header.bits,
+                txs: block
+                    .transactions
+                    .iter()
+                    .enumerate()
+                    .map(|(tx_index, tx)| StaleTx { id: StaleTxPointer::from_parent(pointer.clone(), tx_index as u16), hash: tx.hash.clone() })
+                    .collect(),
+            }
+        })
+        .collect();
+    Reorg {
+        id,
+        timestamp: BlockTimestamp(timestamp),
+        fork_height: fork_height.0,
+        depth: stale.len() as u32,
+        old_tip: stale.last().map_or(BlockHash([0; 32]), |block| block.header.hash.clone()),
+        new_tip: new_tip.clone(),
+        stale_blocks,
+    }
+}
+
+pub fn next_id(read_tx: &ReadTransaction) -> Result<ReorgId, AppError> {
+    Ok(Reorg::last(read_tx)?.map_or(ReorgId(0), |last| ReorgId(last.id.0 + 1)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct StaleBlockView {
+    pub reorg_id: u32,
+    pub height: u32,
+    pub hash: String,
+    pub prev_hash: String,
+    pub timestamp: u32,
+    pub merkle_root: String,
+    pub tx_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txids: Option<Vec<String>>,
+}
+
+impl StaleBlockView {
+    fn new(reorg_id: &ReorgId, block: &StaleBlock, with_txids: bool) -> Self {
+        StaleBlockView {
+            reorg_id: reorg_id.0,
+            height: block.height,
+            hash: block.hash.0.to_lower_hex_string(),
+            prev_hash: block.prev_hash.0.to_lower_hex_string(),
+            timestamp: block.timestamp.0,
+            merkle_root: block.merkle_root.0.to_lower_hex_string(),
+            tx_count: block.txs.len(),
+            txids: with_txids.then(|| block.txs.iter().map(|tx| tx.hash.0.to_lower_hex_string()).collect()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReorgView {
+    pub id: u32,
+    pub timestamp: u32,
+    pub fork_height: u32,
+    pub depth: u32,
+    pub old_tip: String,
+    pub new_tip: String,
+    pub stale_blocks: Vec<StaleBlockView>,
+}
+
+impl From<&Reorg> for ReorgView {
+    fn from(reorg: &Reorg) -> Self {
+        ReorgView {
+            id: reorg.id.0,
+            timestamp: reorg.timestamp.0,
+            fork_height: reorg.fork_height,
+            depth: reorg.depth,
+            old_tip: reorg.old_tip.0.to_lower_hex_string(),
+            new_tip: reorg.new_tip.0.to_lower_hex_string(),
+            stale_blocks: reorg.stale_blocks.iter().map(|block| StaleBlockView::new(&reorg.id, block, false)).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub before: Option<u32>,
+    pub limit: Option<usize>,
+    pub txid: Option<String>,
+}
+
+/// Reorgs from the latest one backwards, starting below `before` reorg id if given
+fn latest_reorgs(read_tx: &ReadTransaction, before: Option<u32>) -> Result<impl Iterator<Item = Result<Reorg, AppError>> + '_, AppError> {
+    let until = match before {
+        Some(before) => before,
+        None => next_id(read_tx)?.0,
+    };
+    Ok((0..until).rev().filter_map(move |id| Reorg::get(read_tx, &ReorgId(id)).transpose()))
+}
+
+pub fn list_reorgs(read_tx: &ReadTransaction, params: &PageParams) -> Result<Vec<ReorgView>, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    latest_reorgs(read_tx, params.before)?.take(limit).map(|reorg| reorg.map(|reorg| ReorgView::from(&reorg))).collect()
+}
+
+/// Stale blocks that contained a transaction at one of `pointers`, latest first, each block once
+fn containing_blocks(pointers: Vec<StaleTxPointer>, before: Option<u32>, limit: usize) -> Vec<StaleBlockPointer> {
+    let blocks: BTreeSet<StaleBlockPointer> =
+        pointers.into_iter().map(|pointer| pointer.parent).filter(|block| before.is_none_or(|before| block.parent.0 < before)).collect();
+    blocks.into_iter().rev().take(limit).collect()
+}
+
+/// Stale blocks, those containing `txid` in either of `codec::byte_orders` if given
+pub fn list_stale_blocks(read_tx: &ReadTransaction, params: &PageParams, txid: Option<[u8; 32]>) -> Result<Vec<StaleBlockView>, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let mut result = Vec::new();
+    if let Some(txid) = txid {
+        let mut pointers = Vec::new();
+        for txid in codec::byte_orders(txid) {
+            pointers.extend(StaleTx::get_ids_by_hash(read_tx, &TxHash(txid))?);
+        }
+        for pointer in containing_blocks(pointers, params.before, limit) {
+            if let Some(block) = StaleBlock::get(read_tx, &pointer)? {
+                result.push(StaleBlockView::new(&pointer.parent, &block, true));
+            }
+        }
+        return Ok(result);
+    }
+    for reorg in latest_reorgs(read_tx, params.before)? {
+        let reorg = reorg?;
+        for block in reorg.stale_blocks.iter().rev() {
+            result.push(StaleBlockView::new(&reorg.id, block, true));
+            if result.len() >= limit {
+                return Ok(result);
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/reorgs", get(reorgs)).route("/stale-blocks", get(stale_blocks)).layer(Extension(pool))
+}
+
+async fn reorgs(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<PageParams>) -> Response {
+    json_response(pool.run(move || list_reorgs(&state.db.begin_read()?, &params)).await)
+}
+
+/// Stale blocks, latest first, optionally only those that contained `txid`
+async fn stale_blocks(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<PageParams>) -> Response {
+    let txid = match params.txid.as_deref().map(codec::parse_hash).transpose() {
+        Ok(txid) => txid,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    json_response(pool.run(move || list_stale_blocks(&state.db.begin_read()?, &params, txid)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive() {
+        let mut first = Block::sample();
+        first.id = Height(10);
+        let mut second = Block::sample();
+        second.id = Height(11);
+        second.header.hash = BlockHash([2; 32]);
+        second.header.prev_hash = first.header.hash.clone();
+        let reorg = archive(ReorgId(3), 1_700_000_000, Height(9), &[first.clone(), second.clone()], &BlockHash([9; 32]));
+
+        assert_eq!(reorg.id, ReorgId(3));
+        assert_eq!(reorg.timestamp, BlockTimestamp(1_700_000_000));
+        assert_eq!(reorg.fork_height, 9);
+        assert_eq!(reorg.depth, 2);
+        assert_eq!(reorg.old_tip, second.header.hash);
+        assert_eq!(reorg.new_tip, BlockHash([9; 32]));
+        for (index, (stale, block)) in reorg.stale_blocks.iter().zip([&first, &second]).enumerate() {
+            assert_eq!(stale.id, StaleBlockPointer::from_parent(ReorgId(3), index as u16));
+            assert_eq!(stale.height, block.id.0);
+            assert_eq!(stale.hash, block.header.hash);
+            assert_eq!(stale.prev_hash, block.header.prev_hash);
+            assert_eq!(stale.txs.len(), block.transactions.len());
+            for (tx_index, (stale_tx, tx)) in stale.txs.iter().zip(&block.transactions).enumerate() {
+                assert_eq!(stale_tx.id, StaleTxPointer::from_parent(stale.id.clone(), tx_index as u16));
+                assert_eq!(stale_tx.hash, tx.hash);
+            }
+        }
+    }
+
+    #[test]
+    fn test_containing_blocks() {
+        let tx = |reorg: u32, block: u16, index: u16| StaleTxPointer::from_parent(StaleBlockPointer::from_parent(ReorgId(reorg), block), index);
+        let block = |reorg: u32, block: u16| StaleBlockPointer::from_parent(ReorgId(reorg), block);
+        // the same tx mined in blocks of several reorgs, twice within reorg 4
+        let pointers = vec![tx(1, 0, 3), tx(4, 0, 1), tx(4, 1, 2), tx(7, 0, 5)];
+
+        assert_eq!(containing_blocks(pointers.clone(), None, 20), vec![block(7, 0), block(4, 1), block(4, 0), block(1, 0)]);
+        assert_eq!(containing_blocks(pointers.clone(), Some(7), 20), vec![block(4, 1), block(4, 0), block(1, 0)]);
+        assert_eq!(containing_blocks(pointers.clone(), Some(7), 2), vec![block(4, 1), block(4, 0)]);
+        assert_eq!(containing_blocks(vec![tx(2, 0, 0), tx(2, 0, 0)], None, 20), vec![block(2, 0)]);
+        assert!(containing_blocks(pointers, Some(1), 20).is_empty());
+    }
+}
@@ -0,0 +1,1415 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec;
+use crate::model::{
+    Address, Block, Height, OutPoint, Rune, RuneAmount, RuneBalance, RuneBalanceAddress, RuneBalanceId, RuneEtching, RuneHolder, RuneHolding,
+    RuneHoldingId, RuneKey, RuneMint, RuneMintId, RuneName, RuneNumber, RuneSpend, RuneSpendId, RuneTerms, TapscriptPush, Transaction,
+    TransactionPointer, Utxo,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::{OP_PUSHNUM_13, OP_RETURN};
+use bitcoin::script::Instruction;
+use bitcoin::{Network, Script, ScriptBuf};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub const MAX_LIMIT: usize = 100;
+pub const MAX_DIVISIBILITY: u8 = 38;
+pub const MAX_SPACERS: u32 = 0b00000111_11111111_11111111_11111111;
+pub const COMMIT_CONFIRMATIONS: u32 = 6;
+// names at or above this one are reserved for etchings that do not specify a name
+pub const RESERVED: u128 = 6402364363415443603228541259936211926;
+const HALVING_INTERVAL: u32 = 210_000;
+// the longest name commitment is 16 bytes of u128
+pub const MAX_COMMITMENT_LEN: usize = 16;
+
+const TAG_BODY: u128 = 0;
+const TAG_FLAGS: u128 = 2;
+const TAG_RUNE: u128 = 4;
+const TAG_PREMINE: u128 = 6;
+const TAG_CAP: u128 = 8;
+const TAG_AMOUNT: u128 = 10;
+const TAG_HEIGHT_START: u128 = 12;
+const TAG_HEIGHT_END: u128 = 14;
+const TAG_OFFSET_START: u128 = 16;
+const TAG_OFFSET_END: u128 = 18;
+const TAG_MINT: u128 = 20;
+const TAG_POINTER: u128 = 22;
+const TAG_DIVISIBILITY: u128 = 1;
+const TAG_SPACERS: u128 = 3;
+const TAG_SYMBOL: u128 = 5;
+
+const FLAG_ETCHING: u32 = 0;
+const FLAG_TERMS: u32 = 1;
+const FLAG_TURBO: u32 = 2;
+
+type OutPointKey = ([u8; 32], u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct RuneId {
+    pub block: u64,
+    pub tx: u32,
+}
+
+impl RuneId {
+    pub fn new(block: u64, tx: u32) -> Option<RuneId> {
+        (block > 0 || tx == 0).then_some(RuneId { block, tx })
+    }
+
+    /// Edict rune ids are delta encoded, tx is relative only within the same block
+    fn next(&self, block_delta: u128, tx_delta: u128) -> Option<RuneId> {
+        let block = self.block.checked_add(u64::try_from(block_delta).ok()?)?;
+        let tx = if block_delta == 0 { self.tx.checked_add(u32::try_from(tx_delta).ok()?)? } else { u32::try_from(tx_delta).ok()? };
+        RuneId::new(block, tx)
+    }
+
+    fn key(&self) -> Option<RuneKey> {
+        Some(RuneKey { block: u32::try_from(self.block).ok()?, tx: self.tx })
+    }
+}
+
+impl fmt::Display for RuneId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.block, self.tx)
+    }
+}
+
+impl FromStr for RuneId {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (block, tx) = s.split_once(':').ok_or_else(|| format!("Rune id {} is not block:tx", s))?;
+        let block = block.parse::<u64>().map_err(|e| e.to_string())?;
+        let tx = tx.parse::<u32>().map_err(|e| e.to_string())?;
+        RuneId::new(block, tx).ok_or_else(|| format!("Rune id {} is invalid", s))
+    }
+}
+
+impl RuneName {
+    /// Name letters as in modified base-26, A is 0, Z is 25 and AA is 26
+    pub fn letters(&self) -> String {
+        let mut n = self.0;
+        if n == u128::MAX {
+            return "BCGDENLQRQWDSLRUGSNLBTMFIJAV".to_string();
+        }
+        n += 1;
+        let mut letters = Vec::new();
+        while n > 0 {
+            letters.push(b'A' + ((n - 1) % 26) as u8);
+            n = (n - 1) / 26;
+        }
+        letters.reverse();
+        String::from_utf8(letters).unwrap()
+    }
+
+    /// Name with `•` spacers after letters whose bits are set in `spacers`
+    pub fn spaced(&self, spacers: u32) -> String {
+        let letters = self.letters();
+        let mut spaced = String::with_capacity(letters.len() * 2);
+        for (i, letter) in letters.chars().enumerate() {
+            spaced.push(letter);
+            if i + 1 < letters.len() && spacers & (1 << i) != 0 {
+                spaced.push('•');
+            }
+        }
+        spaced
+    }
+
+    /// Parses a name optionally spaced with `•` or `.`, spacers are ignored
+    pub fn parse(name: &str) -> Option<RuneName> {
+        let mut n: u128 = 0;
+        for (i, letter) in name.chars().filter(|c| *c != '•' && *c != '.').enumerate() {
+            if i > 0 {
+                n = n.checked_add(1)?;
+            }
+            n = n.checked_mul(26)?;
+            if !letter.is_ascii_uppercase() {
+                return None;
+            }
+            n = n.checked_add((letter as u8 - b'A') as u128)?;
+        }
+        Some(RuneName(n))
+    }
+
+    pub fn is_reserved(&self) -> bool {
+        self.0 >= RESERVED
+    }
+
+    pub fn reserved(block: u64, tx: u32) -> RuneName {
+        RuneName(RESERVED + ((block as u128) << 32 | tx as u128))
+    }
+
+    /// Little endian name without trailing zeros, it must be pushed in a tapscript of a mature taproot output
+    pub fn commitment(&self) -> Vec<u8> {
+        let bytes = self.0.to_le_bytes();
+        let end = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+        bytes[..end].to_vec()
+    }
+}
+
+/// Shortest names get unlocked gradually, 12 letters at the first rune height down to 1 a halving later
+pub fn minimum_at_height(first_height: u32, height: u32) -> RuneName {
+    let mut steps = [0u128; 28];
+    for i in 1..steps.len() {
+        steps[i] = steps[i - 1] * 26 + 26;
+    }
+    let interval = HALVING_INTERVAL / 12;
+    let offset = height.saturating_add(1);
+    if offset < first_height {
+        return RuneName(steps[12]);
+    }
+    if offset >= first_height + HALVING_INTERVAL {
+        return RuneName(0);
+    }
+    let progress = offset - first_height;
+    let length = 12 - progress / interval;
+    let end = steps[length as usize - 1];
+    let start = steps[length as usize];
+    let remainder = (progress % interval) as u128;
+    RuneName(start - ((start - end) * remainder / interval as u128))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flaw {
+    EdictOutput,
+    EdictRuneId,
+    InvalidScript,
+    Opcode,
+    SupplyOverflow,
+    TrailingIntegers,
+    TruncatedField,
+    UnrecognizedEvenTag,
+    UnrecognizedFlag,
+    Varint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edict {
+    pub id: RuneId,
+    pub amount: u128,
+    pub output: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Etching {
+    pub divisibility: Option<u8>,
+    pub premine: Option<u128>,
+    pub rune: Option<RuneName>,
+    pub spacers: Option<u32>,
+    pub symbol: Option<char>,
+    pub terms: Option<RuneTerms>,
+    pub turbo: bool,
+}
+
+impl Etching {
+    pub fn supply(&self) -> Option<u128> {
+        let premine = self.premine.unwrap_or_default();
+        let cap = self.terms.and_then(|terms| terms.cap).unwrap_or_default();
+        let amount = self.terms.and_then(|terms| terms.amount).unwrap_or_default();
+        premine.checked_add(cap.checked_mul(amount)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Runestone {
+    pub edicts: Vec<Edict>,
+    pub etching: Option<Etching>,
+    pub mint: Option<RuneId>,
+    pub pointer: Option<u32>,
+}
+
+/// Malformed runestone, it burns all runes of its inputs, yet its mint counts and its rune gets etched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cenotaph {
+    pub flaw: Flaw,
+    pub etching: Option<RuneName>,
+    pub mint: Option<RuneId>,
+}
+
+// deciphered once per transaction and never stored, boxing the runestone is not worth it
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Artifact {
+    Runestone(Runestone),
+    Cenotaph(Cenotaph),
+}
+
+impl Artifact {
+    pub fn mint(&self) -> Option<RuneId> {
+        match self {
+            Artifact::Runestone(runestone) => runestone.mint,
+            Artifact::Cenotaph(cenotaph) => cenotaph.mint,
+        }
+    }
+}
+
+/// LEB128 varint, at most 19 bytes fitting u128
+pub fn decode_varint(buffer: &[u8]) -> Option<(u128, usize)> {
+    let mut n = 0u128;
+    for (i, byte) in buffer.iter().enumerate() {
+        if i > 18 {
+            return None;
+        }
+        let value = (*byte & 0b0111_1111) as u128;
+        if i == 18 && value & 0b0111_1100 != 0 {
+            return None;
+        }
+        n |= value << (7 * i);
+        if byte & 0b1000_0000 == 0 {
+            return Some((n, i + 1));
+        }
+    }
+    None
+}
+
+pub fn encode_varint(mut n: u128, buffer: &mut Vec<u8>) {
+    while n >> 7 > 0 {
+        buffer.push((n as u8) | 0b1000_0000);
+        n >>= 7;
+    }
+    buffer.push(n as u8);
+}
+
+/// Payload of the first `OP_RETURN OP_13` output, made of data pushes only
+fn payload(outputs: &[&Script]) -> Option<Result<Vec<u8>, Flaw>> {
+    for script in outputs {
+        let mut instructions = script.instructions();
+        if instructions.next() != Some(Ok(Instruction::Op(OP_RETURN))) || instructions.next() != Some(Ok(Instruction::Op(OP_PUSHNUM_13))) {
+            continue;
+        }
+        let mut payload = Vec::new();
+        for instruction in instructions {
+            match instruction {
+                Ok(Instruction::PushBytes(push)) => payload.extend_from_slice(push.as_bytes()),
+                Ok(Instruction::Op(_)) => return Some(Err(Flaw::Opcode)),
+                Err(_) => return Some(Err(Flaw::InvalidScript)),
+            }
+        }
+        return Some(Ok(payload));
+    }
+    None
+}
+
+/// Takes `N` leading values of `tag` if `with` accepts them
+fn take<const N: usize, T>(fields: &mut HashMap<u128, VecDeque<u128>>, tag: u128, with: impl Fn([u128; N]) -> Option<T>) -> Option<T> {
+    let field = fields.get_mut(&tag)?;
+    let mut values = [0u128; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = *field.get(i)?;
+    }
+    let value = with(values)?;
+    field.drain(0..N);
+    if field.is_empty() {
+        fields.remove(&tag);
+    }
+    Some(value)
+}
+
+fn take_flag(flags: &mut u128, flag: u32) -> bool {
+    let mask = 1u128 << flag;
+    let set = *flags & mask != 0;
+    *flags &= !mask;
+    set
+}
+
+/// Runestone of a transaction with `outputs` scripts as specified by `ord`, `None` if there is none
+pub fn decipher(outputs: &[&Script]) -> Option<Artifact> {
+    let cenotaph = |flaw| Some(Artifact::Cenotaph(Cenotaph { flaw, etching: None, mint: None }));
+    let payload = match payload(outputs)? {
+        Ok(payload) => payload,
+        Err(flaw) => return cenotaph(flaw),
+    };
+    let mut integers = Vec::new();
+    let mut i = 0;
+    while i < payload.len() {
+        let Some((integer, length)) = decode_varint(&payload[i..]) else {
+            return cenotaph(Flaw::Varint);
+        };
+        integers.push(integer);
+        i += length;
+    }
+
+    let mut flaw = None;
+    let mut edicts = Vec::new();
+    let mut fields: HashMap<u128, VecDeque<u128>> = HashMap::new();
+    for i in (0..integers.len()).step_by(2) {
+        let tag = integers[i];
+        if tag == TAG_BODY {
+            let mut id = RuneId::default();
+            for chunk in integers[i + 1..].chunks(4) {
+                if chunk.len() != 4 {
+                    flaw.get_or_insert(Flaw::TrailingIntegers);
+                    break;
+                }
+                let Some(next) = id.next(chunk[0], chunk[1]) else {
+                    flaw.get_or_insert(Flaw::EdictRuneId);
+                    break;
+                };
+                match u32::try_from(chunk[3]).ok().filter(|output| (*output as usize) <= outputs.len()) {
+                    Some(output) => edicts.push(Edict { id: next, amount: chunk[2], output }),
+                    None => {
+                        flaw.get_or_insert(Flaw::EdictOutput);
+                        break;
+                    }
+                }
+                id = next;
+            }
+            break;
+        }
+        let Some(value) = integers.get(i + 1) else {
+            flaw.get_or_insert(Flaw::TruncatedField);
+            break;
+        };
+        fields.entry(tag).or_default().push_back(*value);
+    }
+
+    let mut flags = take(&mut fields, TAG_FLAGS, |[flags]| Some(flags)).unwrap_or_default();
+    let etching = take_flag(&mut flags, FLAG_ETCHING).then(|| Etching {
+        divisibility: take(&mut fields, TAG_DIVISIBILITY, |[d]| u8::try_from(d).ok().filter(|d| *d <= MAX_DIVISIBILITY)),
+        premine: take(&mut fields, TAG_PREMINE, |[premine]| Some(premine)),
+        rune: take(&mut fields, TAG_RUNE, |[rune]| Some(RuneName(rune))),
+        spacers: take(&mut fields, TAG_SPACERS, |[s]| u32::try_from(s).ok().filter(|s| *s <= MAX_SPACERS)),
+        symbol: take(&mut fields, TAG_SYMBOL, |[s]| char::from_u32(u32::try_from(s).ok()?)),
+        terms: take_flag(&mut flags, FLAG_TERMS).then(|| RuneTerms {
+            cap: take(&mut fields, TAG_CAP, |[cap]| Some(cap)),
+            height_start: take(&mut fields, TAG_HEIGHT_START, |[h]| u64::try_from(h).ok()),
+            height_end: take(&mut fields, TAG_HEIGHT_END, |[h]| u64::try_from(h).ok()),
+            amount: take(&mut fields, TAG_AMOUNT, |[amount]| Some(amount)),
+            offset_start: take(&mut fields, TAG_OFFSET_START, |[o]| u64::try_from(o).ok()),
+            offset_end: take(&mut fields, TAG_OFFSET_END, |[o]| u64::try_from(o).ok()),
+        }),
+        turbo: take_flag(&mut flags, FLAG_TURBO),
+    });
+    let mint = take(&mut fields, TAG_MINT, |[block, tx]| RuneId::new(u64::try_from(block).ok()?, u32::try_from(tx).ok()?));
+    let pointer = take(&mut fields, TAG_POINTER, |[pointer]| u32::try_from(pointer).ok().filter(|p| (*p as usize) < outputs.len()));
+
+    if etching.is_some_and(|etching| etching.supply().is_none()) {
+        flaw.get_or_insert(Flaw::SupplyOverflow);
+    }
+    if flags != 0 {
+        flaw.get_or_insert(Flaw::UnrecognizedFlag);
+    }
+    if fields.keys().any(|tag| tag % 2 == 0) {
+        flaw.get_or_insert(Flaw::UnrecognizedEvenTag);
+    }
+    match flaw {
+        Some(flaw) => Some(Artifact::Cenotaph(Cenotaph { flaw, etching: etching.and_then(|etching| etching.rune), mint })),
+        None => Some(Artifact::Runestone(Runestone { edicts, etching, mint, pointer })),
+    }
+}
+
+/// Pushes of taproot leaf scripts short enough to be a name commitment
+pub fn parse_tapscript_pushes(tx: &bitcoin::Transaction) -> Vec<TapscriptPush> {
+    let mut pushes = Vec::new();
+    for (input_index, input) in tx.input.iter().enumerate() {
+        let Some(leaf) = input.witness.taproot_leaf_script() else {
+            continue;
+        };
+        for instruction in leaf.script.instructions().map_while(Result::ok) {
+            if let Instruction::PushBytes(data) = instruction
+                && data.len() <= MAX_COMMITMENT_LEN
+            {
+                pushes.push(TapscriptPush { input: input_index as u32, data: data.as_bytes().to_vec() });
+            }
+        }
+    }
+    pushes
+}
+
+/// Rune changes of a block, stored in the same write transaction as the block
+#[derive(Default)]
+pub struct RunesBatch {
+    pub etched: Vec<Rune>,
+    pub minted: Vec<Rune>,
+    pub balances: Vec<RuneBalance>,
+    pub spends: Vec<RuneSpend>,
+    pub mints: Vec<RuneMint>,
+    pub holdings: Vec<RuneHolding>,
+}
+
+/// Ids the batch continues from
+#[derive(Default, Clone, Copy)]
+pub struct NextIds {
+    pub rune: u32,
+    pub balance: u32,
+    pub spend: u32,
+    pub mint: u32,
+    pub holding: u32,
+}
+
+/// Stored runes state a block is indexed on top of
+pub trait RuneState {
+    fn next_ids(&self) -> Result<NextIds, AppError>;
+    fn rune(&self, key: &RuneKey) -> Result<Option<Rune>, AppError>;
+    fn rune_by_number(&self, number: u32) -> Result<Option<Rune>, AppError>;
+    fn name_exists(&self, name: &RuneName) -> Result<bool, AppError>;
+    fn unspent_balances(&self, outpoint: &OutPoint) -> Result<Vec<RuneBalance>, AppError>;
+    /// Amount of the latest holding snapshot, 0 if there is none
+    fn holding(&self, holder: &RuneHolder) -> Result<u128, AppError>;
+    /// Height of the block and script of a stored output
+    fn output(&self, outpoint: &OutPoint) -> Result<Option<(u32, ScriptBuf)>, AppError>;
+}
+
+impl RuneState for ReadTransaction {
+    fn next_ids(&self) -> Result<NextIds, AppError> {
+        Ok(NextIds {
+            rune: Rune::last(self)?.map_or(0, |last| last.id.0 + 1),
+            balance: RuneBalance::last(self)?.map_or(0, |last| last.id.0 + 1),
+            spend: RuneSpend::last(self)?.map_or(0, |last| last.id.0 + 1),
+            mint: RuneMint::last(self)?.map_or(0, |last| last.id.0 + 1),
+            holding: RuneHolding::last(self)?.map_or(0, |last| last.id.0 + 1),
+        })
+    }
+
+    fn rune(&self, key: &RuneKey) -> Result<Option<Rune>, AppError> {
+        match Rune::get_ids_by_rune_id(self, key)?.into_iter().next() {
+            Some(number) => Rune::get(self, &number),
+            None => Ok(None),
+        }
+    }
+
+    fn rune_by_number(&self, number: u32) -> Result<Option<Rune>, AppError> {
+        Rune::get(self, &RuneNumber(number))
+    }
+
+    fn name_exists(&self, name: &RuneName) -> Result<bool, AppError> {
+        Ok(!Rune::get_ids_by_name(self, name)?.is_empty())
+    }
+
+    fn unspent_balances(&self, outpoint: &OutPoint) -> Result<Vec<RuneBalance>, AppError> {
+        unspent_balances(self, outpoint)
+    }
+
+    fn holding(&self, holder: &RuneHolder) -> Result<u128, AppError> {
+        let latest = match RuneHolding::get_ids_by_holder(self, holder)?.into_iter().max() {
+            Some(id) => RuneHolding::get(self, &id)?,
+            None => None,
+        };
+        Ok(latest.map_or(0, |holding| holding.amount.0))
+    }
+
+    fn output(&self, outpoint: &OutPoint) -> Result<Option<(u32, ScriptBuf)>, AppError> {
+        let Some(tx_pointer) = Transaction::get_ids_by_hash(self, &outpoint.tx_hash)?.into_iter().next() else {
+            return Ok(None);
+        };
+        let height = tx_pointer.parent.0;
+        let utxo = Utxo::get(self, &TransactionPointer::from_parent(tx_pointer, outpoint.vout as u16))?;
+        Ok(utxo.map(|utxo| (height, utxo.script_hash.to_script())))
+    }
+}
+
+// address of an output and its rune balances
+type OutputBalances = (Option<Address>, Vec<(u32, u128)>);
+
+/// Runes state of a block being indexed, on top of what is already stored
+struct BlockState<'a, S: RuneState> {
+    stored: &'a S,
+    height: u32,
+    next: NextIds,
+    batch: RunesBatch,
+    runes: HashMap<RuneKey, Rune>,
+    minted: HashSet<RuneKey>,
+    balances: HashMap<OutPointKey, OutputBalances>,
+    holdings: HashMap<RuneHolder, u128>,
+    // holders in order of their first change, so that snapshot ids follow the block
+    changed_holdings: Vec<RuneHolder>,
+}
+
+impl<'a, S: RuneState> BlockState<'a, S> {
+    fn load(stored: &'a S, height: u32) -> Result<Self, AppError> {
+        Ok(BlockState {
+            stored,
+            height,
+            next: stored.next_ids()?,
+            batch: RunesBatch::default(),
+            runes: HashMap::new(),
+            minted: HashSet::new(),
+            balances: HashMap::new(),
+            holdings: HashMap::new(),
+            changed_holdings: Vec::new(),
+        })
+    }
+
+    fn rune(&mut self, id: &RuneId) -> Result<Option<&mut Rune>, AppError> {
+        let Some(key) = id.key() else {
+            return Ok(None);
+        };
+        if !self.runes.contains_key(&key) {
+            match self.stored.rune(&key)? {
+                Some(rune) => self.runes.insert(key, rune),
+                None => return Ok(None),
+            };
+        }
+        Ok(self.runes.get_mut(&key))
+    }
+
+    fn name_exists(&self, name: &RuneName) -> Result<bool, AppError> {
+        Ok(self.runes.values().any(|rune| rune.name == *name) || self.stored.name_exists(name)?)
+    }
+
+    fn holding(&mut self, holder: RuneHolder) -> Result<&mut u128, AppError> {
+        Ok(match self.holdings.entry(holder) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.changed_holdings.push(entry.key().clone());
+                let amount = self.stored.holding(entry.key())?;
+                entry.insert(amount)
+            }
+        })
+    }
+
+    /// Unspent balances of spent `outpoint`, they become unallocated runes of the spending transaction
+    fn spend(&mut self, outpoint: OutPoint) -> Result<Vec<(u32, u128)>, AppError> {
+        let (address, spent) = match self.balances.remove(&(outpoint.tx_hash.0, outpoint.vout)) {
+            Some(spent) => spent,
+            None => {
+                let balances = self.stored.unspent_balances(&outpoint)?;
+                let address = balances.first().and_then(|balance| balance.address.as_ref()).map(|address| address.address.clone());
+                (address, balances.into_iter().map(|balance| (balance.rune, balance.amount.0)).collect())
+            }
+        };
+        if !spent.is_empty() {
+            self.batch.spends.push(RuneSpend { id: RuneSpendId(self.next.spend), outpoint, height: self.height });
+            self.next.spend += 1;
+        }
+        if let Some(address) = address {
+            for (rune, amount) in &spent {
+                let holding = self.holding(RuneHolder { rune: *rune, address: address.0.clone() })?;
+                *holding = holding.saturating_sub(*amount);
+            }
+        }
+        Ok(spent)
+    }
+
+    fn mint(&mut self, id: &RuneId) -> Result<Option<(u32, u128)>, AppError> {
+        let height = self.height;
+        let Some(rune) = self.rune(id)? else {
+            return Ok(None);
+        };
+        let Some(amount) = mintable(rune, height) else {
+            return Ok(None);
+        };
+        rune.mints.0 += 1;
+        let (number, key) = (rune.id.0, rune.rune_id);
+        self.minted.insert(key);
+        self.batch.mints.push(RuneMint { id: RuneMintId(self.next.mint), rune: number, height });
+        self.next.mint += 1;
+        Ok(Some((number, amount)))
+    }
+
+    fn allocate(&mut self, tx: &Transaction, vout: u32, balances: BTreeMap<u32, u128>) -> Result<(), AppError> {
+        let outpoint = OutPoint { tx_hash: tx.hash.clone(), vout };
+        let address = tx.utxos[vout as usize].address.as_ref().map(|address| address.address.clone());
+        for (rune, amount) in &balances {
+            let id = RuneBalanceId(self.next.balance);
+            self.batch.balances.push(RuneBalance {
+                id: id.clone(),
+                outpoint: outpoint.clone(),
+                rune: *rune,
+                amount: RuneAmount(*amount),
+                address: address.clone().map(|address| RuneBalanceAddress { id, address }),
+                height: self.height,
+            });
+            self.next.balance += 1;
+            if let Some(address) = &address {
+                *self.holding(RuneHolder { rune: *rune, address: address.0.clone() })? += *amount;
+            }
+        }
+        self.balances.insert((tx.hash.0, vout), (address, balances.into_iter().collect()));
+        Ok(())
+    }
+
+    fn finish(mut self) -> RunesBatch {
+        for (key, rune) in self.runes {
+            if rune.height == self.height {
+                self.batch.etched.push(rune);
+            } else if self.minted.contains(&key) {
+                self.batch.minted.push(rune);
+            }
+        }
+        self.batch.etched.sort_by_key(|rune| rune.id.0);
+        for holder in self.changed_holdings {
+            let amount = self.holdings[&holder];
+            self.batch.holdings.push(RuneHolding {
+                id: RuneHoldingId(self.next.holding),
+                rune: holder.rune,
+                address: Address(holder.address.clone()),
+                holder,
+                amount: RuneAmount(amount),
+                height: self.height,
+            });
+            self.next.holding += 1;
+        }
+        self.batch
+    }
+}
+
+/// Mint amount if `rune` is mintable at `height`, within its cap and height and offset windows
+fn mintable(rune: &Rune, height: u32) -> Option<u128> {
+    let terms = rune.etching.terms?;
+    let block = rune.rune_id.block as u64;
+    let relative_start = terms.offset_start.map(|offset| block.saturating_add(offset));
+    let start = match (relative_start, terms.height_start) {
+        (Some(relative), Some(absolute)) => Some(relative.max(absolute)),
+        (relative, absolute) => relative.or(absolute),
+    };
+    let relative_end = terms.offset_end.map(|offset| block.saturating_add(offset));
+    let end = match (relative_end, terms.height_end) {
+        (Some(relative), Some(absolute)) => Some(relative.min(absolute)),
+        (relative, absolute) => relative.or(absolute),
+    };
+    let height = height as u64;
+    if start.is_some_and(|start| height < start) || end.is_some_and(|end| height >= end) || rune.mints.0 >= terms.cap.unwrap_or_default() {
+        return None;
+    }
+    Some(terms.amount.unwrap_or_default())
+}
+
+/// Indexes runes like `ord` does, balances are kept per output and burned runes are dropped
+pub struct Runes {
+    first_height: u32,
+}
+
+impl Runes {
+    pub fn new(network: Network) -> Self {
+        let first_height = match network {
+            Network::Bitcoin => HALVING_INTERVAL * 4,
+            Network::Testnet => HALVING_INTERVAL * 12,
+            _ => 0,
+        };
+        Runes { first_height }
+    }
+
+    pub fn index_block(&self, stored: &impl RuneState, block: &Block) -> Result<RunesBatch, AppError> {
+        if block.id.0 < self.first_height {
+            return Ok(RunesBatch::default());
+        }
+        let mut state = BlockState::load(stored, block.id.0)?;
+        for (tx_index, tx) in block.transactions.iter().enumerate() {
+            self.index_tx(&mut state, tx_index as u32, tx)?;
+        }
+        Ok(state.finish())
+    }
+
+    fn index_tx<S: RuneState>(&self, state: &mut BlockState<S>, tx_index: u32, tx: &Transaction) -> Result<(), AppError> {
+        let scripts: Vec<ScriptBuf> = tx.utxos.iter().map(|utxo| utxo.script_hash.to_script()).collect();
+        let artifact = decipher(&scripts.iter().map(ScriptBuf::as_script).collect::<Vec<_>>());
+        let mut unallocated: BTreeMap<u32, u128> = BTreeMap::new();
+        for input in &tx.transient_inputs {
+            for (rune, amount) in state.spend(OutPoint { tx_hash: input.tx_hash.clone(), vout: input.index })? {
+                *unallocated.entry(rune).or_default() += amount;
+            }
+        }
+        let mut allocated: Vec<BTreeMap<u32, u128>> = vec![BTreeMap::new(); tx.utxos.len()];
+        let mut cenotaph = false;
+
+        if let Some(artifact) = &artifact {
+            if let Some(id) = artifact.mint()
+                && let Some((rune, amount)) = state.mint(&id)?
+            {
+                *unallocated.entry(rune).or_default() += amount;
+            }
+            let etched = self.etch(state, tx_index, tx, artifact)?;
+            match artifact {
+                Artifact::Cenotaph(_) => cenotaph = true,
+                Artifact::Runestone(runestone) => {
+                    if let (Some(rune), Some(etching)) = (etched, runestone.etching) {
+                        *unallocated.entry(rune).or_default() += etching.premine.unwrap_or_default();
+                    }
+                    for edict in &runestone.edicts {
+                        // rune id 0:0 refers to the rune etched by this very transaction
+                        let rune = if edict.id == RuneId::default() { etched } else { state.rune(&edict.id)?.map(|rune| rune.id.0) };
+                        let Some(rune) = rune else {
+                            continue;
+                        };
+                        let Some(balance) = unallocated.get_mut(&rune) else {
+                            continue;
+                        };
+                        let mut allocate = |balance: &mut u128, amount: u128, vout: usize| {
+                            if amount > 0 {
+                                *balance -= amount;
+                                *allocated[vout].entry(rune).or_default() += amount;
+                            }
+                        };
+                        if edict.output as usize == tx.utxos.len() {
+                            let destinations: Vec<usize> = (0..tx.utxos.len()).filter(|vout| !scripts[*vout].is_op_return()).collect();
+                            if destinations.is_empty() {
+                                continue;
+                            }
+                            if edict.amount == 0 {
+                                // balance is split evenly, the first outputs get the remainder
+                                let amount = *balance / destinations.len() as u128;
+                                let remainder = (*balance % destinations.len() as u128) as usize;
+                                for (i, vout) in destinations.iter().enumerate() {
+                                    allocate(balance, if i < remainder { amount + 1 } else { amount }, *vout);
+                                }
+                            } else {
+                                for vout in destinations {
+                                    let amount = edict.amount.min(*balance);
+                                    allocate(balance, amount, vout);
+                                }
+                            }
+                        } else {
+                            let amount = if edict.amount == 0 { *balance } else { edict.amount.min(*balance) };
+                            allocate(balance, amount, edict.output as usize);
+                        }
+                    }
+                }
+            }
+        }
+
+        // cenotaphs burn everything, otherwise the rest goes to pointer or the first non OP_RETURN output
+        if !cenotaph {
+            let pointer = match &artifact {
+                Some(Artifact::Runestone(runestone)) => runestone.pointer.map(|pointer| pointer as usize),
+                _ => None,
+            };
+            if let Some(vout) = pointer.or_else(|| (0..tx.utxos.len()).find(|vout| !scripts[*vout].is_op_return())) {
+                for (rune, balance) in unallocated.into_iter().filter(|(_, balance)| *balance > 0) {
+                    *allocated[vout].entry(rune).or_default() += balance;
+                }
+            }
+        }
+        for (vout, balances) in allocated.into_iter().enumerate() {
+            if !balances.is_empty() && !scripts[vout].is_op_return() {
+                state.allocate(tx, vout as u32, balances)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of the rune etched by `tx`, `None` if there is no etching or its name is not available
+    fn etch<S: RuneState>(&self, state: &mut BlockState<S>, tx_index: u32, tx: &Transaction, artifact: &Artifact) -> Result<Option<u32>, AppError> {
+        let (name, etching) = match artifact {
+            Artifact::Runestone(Runestone { etching: Some(etching), .. }) => (etching.rune, *etching),
+            Artifact::Cenotaph(Cenotaph { etching: Some(name), .. }) => (Some(*name), Etching::default()),
+            _ => return Ok(None),
+        };
+        let name = match name {
+            Some(name) => {
+                if name.0 < minimum_at_height(self.first_height, state.height).0
+                    || name.is_reserved()
+                    || state.name_exists(&name)?
+                    || !commits_to_rune(state.stored, state.height, tx, &name)?
+                {
+                    return Ok(None);
+                }
+                name
+            }
+            None => RuneName::reserved(state.height as u64, tx_index),
+        };
+        let number = state.next.rune;
+        state.next.rune += 1;
+        let rune_id = RuneKey { block: state.height, tx: tx_index };
+        state.runes.insert(
+            rune_id,
+            Rune {
+                id: RuneNumber(number),
+                rune_id,
+                name,
+                etching: RuneEtching {
+                    divisibility: etching.divisibility.unwrap_or_default(),
+                    premine: etching.premine.unwrap_or_default(),
+                    spacers: etching.spacers.unwrap_or_default(),
+                    symbol: etching.symbol,
+                    terms: etching.terms,
+                    turbo: etching.turbo,
+                },
+                etching_tx: tx.hash.clone(),
+                height: state.height,
+                mints: RuneAmount(0),
+            },
+        );
+        Ok(Some(number))
+    }
+}
+
+/// Name commitment must be pushed in a tapscript spending a taproot output with at least 6 confirmations
+fn commits_to_rune(stored: &impl RuneState, height: u32, tx: &Transaction, name: &RuneName) -> Result<bool, AppError> {
+    let commitment = name.commitment();
+    for push in tx.tapscript_pushes.iter().filter(|push| push.data == commitment) {
+        let Some(input) = tx.transient_inputs.get(push.input as usize) else {
+            continue;
+        };
+        let Some((commit_height, script)) = stored.output(&OutPoint { tx_hash: input.tx_hash.clone(), vout: input.index })? else {
+            continue;
+        };
+        if script.is_p2tr() && height.saturating_sub(commit_height) + 1 >= COMMIT_CONFIRMATIONS {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub fn unspent_balances(read_tx: &ReadTransaction, outpoint: &OutPoint) -> Result<Vec<RuneBalance>, AppError> {
+    if !RuneSpend::get_ids_by_outpoint(read_tx, outpoint)?.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut balances = Vec::new();
+    for id in RuneBalance::get_ids_by_outpoint(read_tx, outpoint)? {
+        balances.extend(RuneBalance::get(read_tx, &id)?);
+    }
+    Ok(balances)
+}
+
+/// Rune changes of blocks at `heights` that are about to be deleted by a reorg
+#[derive(Default)]
+pub struct RunesRollback {
+    pub runes: Vec<RuneNumber>,
+    pub balances: Vec<RuneBalanceId>,
+    pub spends: Vec<RuneSpendId>,
+    pub mints: Vec<RuneMintId>,
+    pub holdings: Vec<RuneHoldingId>,
+    /// surviving runes with their mint count recounted without the deleted mints
+    pub recounted: Vec<Rune>,
+}
+
+pub fn rollback(read_tx: &ReadTransaction, heights: &[Height]) -> Result<RunesRollback, AppError> {
+    let mut rollback = RunesRollback::default();
+    let mut mints = Vec::new();
+    for height in heights {
+        rollback.runes.extend(Rune::get_ids_by_height(read_tx, &height.0)?);
+        rollback.balances.extend(RuneBalance::get_ids_by_height(read_tx, &height.0)?);
+        rollback.spends.extend(RuneSpend::get_ids_by_height(read_tx, &height.0)?);
+        rollback.holdings.extend(RuneHolding::get_ids_by_height(read_tx, &height.0)?);
+        for id in RuneMint::get_ids_by_height(read_tx, &height.0)? {
+            mints.extend(RuneMint::get(read_tx, &id)?);
+            rollback.mints.push(id);
+        }
+    }
+    rollback.recounted = recount(read_tx, &mints, &rollback.runes)?;
+    Ok(rollback)
+}
+
+/// Runes minted by deleted `mints` that survive the reorg, with their mint count lowered accordingly
+fn recount(stored: &impl RuneState, mints: &[RuneMint], deleted: &[RuneNumber]) -> Result<Vec<Rune>, AppError> {
+    let mut unminted: BTreeMap<u32, u128> = BTreeMap::new();
+    for mint in mints {
+        *unminted.entry(mint.rune).or_default() += 1;
+    }
+    let mut recounted = Vec::new();
+    for (number, count) in unminted {
+        if let Some(mut rune) = stored.rune_by_number(number)?.filter(|rune| !deleted.contains(&rune.id)) {
+            rune.mints.0 = rune.mints.0.saturating_sub(count);
+            recounted.push(rune);
+        }
+    }
+    Ok(recounted)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TermsView {
+    pub amount: Option<String>,
+    pub cap: Option<String>,
+    pub height: (Option<u64>, Option<u64>),
+    pub offset: (Option<u64>, Option<u64>),
+}
+
+/// Amounts are strings as u128 does not fit json numbers
+#[derive(Debug, Serialize)]
+pub struct RuneView {
+    pub number: u32,
+    pub id: String,
+    pub name: String,
+    pub spaced_name: String,
+    pub divisibility: u8,
+    pub symbol: Option<char>,
+    pub premine: String,
+    pub terms: Option<TermsView>,
+    pub turbo: bool,
+    pub mints: String,
+    pub etching_tx: String,
+    pub height: u32,
+}
+
+impl From<&Rune> for RuneView {
+    fn from(rune: &Rune) -> Self {
+        RuneView {
+            number: rune.id.0,
+            id: format!("{}:{}", rune.rune_id.block, rune.rune_id.tx),
+            name: rune.name.letters(),
+            spaced_name: rune.name.spaced(rune.etching.spacers),
+            divisibility: rune.etching.divisibility,
+            symbol: rune.etching.symbol,
+            premine: rune.etching.premine.to_string(),
+            terms: rune.etching.terms.map(|terms| TermsView {
+                amount: terms.amount.map(|amount| amount.to_string()),
+                cap: terms.cap.map(|cap| cap.to_string()),
+                height: (terms.height_start, terms.height_end),
+                offset: (terms.offset_start, terms.offset_end),
+            }),
+            turbo: rune.etching.turbo,
+            mints: rune.mints.0.to_string(),
+            etching_tx: bitcoin::Txid::from_byte_array(rune.etching_tx.0).to_string(),
+            height: rune.height,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HolderView {
+    pub address: String,
+    pub amount: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceView {
+    pub rune: RuneView,
+    pub amount: String,
+}
+
+/// Rune by number, `block:tx` id or optionally spaced name
+pub fn find_rune(read_tx: &ReadTransaction, id: &str) -> Result<Option<Rune>, AppError> {
+    let number = if let Ok(number) = id.parse::<u32>() {
+        Some(RuneNumber(number))
+    } else if let Some(key) = RuneId::from_str(id).ok().and_then(|id| id.key()) {
+        Rune::get_ids_by_rune_id(read_tx, &key)?.into_iter().next()
+    } else if let Some(name) = RuneName::parse(id) {
+        Rune::get_ids_by_name(read_tx, &name)?.into_iter().next()
+    } else {
+        None
+    };
+    match number {
+        Some(number) => Rune::get(read_tx, &number),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub before: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+/// Latest etched runes first, starting below `before` number if given
+pub fn list_runes(read_tx: &ReadTransaction, params: &PageParams) -> Result<Vec<RuneView>, AppError> {
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let until = match params.before {
+        Some(before) => before,
+        None => Rune::last(read_tx)?.map_or(0, |last| last.id.0 + 1),
+    };
+    let mut result = Vec::with_capacity(limit);
+    for number in (0..until).rev() {
+        if result.len() >= limit {
+            break;
+        }
+        if let Some(rune) = Rune::get(read_tx, &RuneNumber(number))? {
+            result.push(RuneView::from(&rune));
+        }
+    }
+    Ok(result)
+}
+
+/// Latest snapshots of `ids` per holder, holders that spent all of the rune are left out
+fn latest_holdings(read_tx: &ReadTransaction, ids: Vec<RuneHoldingId>) -> Result<Vec<RuneHolding>, AppError> {
+    let mut latest: HashMap<RuneHolder, RuneHolding> = HashMap::new();
+    for id in ids {
+        if let Some(holding) = RuneHolding::get(read_tx, &id)?
+            && latest.get(&holding.holder).is_none_or(|current| current.id < holding.id)
+        {
+            latest.insert(holding.holder.clone(), holding);
+        }
+    }
+    Ok(latest.into_values().filter(|holding| holding.amount.0 > 0).collect())
+}
+
+/// Addresses holding the rune in unspent outputs, largest first
+pub fn holders(read_tx: &ReadTransaction, rune: &Rune, limit: usize) -> Result<Vec<HolderView>, AppError> {
+    let mut holdings = latest_holdings(read_tx, RuneHolding::get_ids_by_rune(read_tx, &rune.id.0)?)?;
+    holdings.sort_by(|a, b| b.amount.0.cmp(&a.amount.0).then_with(|| a.address.0.cmp(&b.address.0)));
+    Ok(holdings
+        .into_iter()
+        .take(limit)
+        .map(|holding| HolderView { address: String::from_utf8_lossy(&holding.address.0).into_owned(), amount: holding.amount.0.to_string() })
+        .collect())
+}
+
+/// Unspent rune balances of `address` summed per rune
+pub fn address_balances(read_tx: &ReadTransaction, address: &str) -> Result<Vec<BalanceView>, AppError> {
+    let mut holdings = latest_holdings(read_tx, RuneHolding::get_ids_by_address(read_tx, &Address(address.as_bytes().to_vec()))?)?;
+    holdings.sort_by_key(|holding| holding.rune);
+    let mut balances = Vec::with_capacity(holdings.len());
+    for holding in holdings {
+        if let Some(rune) = Rune::get(read_tx, &RuneNumber(holding.rune))? {
+            balances.push(BalanceView { rune: RuneView::from(&rune), amount: holding.amount.0.to_string() });
+        }
+    }
+    Ok(balances)
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/runes", get(runes))
+        .route("/rune/{id}", get(rune))
+        .route("/rune/{id}/holders", get(rune_holders))
+        .route("/address/{address}/runes", get(runes_of_address))
+        .layer(Extension(pool))
+}
+
+async fn runes(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<PageParams>) -> Response {
+    json_response(pool.run(move || list_runes(&state.db.begin_read()?, &params)).await)
+}
+
+async fn rune(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(id): Path<String>) -> Response {
+    let query_id = id.clone();
+    match pool.run(move || find_rune(&state.db.begin_read()?, &query_id).map(|rune| rune.as_ref().map(RuneView::from))).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Rune {} not found", id)),
+        result => json_response(result),
+    }
+}
+
+async fn rune_holders(
+    State(state): State<RequestState>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+    Path(id): Path<String>,
+    Query(params): Query<PageParams>,
+) -> Response {
+    let query_id = id.clone();
+    let limit = params.limit.unwrap_or(20).min(MAX_LIMIT);
+    let result = pool
+        .run(move || {
+            let read_tx = state.db.begin_read()?;
+            match find_rune(&read_tx, &query_id)? {
+                Some(rune) => holders(&read_tx, &rune, limit).map(Some),
+                None => Ok(None),
+            }
+        })
+        .await;
+    match result {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Rune {} not found", id)),
+        result => json_response(result),
+    }
+}
+
+async fn runes_of_address(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Path(address): Path<String>) -> Response {
+    let address = match codec::normalize_address(&address) {
+        Ok(address) => address,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    json_response(pool.run(move || address_balances(&state.db.begin_read()?, &address)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ScriptHash, TempInputRef, TxHash, UtxoAddress};
+    use bitcoin::script::{Builder, PushBytesBuf};
+    use bitcoin::WPubkeyHash;
+
+    fn runestone_script(integers: &[u128]) -> bitcoin::ScriptBuf {
+        let mut payload = Vec::new();
+        for integer in integers {
+            encode_varint(*integer, &mut payload);
+        }
+        Builder::new().push_opcode(OP_RETURN).push_opcode(OP_PUSHNUM_13).push_slice(PushBytesBuf::try_from(payload).unwrap()).into_script()
+    }
+
+    fn decipher_integers(integers: &[u128], outputs: usize) -> Option<Artifact> {
+        let runestone = runestone_script(integers);
+        let other = bitcoin::ScriptBuf::new();
+        let mut scripts: Vec<&Script> = vec![runestone.as_script()];
+        scripts.extend((1..outputs).map(|_| other.as_script()));
+        decipher(&scripts)
+    }
+
+    fn flaw(artifact: Option<Artifact>) -> Option<Flaw> {
+        match artifact {
+            Some(Artifact::Cenotaph(cenotaph)) => Some(cenotaph.flaw),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for n in [0, 1, 127, 128, 255, 16384, u64::MAX as u128, u128::MAX] {
+            let mut buffer = Vec::new();
+            encode_varint(n, &mut buffer);
+            assert_eq!(decode_varint(&buffer), Some((n, buffer.len())));
+        }
+        assert_eq!(decode_varint(&[0x80]), None); // unterminated
+        assert_eq!(decode_varint(&[0xff; 19]), None); // overflow
+    }
+
+    #[test]
+    fn test_rune_names() {
+        for (n, name) in [(0, "A"), (25, "Z"), (26, "AA"), (27, "AB"), (701, "ZZ"), (702, "AAA"), (u128::MAX, "BCGDENLQRQWDSLRUGSNLBTMFIJAV")] {
+            assert_eq!(RuneName(n).letters(), name);
+            assert_eq!(RuneName::parse(name), Some(RuneName(n)));
+        }
+        assert_eq!(RuneName::parse("UNCOMMON•GOODS").unwrap().spaced(0b10000000), "UNCOMMON•GOODS");
+        assert_eq!(RuneName::parse("abc"), None);
+        assert_eq!(RuneName(RESERVED).letters(), "AAAAAAAAAAAAAAAAAAAAAAAAAAA");
+        assert_eq!(RuneName::reserved(840000, 1), RuneName(RESERVED + (840000u128 << 32) + 1));
+        assert_eq!(RuneName(0).commitment(), Vec::<u8>::new());
+        assert_eq!(RuneName(0x0102).commitment(), vec![0x02, 0x01]);
+    }
+
+    #[test]
+    fn test_minimum_at_height() {
+        let first = HALVING_INTERVAL * 4;
+        assert_eq!(minimum_at_height(first, 0).letters(), "AAAAAAAAAAAAA");
+        assert_eq!(minimum_at_height(first, first - 1).letters(), "AAAAAAAAAAAAA");
+        assert_eq!(minimum_at_height(first, first + 17500 - 1).letters(), "AAAAAAAAAAAA");
+        assert_eq!(minimum_at_height(first, first + HALVING_INTERVAL - 1), RuneName(0));
+    }
+
+    #[test]
+    fn test_decipher_etching_and_edicts() {
+        let name = RuneName::parse("UNCOMMONGOODS").unwrap().0;
+        let integers = [
+            TAG_FLAGS, 0b11, TAG_RUNE, name, TAG_DIVISIBILITY, 2, TAG_SYMBOL, '$' as u128, TAG_AMOUNT, 1, TAG_CAP, u128::MAX, TAG_POINTER, 1,
+            TAG_BODY, 840000, 1, 5, 1, 0, 2, 7, 2,
+        ];
+        let Some(Artifact::Runestone(runestone)) = decipher_integers(&integers, 3) else {
+            panic!("Runestone expected");
+        };
+        let etching = runestone.etching.unwrap();
+        assert_eq!(etching.rune, Some(RuneName(name)));
+        assert_eq!((etching.divisibility, etching.symbol), (Some(2), Some('$')));
+        assert_eq!(etching.terms.unwrap().cap, Some(u128::MAX));
+        assert_eq!(runestone.pointer, Some(1));
+        assert_eq!(
+            runestone.edicts,
+            vec![
+                Edict { id: RuneId { block: 840000, tx: 1 }, amount: 5, output: 1 },
+                Edict { id: RuneId { block: 840000, tx: 3 }, amount: 7, output: 2 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cenotaphs() {
+        assert_eq!(flaw(decipher_integers(&[TAG_FLAGS, 1 << 127], 1)), Some(Flaw::UnrecognizedFlag));
+        assert_eq!(flaw(decipher_integers(&[126, 0], 1)), Some(Flaw::UnrecognizedEvenTag));
+        assert_eq!(flaw(decipher_integers(&[TAG_RUNE], 1)), Some(Flaw::TruncatedField));
+        assert_eq!(flaw(decipher_integers(&[TAG_BODY, 1, 1, 1], 1)), Some(Flaw::TrailingIntegers));
+        assert_eq!(flaw(decipher_integers(&[TAG_BODY, 1, 1, 1, 2], 1)), Some(Flaw::EdictOutput));
+        assert_eq!(flaw(decipher_integers(&[TAG_BODY, 0, 1, 1, 0], 1)), Some(Flaw::EdictRuneId));
+        // premine plus cap times amount must fit u128
+        assert_eq!(flaw(decipher_integers(&[TAG_FLAGS, 0b11, TAG_PREMINE, 1, TAG_CAP, u128::MAX, TAG_AMOUNT, 1], 1)), Some(Flaw::SupplyOverflow));
+        // odd tags may be ignored, mint survives a cenotaph
+        assert!(matches!(decipher_integers(&[127, 5], 1), Some(Artifact::Runestone(_))));
+        let Some(Artifact::Cenotaph(cenotaph)) = decipher_integers(&[TAG_MINT, 1, TAG_MINT, 0, 126, 0], 1) else {
+            panic!("Cenotaph expected");
+        };
+        assert_eq!(cenotaph.mint, Some(RuneId { block: 1, tx: 0 }));
+
+        let opcode = Builder::new().push_opcode(OP_RETURN).push_opcode(OP_PUSHNUM_13).push_opcode(OP_RETURN).into_script();
+        assert_eq!(flaw(decipher(&[opcode.as_script()])), Some(Flaw::Opcode));
+        let varint = Builder::new().push_opcode(OP_RETURN).push_opcode(OP_PUSHNUM_13).push_slice([0x80]).into_script();
+        assert_eq!(flaw(decipher(&[varint.as_script()])), Some(Flaw::Varint));
+        // without the magic number it is not a runestone
+        let other = Builder::new().push_opcode(OP_RETURN).push_slice([0x00]).into_script();
+        assert_eq!(decipher(&[other.as_script()]), None);
+    }
+
+    #[test]
+    fn test_mintable() {
+        let terms = RuneTerms { amount: Some(10), cap: Some(2), height_start: Some(100), height_end: None, offset_start: None, offset_end: Some(50) };
+        let mut rune = Rune {
+            id: RuneNumber(0),
+            rune_id: RuneKey { block: 90, tx: 1 },
+            name: RuneName(0),
+            etching: RuneEtching { divisibility: 0, premine: 0, spacers: 0, symbol: None, terms: Some(terms), turbo: false },
+            etching_tx: TxHash([0; 32]),
+            height: 90,
+            mints: RuneAmount(0),
+        };
+        assert_eq!(mintable(&rune, 99), None);
+        assert_eq!(mintable(&rune, 100), Some(10));
+        assert_eq!(mintable(&rune, 140), None); // offset end is relative to etching block
+        rune.mints = RuneAmount(2);
+        assert_eq!(mintable(&rune, 100), None);
+    }
+
+    #[derive(Default)]
+    struct MemoryState {
+        runes: Vec<Rune>,
+        balances: Vec<RuneBalance>,
+        spends: Vec<RuneSpend>,
+        mints: Vec<RuneMint>,
+        holdings: Vec<RuneHolding>,
+    }
+
+    impl MemoryState {
+        fn index(&mut self, runes: &Runes, height: u32, transactions: Vec<Transaction>) {
+            let mut block = Block::sample();
+            block.id = Height(height);
+            block.transactions = transactions;
+            let batch = runes.index_block(self, &block).unwrap();
+            for rune in batch.minted {
+                self.runes.retain(|stored| stored.id != rune.id);
+                self.runes.push(rune);
+            }
+            self.runes.extend(batch.etched);
+            self.balances.extend(batch.balances);
+            self.spends.extend(batch.spends);
+            self.mints.extend(batch.mints);
+            self.holdings.extend(batch.holdings);
+        }
+
+        /// What `rollback` selects is deleted along with the stale block, surviving runes are replaced by their recount
+        fn rollback(&mut self, height: u32) {
+            let runes: Vec<RuneNumber> = self.runes.iter().filter(|rune| rune.height == height).map(|rune| rune.id.clone()).collect();
+            let mints: Vec<RuneMint> = self.mints.iter().filter(|mint| mint.height == height).cloned().collect();
+            for rune in recount(self, &mints, &runes).unwrap() {
+                self.runes.retain(|stored| stored.id != rune.id);
+                self.runes.push(rune);
+            }
+            self.runes.retain(|rune| rune.height != height);
+            self.balances.retain(|balance| balance.height != height);
+            self.spends.retain(|spend| spend.height != height);
+            self.mints.retain(|mint| mint.height != height);
+            self.holdings.retain(|holding| holding.height != height);
+        }
+
+        fn unspent(&self, hash: u8, vout: u32) -> Vec<(u32, u128)> {
+            let balances = self.unspent_balances(&OutPoint { tx_hash: TxHash([hash; 32]), vout }).unwrap();
+            balances.into_iter().map(|balance| (balance.rune, balance.amount.0)).collect()
+        }
+
+        fn held(&self, address: &str) -> u128 {
+            self.holding(&RuneHolder { rune: 0, address: address.as_bytes().to_vec() }).unwrap()
+        }
+    }
+
+    impl RuneState for MemoryState {
+        fn next_ids(&self) -> Result<NextIds, AppError> {
+            Ok(NextIds {
+                rune: self.runes.len() as u32,
+                balance: self.balances.len() as u32,
+                spend: self.spends.len() as u32,
+                mint: self.mints.len() as u32,
+                holding: self.holdings.iter().map(|holding| holding.id.0 + 1).max().unwrap_or_default(),
+            })
+        }
+
+        fn rune(&self, key: &RuneKey) -> Result<Option<Rune>, AppError> {
+            Ok(self.runes.iter().find(|rune| rune.rune_id == *key).cloned())
+        }
+
+        fn rune_by_number(&self, number: u32) -> Result<Option<Rune>, AppError> {
+            Ok(self.runes.iter().find(|rune| rune.id.0 == number).cloned())
+        }
+
+        fn name_exists(&self, name: &RuneName) -> Result<bool, AppError> {
+            Ok(self.runes.iter().any(|rune| rune.name == *name))
+        }
+
+        fn unspent_balances(&self, outpoint: &OutPoint) -> Result<Vec<RuneBalance>, AppError> {
+            if self.spends.iter().any(|spend| spend.outpoint == *outpoint) {
+                return Ok(Vec::new());
+            }
+            Ok(self.balances.iter().filter(|balance| balance.outpoint == *outpoint).cloned().collect())
+        }
+
+        fn holding(&self, holder: &RuneHolder) -> Result<u128, AppError> {
+            let latest = self.holdings.iter().filter(|holding| holding.holder == *holder).max_by_key(|holding| holding.id.0);
+            Ok(latest.map_or(0, |holding| holding.amount.0))
+        }
+
+        fn output(&self, _outpoint: &OutPoint) -> Result<Option<(u32, ScriptBuf)>, AppError> {
+            Ok(None)
+        }
+    }
+
+    /// Runestone in output 0 if given, followed by an output per address, `None` being an output without an address
+    fn tx(hash: u8, inputs: &[(u8, u32)], runestone: Option<&[u128]>, addresses: &[Option<&str>]) -> Transaction {
+        let mut tx = Block::sample().transactions[0].clone();
+        tx.hash = TxHash([hash; 32]);
+        tx.transient_inputs = inputs.iter().map(|(tx_hash, index)| TempInputRef { tx_hash: TxHash([*tx_hash; 32]), index: *index }).collect();
+        let output = |script: &Script, address: Option<&str>| Utxo {
+            script_hash: ScriptHash::from_script(script),
+            address: address.map(|address| UtxoAddress { id: TransactionPointer::default(), address: Address(address.as_bytes().to_vec()) }),
+            ..tx.utxos[0].clone()
+        };
+        let mut utxos: Vec<Utxo> = runestone.map(|integers| output(&runestone_script(integers), None)).into_iter().collect();
+        for (vout, address) in addresses.iter().enumerate() {
+            utxos.push(output(&ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([vout as u8; 20])), *address));
+        }
+        tx.utxos = utxos;
+        tx
+    }
+
+    // premine of 1000, then 2 mints of 100
+    const ETCHING: [u128; 8] = [TAG_FLAGS, 0b11, TAG_PREMINE, 1000, TAG_AMOUNT, 100, TAG_CAP, 2];
+
+    #[test]
+    fn test_index_edicts_split_and_pointer() {
+        let runes = Runes::new(Network::Regtest);
+        let mut state = MemoryState::default();
+        // 300 to bob by the edict, the rest to the first non OP_RETURN output
+        let etching = [&ETCHING[..], &[TAG_BODY, 0, 0, 300, 2]].concat();
+        state.index(&runes, 10, vec![tx(0xa1, &[], Some(&etching), &[Some("alice"), Some("bob")])]);
+        assert_eq!(state.runes[0].rune_id, RuneKey { block: 10, tx: 0 });
+        assert_eq!(state.unspent(0xa1, 1), vec![(0, 700)]);
+        assert_eq!(state.unspent(0xa1, 2), vec![(0, 300)]);
+        assert_eq!((state.held("alice"), state.held("bob")), (700, 300));
+
+        state.index(
+            &runes,
+            11,
+            vec![
+                // output equal to the number of outputs splits evenly, the first outputs get the remainder
+                tx(0xb1, &[(0xa1, 1)], Some(&[TAG_BODY, 10, 0, 0, 4]), &[Some("carol"), Some("dave"), Some("erin")]),
+                // fixed amount goes to every non OP_RETURN output, the rest to the pointer
+                tx(0xb2, &[(0xa1, 2)], Some(&[TAG_POINTER, 2, TAG_BODY, 10, 0, 50, 3]), &[Some("frank"), Some("grace")]),
+            ],
+        );
+        assert_eq!([1, 2, 3].map(|vout| state.unspent(0xb1, vout)), [vec![(0, 234)], vec![(0, 233)], vec![(0, 233)]]);
+        assert_eq!([1, 2].map(|vout| state.unspent(0xb2, vout)), [vec![(0, 50)], vec![(0, 250)]]);
+        assert!(state.unspent(0xa1, 1).is_empty());
+        assert_eq!((state.held("alice"), state.held("bob"), state.held("carol"), state.held("grace")), (0, 0, 234, 250));
+    }
+
+    #[test]
+    fn test_index_burns_mint_cap_and_rollback() {
+        let runes = Runes::new(Network::Regtest);
+        let mut state = MemoryState::default();
+        state.index(&runes, 10, vec![tx(0xa1, &[], Some(&ETCHING), &[Some("alice"), None])]);
+        assert_eq!(state.held("alice"), 1000);
+
+        let mint = [TAG_MINT, 10, TAG_MINT, 0];
+        state.index(
+            &runes,
+            11,
+            vec![
+                tx(0xc1, &[], Some(&mint), &[Some("bob")]),
+                // the output without an address holds the balance, but no address is credited
+                tx(0xc2, &[], Some(&[&mint[..], &[TAG_POINTER, 2]].concat()), &[Some("carol"), None]),
+                // over the cap
+                tx(0xc3, &[], Some(&mint), &[Some("dave")]),
+                // unrecognized even tag makes a cenotaph which burns the inputs
+                tx(0xc4, &[(0xa1, 1)], Some(&[126, 0]), &[Some("erin")]),
+            ],
+        );
+        assert_eq!(state.runes[0].mints, RuneAmount(2));
+        assert_eq!(state.unspent(0xc1, 1), vec![(0, 100)]);
+        assert_eq!(state.balances.iter().find(|balance| balance.outpoint.tx_hash == TxHash([0xc2; 32])).unwrap().address, None);
+        assert!(state.unspent(0xc3, 1).is_empty());
+        assert!(state.unspent(0xc4, 1).is_empty());
+        assert_eq!((state.held("alice"), state.held("bob"), state.held("carol"), state.held("erin")), (0, 100, 0, 0));
+
+        // an edict to the OP_RETURN output burns
+        state.index(&runes, 12, vec![tx(0xd1, &[(0xc1, 1)], Some(&[TAG_BODY, 10, 0, 0, 0]), &[Some("frank")])]);
+        assert!(state.unspent(0xd1, 1).is_empty());
+        assert_eq!((state.held("bob"), state.held("frank")), (0, 0));
+
+        state.rollback(12);
+        state.rollback(11);
+        assert_eq!(state.runes[0].mints, RuneAmount(0));
+        assert_eq!(state.unspent(0xa1, 1), vec![(0, 1000)]);
+        assert_eq!((state.held("alice"), state.held("bob")), (1000, 0));
+        // deleted runes are not recounted
+        assert!(recount(&state, &[RuneMint { id: RuneMintId(0), rune: 0, height: 11 }], &[RuneNumber(0)]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::decode::{self, DecodeRequest};
+use crate::model::{Block, ExplorerError};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::post;
+use axum::{Extension, Json};
+use bitcoin::consensus::{serialize, verify_script_with_flags};
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Amount, Network, Psbt, TxOut};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::Serialize;
+use std::sync::Arc;
+
+// libbitcoinconsensus flags, it has none for taproot so witness v1 spends pass as an unknown witness version
+const VERIFY_NONE: u32 = 0;
+const VERIFY_P2SH: u32 = 1 << 0;
+const VERIFY_DERSIG: u32 = 1 << 2;
+const VERIFY_NULLDUMMY: u32 = 1 << 4;
+const VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+const VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+const VERIFY_WITNESS: u32 = 1 << 11;
+pub const VERIFY_ALL: u32 =
+    VERIFY_P2SH | VERIFY_DERSIG | VERIFY_NULLDUMMY | VERIFY_CHECKLOCKTIMEVERIFY | VERIFY_CHECKSEQUENCEVERIFY | VERIFY_WITNESS;
+
+/// Consensus script validation of spent outputs, with the soft forks active at each height like Bitcoin Core
+pub struct ScriptVerifier {
+    // block violating BIP16 before it was enforced, verified without any flags
+    bip16_exception: Option<[u8; 32]>,
+    bip66_height: u32,
+    bip65_height: u32,
+    csv_height: u32,
+    segwit_height: u32,
+}
+
+impl ScriptVerifier {
+    pub fn new(network: Network) -> Self {
+        let exception = |hash: &str| {
+            let mut bytes: [u8; 32] = bitcoin::hex::FromHex::from_hex(hash).expect("valid block hash");
+            bytes.reverse();
+            Some(bytes)
+        };
+        match network {
+            Network::Bitcoin => ScriptVerifier {
+                bip16_exception: exception("00000000000002dc756eebf4f49723ed8d30cc28a5f108eb94b1ba88ac4f9c22"),
+                bip66_height: 363725,
+                bip65_height: 388381,
+                csv_height: 419328,
+                segwit_height: 481824,
+            },
+            Network::Testnet => ScriptVerifier {
+                bip16_exception: exception("00000000dd30457c001f4095d208cc1296b0eed002427aa599874af7a432b105"),
+                bip66_height: 330776,
+                bip65_height: 581885,
+                csv_height: 770112,
+                segwit_height: 834624,
+            },
+            // everything is active from the first block
+            _ => ScriptVerifier { bip16_exception: None, bip66_height: 1, bip65_height: 1, csv_height: 1, segwit_height: 1 },
+        }
+    }
+
+    /// Flags of Bitcoin Core's `GetBlockScriptFlags` without taproot
+    pub fn flags(&self, height: u32, block_hash: &[u8; 32]) -> u32 {
+        if self.bip16_exception.as_ref() == Some(block_hash) {
+            return VERIFY_NONE;
+        }
+        let mut flags = VERIFY_P2SH | VERIFY_WITNESS;
+        if height >= self.bip66_height {
+            flags |= VERIFY_DERSIG;
+        }
+        if height >= self.bip65_height {
+            flags |= VERIFY_CHECKLOCKTIMEVERIFY;
+        }
+        if height >= self.csv_height {
+            flags |= VERIFY_CHECKSEQUENCEVERIFY;
+        }
+        if height >= self.segwit_height {
+            flags |= VERIFY_NULLDUMMY;
+        }
+        flags
+    }
+
+    /// Verifies inputs of all but the coinbase transaction against `prevouts` resolved from the database,
+    /// so a failure means either an invalid block or inconsistent prevout data
+    pub fn verify_block(&self, block: &Block, prevouts: &[Vec<Option<TxOut>>]) -> Result<(), ExplorerError> {
+        let flags = self.flags(block.id.0, &block.header.hash.0);
+        for (tx, tx_prevouts) in block.transactions.iter().zip(prevouts).skip(1) {
+            for (input, prevout) in tx_prevouts.iter().enumerate() {
+                let failure = |error: String| ExplorerError::ScriptVerification {
+                    height: block.id.0,
+                    txid: bitcoin::Txid::from_byte_array(tx.hash.0).to_string(),
+                    input,
+                    error,
+                };
+                let prevout = prevout.as_ref().ok_or_else(|| failure("prevout is not indexed".to_string()))?;
+                verify_input(&tx.raw, input, prevout, flags).map_err(failure)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn verify_input(raw_tx: &[u8], input: usize, prevout: &TxOut, flags: u32) -> Result<(), String> {
+    verify_script_with_flags(&prevout.script_pubkey, input, prevout.value, raw_tx, flags).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputVerification {
+    pub vin: usize,
+    /// `valid`, `invalid`, `unknown_prevout` or `unchecked_taproot`
+    pub status: &'static str,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxVerification {
+    pub tx_hash: String,
+    /// no input is invalid or spends an output that is not indexed
+    pub valid: bool,
+    pub inputs: Vec<InputVerification>,
+}
+
+/// Verifies every input of an unbroadcast transaction with all consensus flags against indexed prevouts
+pub fn verify_tx(read_tx: &ReadTransaction, tx: &bitcoin::Transaction) -> Result<TxVerification, AppError> {
+    let raw_tx = serialize(tx);
+    let mut inputs = Vec::with_capacity(tx.input.len());
+    for (vin, tx_in) in tx.input.iter().enumerate() {
+        let verification = match decode::find_prevout(read_tx, &tx_in.previous_output)? {
+            None => InputVerification { vin, status: "unknown_prevout", error: None },
+            Some((_, utxo)) => {
+                let prevout = TxOut { value: Amount::from_sat(utxo.amount), script_pubkey: utxo.script_hash.to_script() };
+                match verify_input(&raw_tx, vin, &prevout, VERIFY_ALL) {
+                    Ok(()) if prevout.script_pubkey.is_p2tr() => InputVerification { vin, status: "unchecked_taproot", error: None },
+                    Ok(()) => InputVerification { vin, status: "valid", error: None },
+                    Err(e) => InputVerification { vin, status: "invalid", error: Some(e) },
+                }
+            }
+        };
+        inputs.push(verification);
+    }
+    Ok(TxVerification {
+        tx_hash: tx.compute_txid().to_byte_array().to_lower_hex_string(),
+        valid: inputs.iter().all(|input| input.status == "valid" || input.status == "unchecked_taproot"),
+        inputs,
+    })
+}
+
+/// Transaction of a finalized PSBT, whose final scripts replace the empty ones of its unsigned transaction
+pub fn finalized_tx(psbt: Psbt) -> Result<bitcoin::Transaction, String> {
+    if psbt.inputs.iter().any(|input| input.final_script_sig.is_none() && input.final_script_witness.is_none()) {
+        return Err("PSBT has no final scripts, finalize it first".to_string());
+    }
+    Ok(psbt.extract_tx_unchecked_fee_rate())
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/tx/verify", post(verify)).layer(Extension(pool))
+}
+
+async fn verify(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Json(request): Json<DecodeRequest>) -> Response {
+    let tx = match decode::parse(&request.tx) {
+        Ok((tx, None)) => tx,
+        Ok((_, Some(psbt))) => match finalized_tx(psbt) {
+            Ok(tx) => tx,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+        },
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    json_response(pool.run(move || verify_tx(&state.db.begin_read()?, &tx)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TX_F4184;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::hex::FromHex;
+
+    // coinbase output of block 9 that the first transaction between people spends
+    const BLOCK_9_OUTPUT: &str = "410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac";
+
+    #[test]
+    fn test_verify_input() {
+        let raw_tx = Vec::<u8>::from_hex(TX_F4184).unwrap();
+        let prevout = TxOut { value: Amount::from_sat(5_000_000_000), script_pubkey: bitcoin::ScriptBuf::from_hex(BLOCK_9_OUTPUT).unwrap() };
+        let verifier = ScriptVerifier::new(Network::Bitcoin);
+        assert!(verify_input(&raw_tx, 0, &prevout, verifier.flags(170, &[0; 32])).is_ok());
+
+        let mut tampered: bitcoin::Transaction = deserialize(&raw_tx).unwrap();
+        tampered.output[0].value = Amount::from_sat(1);
+        assert!(verify_input(&serialize(&tampered), 0, &prevout, VERIFY_ALL).is_err());
+    }
+
+    #[test]
+    fn test_finalized_psbt() {
+        let tx: bitcoin::Transaction = deserialize(&Vec::<u8>::from_hex(TX_F4184).unwrap()).unwrap();
+        let mut unsigned = tx.clone();
+        unsigned.input[0].script_sig = bitcoin::ScriptBuf::new();
+        let mut psbt = Psbt::from_unsigned_tx(unsigned).unwrap();
+        assert!(finalized_tx(psbt.clone()).is_err());
+        psbt.inputs[0].final_script_sig = Some(tx.input[0].script_sig.clone());
+        assert_eq!(finalized_tx(psbt), Ok(tx));
+    }
+
+    #[test]
+    fn test_flags_follow_activations() {
+        let verifier = ScriptVerifier::new(Network::Bitcoin);
+        assert_eq!(verifier.flags(170, &[0; 32]), VERIFY_P2SH | VERIFY_WITNESS);
+        assert_eq!(verifier.flags(170060, &verifier.bip16_exception.unwrap()), VERIFY_NONE);
+        assert_eq!(verifier.flags(400000, &[0; 32]) & VERIFY_CHECKSEQUENCEVERIFY, 0);
+        assert_eq!(verifier.flags(481824, &[0; 32]), VERIFY_ALL);
+        assert_eq!(ScriptVerifier::new(Network::Regtest).flags(1, &[0; 32]), VERIFY_ALL);
+    }
+}
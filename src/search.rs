@@ -0,0 +1,93 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::codec::{self, SearchQuery};
+use crate::model::{Address, BlockHash, BlockHeader, Height, ScriptHash, ScriptType, Transaction, TxHash, Utxo, UtxoAddress};
+use crate::pubkeys;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hex::DisplayHex;
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+}
+
+/// What a query was found to be, tagged by `type` so that clients know where to go with it
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchResult {
+    Block { height: u32, hash: String },
+    Transaction { tx_hash: String, height: u32, index: u16 },
+    Address { address: String },
+    /// public key with the legacy addresses it may be paid to as well
+    Pubkey { pubkey: String, addresses: Vec<String> },
+    Script { script: String, script_type: &'static str, address: Option<String> },
+}
+
+fn block_of_hash(read_tx: &ReadTransaction, hash: [u8; 32]) -> Result<Option<SearchResult>, AppError> {
+    if let Some(header) = BlockHeader::find_by_hash(read_tx, &BlockHash(hash))? {
+        return Ok(Some(SearchResult::Block { height: header.id.0, hash: hash.to_lower_hex_string() }));
+    }
+    Ok(Transaction::get_ids_by_hash(read_tx, &TxHash(hash))?.into_iter().next().map(|pointer| SearchResult::Transaction {
+        tx_hash: hash.to_lower_hex_string(),
+        height: pointer.parent.0,
+        index: pointer.index,
+    }))
+}
+
+/// Indexed entity a query refers to, 32 byte hashes are looked up in both of `codec::byte_orders`
+pub fn search(read_tx: &ReadTransaction, query: SearchQuery) -> Result<Option<SearchResult>, AppError> {
+    match query {
+        SearchQuery::Height(height) => {
+            Ok(BlockHeader::get(read_tx, &Height(height))?.map(|header| SearchResult::Block { height, hash: header.hash.0.to_lower_hex_string() }))
+        }
+        SearchQuery::Hash(hash) => {
+            for hash in codec::byte_orders(hash) {
+                if let Some(result) = block_of_hash(read_tx, hash)? {
+                    return Ok(Some(result));
+                }
+            }
+            Ok(None)
+        }
+        SearchQuery::Address(address) => {
+            let found = !UtxoAddress::get_ids_by_address(read_tx, &Address(address.as_bytes().to_vec()))?.is_empty();
+            Ok(found.then_some(SearchResult::Address { address }))
+        }
+        SearchQuery::Pubkey(pubkey) => {
+            let outputs = pubkeys::outputs_of_pubkey(read_tx, &pubkey, 1)?;
+            Ok((!outputs.outputs.is_empty()).then(|| SearchResult::Pubkey { pubkey: pubkey.to_lower_hex_string(), addresses: outputs.addresses }))
+        }
+        SearchQuery::Script(script) => {
+            if Utxo::get_ids_by_script_hash(read_tx, &ScriptHash::from_script(&script))?.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(SearchResult::Script {
+                script: script.as_bytes().to_lower_hex_string(),
+                script_type: ScriptType::of(&script).name(),
+                address: bitcoin::Address::from_script(&script, bitcoin::Network::Bitcoin).ok().map(|address| address.to_string()),
+            }))
+        }
+    }
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/search", get(search_handler)).layer(Extension(pool))
+}
+
+/// Height, block hash, txid, address, output script hex or public key hex
+async fn search_handler(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<SearchParams>) -> Response {
+    let query = match codec::parse_search(&params.q) {
+        Ok(query) => query,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    match pool.run(move || search(&state.db.begin_read()?, query)).await {
+        Ok(Ok(None)) => error_response(StatusCode::NOT_FOUND, format!("Nothing indexed matches {}", params.q.trim())),
+        result => json_response(result),
+    }
+}
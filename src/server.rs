@@ -0,0 +1,33 @@
+use crate::backpressure::{self, Guard, QueryPool};
+use crate::finality::{self, Finality};
+use crate::telemetry;
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::Router;
+use redbit::*;
+use std::sync::Arc;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::watch;
+
+/// Whole http api. Redbit generated explorer routes run on `QueryPool` like the `extras` handlers do on their own,
+/// and every route, generated or not, gets the request timeout, latency feedback to `WriteThrottle`, http metrics
+/// and the final height header, except for health and metrics.
+pub fn app(state: RequestState, extras: OpenApiRouter<RequestState>, pool: Arc<QueryPool>, guard: Guard, finality: Arc<Finality>) -> Router {
+    // extras are merged afterwards, their handlers already use the pool and would wait on it from its own threads
+    let explorer = build_router(state.clone(), None, None).layer(from_fn_with_state(pool, backpressure::pooled));
+    let extras: Router<RequestState> = extras.into();
+    explorer
+        .merge(extras.with_state(state))
+        .layer(from_fn_with_state(finality, finality::final_height_header))
+        .layer(from_fn_with_state(guard, backpressure::guard))
+        .layer(from_fn(telemetry::track_http))
+}
+
+pub async fn serve(app: Router, bind_address: impl ToSocketAddrs, mut shutdown: watch::Receiver<bool>) {
+    let listener = TcpListener::bind(bind_address).await.expect("Failed to bind http server");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        })
+        .await
+        .expect("Http server failed");
+}
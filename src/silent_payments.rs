@@ -0,0 +1,330 @@
+use crate::backpressure::{error_response, json_response, QueryPool};
+use crate::model::{Block, Height, SpInput, SpTweak, SpTweakId, TempInputRef, TweakPoint};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hashes::{hash160, sha256, Hash, HashEngine};
+use bitcoin::hex::DisplayHex;
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, Verification};
+use bitcoin::{Script, ScriptBuf, TxOut, WitnessVersion};
+use redbit::redb::ReadTransaction;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const MAX_BLOCKS: u32 = 144;
+// x coordinate of the NUMS point H, taproot outputs committing to it as internal key have no key path
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+const ANNEX_PREFIX: u8 = 0x50;
+
+/// Inputs of a transaction that can pay to silent payment addresses, ie. it has a taproot output, empty otherwise
+pub fn sp_inputs(tx: &bitcoin::Transaction) -> Vec<SpInput> {
+    if tx.is_coinbase() || !tx.output.iter().any(|output| output.script_pubkey.is_p2tr()) {
+        return Vec::new();
+    }
+    tx.input
+        .iter()
+        .map(|input| SpInput {
+            script_sig: input.script_sig.to_bytes(),
+            witness_key: input.witness.last().filter(|item| item.len() == 33 && matches!(item[0], 0x02 | 0x03)).map_or_else(Vec::new, <[u8]>::to_vec),
+            nums_script_path: is_nums_script_path(&input.witness),
+        })
+        .collect()
+}
+
+fn is_nums_script_path(witness: &bitcoin::Witness) -> bool {
+    let mut items: Vec<&[u8]> = witness.iter().collect();
+    if items.len() > 1 && items.last().is_some_and(|item| item.first() == Some(&ANNEX_PREFIX)) {
+        items.pop();
+    }
+    items.len() > 1 && items.last().is_some_and(|control| control.len() >= 33 && control[1..33] == NUMS_H)
+}
+
+fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(data);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Public key of an input spending `prevout` if its type is eligible, only compressed keys are
+fn input_key(input: &SpInput, prevout: &Script) -> Option<PublicKey> {
+    let key = if prevout.is_p2pkh() {
+        // the key is the last push matching the hash, whatever else a malleated script_sig pushes
+        let hash = &prevout.as_bytes()[3..23];
+        let pushes: Vec<Vec<u8>> = Script::from_bytes(&input.script_sig)
+            .instructions()
+            .map_while(Result::ok)
+            .filter_map(|instruction| match instruction {
+                Instruction::PushBytes(push) => Some(push.as_bytes().to_vec()),
+                Instruction::Op(_) => None,
+            })
+            .collect();
+        pushes.into_iter().rev().find(|push| push.len() == 33 && hash160::Hash::hash(push).as_byte_array() == hash)?
+    } else if prevout.is_p2sh() {
+        // only P2SH wrapped P2WPKH, whose script_sig pushes the witness program only
+        let is_p2wpkh_redeem = input.script_sig.len() == 23 && input.script_sig[..3] == [0x16, 0x00, 0x14];
+        if !is_p2wpkh_redeem || input.witness_key.is_empty() {
+            return None;
+        }
+        input.witness_key.clone()
+    } else if prevout.is_p2wpkh() {
+        input.witness_key.clone()
+    } else if prevout.is_p2tr() && !input.nums_script_path {
+        // taproot output keys have even y
+        [&[0x02], &prevout.as_bytes()[2..34]].concat()
+    } else {
+        return None;
+    };
+    PublicKey::from_slice(&key).ok().filter(|_| key.len() == 33)
+}
+
+/// BIP352 tweak of a transaction with a taproot output given its outpoints, inputs and their prevout scripts,
+/// `None` if the transaction is not eligible
+pub fn compute_tweak<C: Verification>(secp: &Secp256k1<C>, outpoints: &[TempInputRef], inputs: &[SpInput], prevouts: &[ScriptBuf]) -> Option<PublicKey> {
+    // spending future segwit versions makes the whole transaction ineligible
+    if prevouts.iter().any(|prevout| prevout.witness_version().is_some_and(|version| version > WitnessVersion::V1)) {
+        return None;
+    }
+    let keys: Vec<PublicKey> = inputs.iter().zip(prevouts).filter_map(|(input, prevout)| input_key(input, prevout)).collect();
+    let sum = PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>()).ok()?;
+    let smallest_outpoint = outpoints.iter().map(|outpoint| [outpoint.tx_hash.0.as_slice(), &outpoint.index.to_le_bytes()].concat()).min()?;
+    let input_hash = tagged_hash("BIP0352/Inputs", &[smallest_outpoint, sum.serialize().to_vec()].concat());
+    sum.mul_tweak(secp, &Scalar::from_be_bytes(input_hash).ok()?).ok()
+}
+
+/// Tweaks of eligible transactions, `prevouts` are the spent outputs of every transaction in input order
+pub fn index_block(read_tx: &ReadTransaction, block: &Block, prevouts: &[Vec<Option<TxOut>>]) -> Result<Vec<SpTweak>, AppError> {
+    let secp = Secp256k1::verification_only();
+    let mut next_id = SpTweak::last(read_tx)?.map_or(0, |last| last.id.0 + 1);
+    let mut tweaks = Vec::new();
+    for (tx, tx_prevouts) in block.transactions.iter().zip(prevouts).filter(|(tx, _)| !tx.sp_inputs.is_empty()) {
+        // eligibility depends on every prevout
+        let Some(tx_prevouts) = tx_prevouts.iter().map(|prevout| prevout.as_ref().map(|prevout| prevout.script_pubkey.clone())).collect::<Option<Vec<ScriptBuf>>>() else {
+            continue;
+        };
+        if let Some(tweak) = compute_tweak(&secp, &tx.transient_inputs, &tx.sp_inputs, &tx_prevouts) {
+            tweaks.push(SpTweak { id: SpTweakId(next_id), height: block.id.0, tx_hash: tx.hash.clone(), tweak: TweakPoint(tweak.serialize().to_vec()) });
+            next_id += 1;
+        }
+    }
+    Ok(tweaks)
+}
+
+pub fn rollback(read_tx: &ReadTransaction, heights: &[Height]) -> Result<Vec<SpTweakId>, AppError> {
+    let mut ids = Vec::new();
+    for height in heights {
+        ids.extend(SpTweak::get_ids_by_height(read_tx, &height.0)?);
+    }
+    Ok(ids)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TweakView {
+    pub tx_hash: String,
+    pub tweak: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockTweaks {
+    pub height: u32,
+    pub tweaks: Vec<TweakView>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RangeParams {
+    pub from_height: u32,
+    pub until_height: Option<u32>,
+}
+
+/// Tweaks of blocks `from..=until` in chain order, blocks without eligible transactions included
+pub fn block_tweaks(read_tx: &ReadTransaction, from: u32, until: u32) -> Result<Vec<BlockTweaks>, AppError> {
+    let mut result = Vec::with_capacity((until - from + 1) as usize);
+    for height in from..=until {
+        let mut ids = SpTweak::get_ids_by_height(read_tx, &height)?;
+        ids.sort();
+        let mut tweaks = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(tweak) = SpTweak::get(read_tx, &id)? {
+                tweaks.push(TweakView { tx_hash: tweak.tx_hash.0.to_lower_hex_string(), tweak: tweak.tweak.0.to_lower_hex_string() });
+            }
+        }
+        result.push(BlockTweaks { height, tweaks });
+    }
+    Ok(result)
+}
+
+pub fn routes(pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/silent-payments/tweaks", get(tweaks)).layer(Extension(pool))
+}
+
+async fn tweaks(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>, Query(params): Query<RangeParams>) -> Response {
+    let (from, until) = (params.from_height, params.until_height.unwrap_or(params.from_height));
+    if until < from || until - from >= MAX_BLOCKS {
+        return error_response(StatusCode::BAD_REQUEST, format!("Range {}..={} must be ascending and span at most {} blocks", from, until, MAX_BLOCKS));
+    }
+    json_response(pool.run(move || block_tweaks(&state.db.begin_read()?, from, until)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{serde_json, TxHash};
+    use bitcoin::hex::FromHex;
+    use bitcoin::script::{Builder, PushBytesBuf};
+    use bitcoin::secp256k1::SecretKey;
+
+    fn outpoint(txid: &str, index: u32) -> TempInputRef {
+        // txids are displayed in reverse byte order
+        let mut tx_hash = <[u8; 32]>::from_hex(txid).unwrap();
+        tx_hash.reverse();
+        TempInputRef { tx_hash: TxHash(tx_hash), index }
+    }
+
+    fn key(secret: &str) -> PublicKey {
+        SecretKey::from_slice(&Vec::<u8>::from_hex(secret).unwrap()).unwrap().public_key(&Secp256k1::new())
+    }
+
+    fn p2pkh_input(key: &PublicKey) -> (SpInput, ScriptBuf) {
+        // signature is irrelevant, only the key is taken
+        let script_sig = Builder::new().push_slice([0x30; 71]).push_slice(key.serialize()).into_script();
+        let input = SpInput { script_sig: script_sig.into_bytes(), witness_key: Vec::new(), nums_script_path: false };
+        (input, ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::hash(&key.serialize())))
+    }
+
+    #[test]
+    fn test_bip352_simple_send() {
+        // first vector of BIP352 send and receive test vectors, two P2PKH inputs
+        let secp = Secp256k1::new();
+        let outpoints = [
+            outpoint("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0),
+            outpoint("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d", 0),
+        ];
+        let (first, first_prevout) = p2pkh_input(&key("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"));
+        let (second, second_prevout) = p2pkh_input(&key("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16"));
+        let tweak = compute_tweak(&secp, &outpoints, &[first.clone(), second.clone()], &[first_prevout.clone(), second_prevout.clone()]).unwrap();
+        assert_eq!(tweak.serialize().to_lower_hex_string(), "024ac253c216532e961988e2a8ce266a447c894c781e52ef6cee902361db960004");
+
+        // the receiver finds the expected output with its scan and spend keys and the tweak
+        let scan = SecretKey::from_slice(&Vec::<u8>::from_hex("0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c").unwrap()).unwrap();
+        let spend = key("9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3");
+        let shared_secret = tweak.mul_tweak(&secp, &Scalar::from(scan)).unwrap();
+        let t = tagged_hash("BIP0352/SharedSecret", &[shared_secret.serialize().as_slice(), &0u32.to_be_bytes()].concat());
+        let output = spend.add_exp_tweak(&secp, &Scalar::from_be_bytes(t).unwrap()).unwrap();
+        assert_eq!(output.x_only_public_key().0.to_string(), "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1");
+
+        // input order does not matter
+        let reversed = compute_tweak(&secp, &[outpoints[1].clone(), outpoints[0].clone()], &[second, first], &[second_prevout, first_prevout]);
+        assert_eq!(reversed, Some(tweak));
+    }
+
+    // cases of the official https://github.com/bitcoin/bips/blob/master/bip-0352/send_and_receive_test_vectors.json with
+    // placeholder signatures as only the keys are taken, the whole file can replace it as is
+    const BIP352_VECTORS: &str = include_str!("testdata/send_and_receive_test_vectors.json");
+
+    fn hex_of(value: &serde_json::Value) -> Vec<u8> {
+        Vec::<u8>::from_hex(value.as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_bip352_vectors() {
+        let secp = Secp256k1::new();
+        let vectors: Vec<serde_json::Value> = serde_json::from_str(BIP352_VECTORS).unwrap();
+        for vector in &vectors {
+            let comment = vector["comment"].as_str().unwrap();
+            for receiving in vector["receiving"].as_array().unwrap() {
+                let given = &receiving["given"];
+                let expected = &receiving["expected"];
+                let vin = given["vin"].as_array().unwrap();
+                let outpoints: Vec<TempInputRef> =
+                    vin.iter().map(|input| outpoint(input["txid"].as_str().unwrap(), input["vout"].as_u64().unwrap() as u32)).collect();
+                let prevouts: Vec<ScriptBuf> =
+                    vin.iter().map(|input| ScriptBuf::from_bytes(hex_of(&input["prevout"]["scriptPubKey"]["hex"]))).collect();
+                // inputs are taken from a transaction with a taproot output like when indexing
+                let input = vin
+                    .iter()
+                    .map(|input| {
+                        let witness = hex_of(&input["txinwitness"]);
+                        bitcoin::TxIn {
+                            script_sig: ScriptBuf::from_bytes(hex_of(&input["scriptSig"])),
+                            witness: if witness.is_empty() { bitcoin::Witness::new() } else { bitcoin::consensus::deserialize(&witness).unwrap() },
+                            ..Default::default()
+                        }
+                    })
+                    .collect();
+                let output =
+                    vec![TxOut { value: bitcoin::Amount::ZERO, script_pubkey: ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[2; 32]].concat()) }];
+                let tx =
+                    bitcoin::Transaction { version: bitcoin::transaction::Version::TWO, lock_time: bitcoin::absolute::LockTime::ZERO, input, output };
+                let inputs = sp_inputs(&tx);
+                let tweak = compute_tweak(&secp, &outpoints, &inputs, &prevouts);
+                if let Some(expected_tweak) = expected.get("tweak").and_then(serde_json::Value::as_str) {
+                    assert_eq!(tweak.map(|tweak| tweak.serialize().to_lower_hex_string()).as_deref(), Some(expected_tweak), "{}", comment);
+                }
+                let outputs = expected["outputs"].as_array().unwrap();
+                // the outputs found by the receiver must be derivable from the tweak, labeled ones need the label tweak on top
+                if !outputs.is_empty() && given["labels"].as_array().is_some_and(|labels| labels.is_empty()) {
+                    let tweak = tweak.unwrap_or_else(|| panic!("{}", comment));
+                    let scan = SecretKey::from_slice(&hex_of(&given["key_material"]["scan_priv_key"])).unwrap();
+                    let spend = SecretKey::from_slice(&hex_of(&given["key_material"]["spend_priv_key"])).unwrap().public_key(&secp);
+                    let shared_secret = tweak.mul_tweak(&secp, &Scalar::from(scan)).unwrap();
+                    let derived: Vec<String> = (0..outputs.len() as u32)
+                        .map(|k| {
+                            let t = tagged_hash("BIP0352/SharedSecret", &[shared_secret.serialize().as_slice(), &k.to_be_bytes()].concat());
+                            spend.add_exp_tweak(&secp, &Scalar::from_be_bytes(t).unwrap()).unwrap().x_only_public_key().0.to_string()
+                        })
+                        .collect();
+                    for output in outputs {
+                        assert!(derived.iter().any(|key| key == output["pub_key"].as_str().unwrap()), "{}", comment);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_eligibility() {
+        let secp = Secp256k1::new();
+        let outpoints = [outpoint("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", 0)];
+        let key = key("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1");
+        let (x_only, _) = key.x_only_public_key();
+        let taproot = ScriptBuf::new_p2tr_tweaked(bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(x_only));
+        let keypath = SpInput { script_sig: Vec::new(), witness_key: Vec::new(), nums_script_path: false };
+        let taproot_tweak = compute_tweak(&secp, &outpoints, std::slice::from_ref(&keypath), std::slice::from_ref(&taproot));
+        assert!(taproot_tweak.is_some());
+        // taproot keys are taken with even y whatever the parity of the key was
+        let even = PublicKey::from_slice(&[&[0x02], x_only.serialize().as_slice()].concat()).unwrap();
+        let even_taproot = ScriptBuf::new_p2tr_tweaked(bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(even.x_only_public_key().0));
+        assert_eq!(taproot_tweak, compute_tweak(&secp, &outpoints, std::slice::from_ref(&keypath), &[even_taproot]));
+
+        let nums = SpInput { nums_script_path: true, ..keypath.clone() };
+        assert_eq!(compute_tweak(&secp, &outpoints, &[nums], std::slice::from_ref(&taproot)), None);
+        let witness_v2 = Builder::new().push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_2).push_slice([7; 32]).into_script();
+        assert_eq!(compute_tweak(&secp, &[outpoints[0].clone(), outpoints[0].clone()], &[keypath.clone(), keypath.clone()], &[taproot, witness_v2]), None);
+
+        let p2wpkh = ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::hash(&key.serialize()));
+        let witness = SpInput { witness_key: key.serialize().to_vec(), ..keypath.clone() };
+        assert!(compute_tweak(&secp, &outpoints, std::slice::from_ref(&witness), std::slice::from_ref(&p2wpkh)).is_some());
+        let wrapped = SpInput { script_sig: Builder::new().push_slice(PushBytesBuf::try_from(p2wpkh.to_bytes()).unwrap()).into_script().into_bytes(), ..witness };
+        assert!(compute_tweak(&secp, &outpoints, &[wrapped], &[ScriptBuf::new_p2sh(&p2wpkh.script_hash())]).is_some());
+        // bare multisig inputs carry no eligible key
+        assert_eq!(compute_tweak(&secp, &outpoints, &[keypath], &[ScriptBuf::from_hex("5121022afc20bf379bc96a2f4e9e63ffceb8652b2b6a097f63fbee6ecec2a49a48010e51ae").unwrap()]), None);
+    }
+
+    #[test]
+    fn test_nums_script_path() {
+        let control = [&[0xc0], NUMS_H.as_slice()].concat();
+        assert!(is_nums_script_path(&bitcoin::Witness::from_slice(&[vec![1], vec![0x51], control.clone()])));
+        assert!(is_nums_script_path(&bitcoin::Witness::from_slice(&[vec![1], vec![0x51], control.clone(), vec![ANNEX_PREFIX, 1]])));
+        assert!(!is_nums_script_path(&bitcoin::Witness::from_slice(&[vec![0x30; 64]])));
+        assert!(!is_nums_script_path(&bitcoin::Witness::from_slice(&[vec![1], vec![0x51], [&[0xc0], [3; 32].as_slice()].concat()])));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::backpressure::QueryPool;
+use crate::config::StatusSettings;
+use crate::model::BlockHeader;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Extension, Json};
+use chrono::Utc;
+use redbit::*;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastError {
+    pub message: String,
+    pub at: String,
+}
+
+/// Indexing progress shared between `BtcBlockProvider`, `BtcBlockPersistence` and the http server
+pub struct SyncStatus {
+    pub indexing_enabled: bool,
+    pub http_enabled: bool,
+    settings: StatusSettings,
+    node_tip: Mutex<Option<u32>>,
+    blocks_stored: AtomicU64,
+    samples: Mutex<VecDeque<(Instant, u64)>>,
+    last_error: Mutex<Option<LastError>>,
+}
+
+impl SyncStatus {
+    pub fn new(indexing_enabled: bool, http_enabled: bool, settings: StatusSettings) -> Self {
+        SyncStatus {
+            indexing_enabled,
+            http_enabled,
+            settings,
+            node_tip: Mutex::new(None),
+            blocks_stored: AtomicU64::new(0),
+            samples: Mutex::new(VecDeque::new()),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    pub fn record_node_tip(&self, height: u32) {
+        *self.node_tip.lock().unwrap() = Some(height);
+    }
+
+    pub fn record_stored(&self, block_count: usize) {
+        let total = self.blocks_stored.fetch_add(block_count as u64, Ordering::Relaxed) + block_count as u64;
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back((now, total));
+        while samples.front().is_some_and(|(at, _)| now.duration_since(*at) > THROUGHPUT_WINDOW) {
+            samples.pop_front();
+        }
+    }
+
+    pub fn record_error(&self, message: impl ToString) {
+        *self.last_error.lock().unwrap() = Some(LastError { message: message.to_string(), at: Utc::now().to_rfc3339() });
+    }
+
+    pub fn node_tip(&self) -> Option<u32> {
+        *self.node_tip.lock().unwrap()
+    }
+
+    pub fn blocks_per_second(&self) -> f64 {
+        let samples = self.samples.lock().unwrap();
+        match (samples.front(), samples.back()) {
+            (Some((from, from_total)), Some((until, until_total))) if until > from => {
+                (until_total - from_total) as f64 / until.duration_since(*from).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn report(&self, indexed_height: Option<u32>) -> StatusReport {
+        let node_tip_height = self.node_tip();
+        let lag = match (indexed_height, node_tip_height) {
+            (Some(indexed), Some(tip)) => Some(tip.saturating_sub(indexed)),
+            (None, Some(tip)) => Some(tip),
+            _ => None,
+        };
+        // without indexing we only serve existing data, so there is nothing to catch up with
+        let ready = !self.indexing_enabled || lag.is_some_and(|lag| lag <= self.settings.ready_max_lag);
+        StatusReport {
+            ready,
+            indexed_height,
+            node_tip_height,
+            lag,
+            blocks_per_second: self.blocks_per_second(),
+            blocks_stored: self.blocks_stored.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+            indexing_enabled: self.indexing_enabled,
+            http_enabled: self.http_enabled,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub ready: bool,
+    pub indexed_height: Option<u32>,
+    pub node_tip_height: Option<u32>,
+    pub lag: Option<u32>,
+    pub blocks_per_second: f64,
+    pub blocks_stored: u64,
+    pub last_error: Option<LastError>,
+    pub indexing_enabled: bool,
+    pub http_enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn routes(status: Arc<SyncStatus>, pool: Arc<QueryPool>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new()
+        .route("/health", get(health))
+        .route("/status", get(sync_status))
+        .layer(Extension(status))
+        .layer(Extension(pool))
+}
+
+fn indexed_height(db: &Database) -> Result<Option<u32>, AppError> {
+    let read_tx = db.begin_read()?;
+    Ok(BlockHeader::last(&read_tx)?.map(|header| header.id.0))
+}
+
+/// Liveness probe, fails only if the database cannot be read
+async fn health(State(state): State<RequestState>, Extension(pool): Extension<Arc<QueryPool>>) -> (StatusCode, Json<HealthReport>) {
+    match pool.run(move || indexed_height(&state.db)).await {
+        Ok(Ok(_)) => (StatusCode::OK, Json(HealthReport { status: "ok", error: None })),
+        Ok(Err(e)) => (StatusCode::SERVICE_UNAVAILABLE, Json(HealthReport { status: "error", error: Some(e.to_string()) })),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, Json(HealthReport { status: "error", error: Some(e.to_string()) })),
+    }
+}
+
+/// Readiness probe, 503 while indexer lags behind node tip more than `status.ready_max_lag` blocks
+async fn sync_status(
+    State(state): State<RequestState>,
+    Extension(status): Extension<Arc<SyncStatus>>,
+    Extension(pool): Extension<Arc<QueryPool>>,
+) -> (StatusCode, Json<StatusReport>) {
+    let indexed = match pool.run(move || indexed_height(&state.db)).await {
+        Ok(Ok(height)) => height,
+        Ok(Err(e)) => {
+            status.record_error(&e);
+            None
+        }
+        Err(e) => {
+            status.record_error(&e);
+            None
+        }
+    };
+    let report = status.report(indexed);
+    let code = if report.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_status(indexing_enabled: bool) -> SyncStatus {
+        SyncStatus::new(indexing_enabled, true, StatusSettings { ready_max_lag: 3 })
+    }
+
+    #[test]
+    fn test_report_lag_and_readiness() {
+        let status = new_status(true);
+        let report = status.report(Some(100));
+        assert_eq!((report.lag, report.ready), (None, false));
+
+        status.record_node_tip(110);
+        let report = status.report(Some(100));
+        assert_eq!((report.node_tip_height, report.lag, report.ready), (Some(110), Some(10), false));
+        assert_eq!(status.report(Some(107)).lag, Some(3));
+        assert!(status.report(Some(107)).ready);
+        assert_eq!(status.report(None).lag, Some(110));
+        // indexed height may pass a stale node tip
+        assert_eq!(status.report(Some(112)).lag, Some(0));
+        assert!(!status.report(None).ready);
+
+        assert!(new_status(false).report(None).ready);
+    }
+
+    #[test]
+    fn test_stored_blocks_and_errors() {
+        let status = new_status(true);
+        assert_eq!(status.blocks_per_second(), 0.0);
+        status.record_stored(2);
+        status.record_stored(3);
+        let report = status.report(Some(5));
+        assert_eq!(report.blocks_stored, 5);
+        assert!(report.last_error.is_none());
+
+        status.record_error("node unreachable");
+        status.record_error("rpc timeout");
+        assert_eq!(status.report(Some(5)).last_error.unwrap().message, "rpc timeout");
+    }
+}
@@ -1,18 +1,157 @@
-use crate::model::Block;
-use redb::Database;
+use crate::model::{Block, BlockHeader, FeedEvent, Reorg};
+use chain_syncer::info;
+use redb::{Database, ReadableTable, TableDefinition, TableError};
 use redbit::*;
 use std::path::PathBuf;
 
-pub fn get_db(db_dir: PathBuf) -> redb::Result<Database, AppError> {
+const SCHEMA: TableDefinition<&str, u32> = TableDefinition::new("schema");
+/// Bumped when stored layout changes so that existing databases cannot be read anymore and have to be rebuilt :
+/// 2 - outputs without an address have no `UtxoAddress` instead of sharing a sentinel address
+/// 3 - output scripts are stored compressed
+/// 4 - every block has its BIP158 basic filter
+/// 5 - headers keep version and nonce
+/// 6 - inscriptions record whether they are cursed
+/// 7 - output script type is no longer indexed
+/// 8 - inscriptions are keyed by sequence and numbered like `ord`
+/// 9 - rune balances without an address have none and holdings are aggregated per address
+pub const SCHEMA_VERSION: u32 = 9;
+
+fn write_schema_version(db: &Database) -> Result<(), AppError> {
+    let write_tx = db.begin_write()?;
+    write_tx.open_table(SCHEMA)?.insert("version", SCHEMA_VERSION)?;
+    write_tx.commit()?;
+    Ok(())
+}
+
+/// Schema version of an existing database, those created before it was recorded are of version 1
+pub fn schema_version(db: &Database) -> Result<u32, AppError> {
+    let read_tx = db.begin_read()?;
+    match read_tx.open_table(SCHEMA) {
+        Ok(table) => Ok(table.get("version")?.map_or(1, |version| version.value())),
+        Err(TableError::TableDoesNotExist(_)) => Ok(1),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Opens the database in `db_dir` or creates it. A database of an older schema version is refused unless `rebuild_outdated`,
+/// then it is upgraded in place by `rebuild`.
+pub fn get_db(db_dir: PathBuf, rebuild_outdated: bool) -> redb::Result<Database, AppError> {
     if !db_dir.exists() {
         std::fs::create_dir_all(db_dir.clone()).map_err(|e| AppError::Internal(format!("Failed to create database directory: {}", e)))?;
         let db = Database::create(db_dir.join("chain_syncer.db"))?;
         // Database::builder().set_page_size(4096 * 4).create(db_dir.join("chain_syncer.db"))?;
-        let sample_block = Block::sample();
-        Block::store_and_commit(&db, &sample_block)?;
-        Block::delete_and_commit(&db, &sample_block.id)?;
+        create_tables(&db)?;
         Ok(db)
     } else {
-        Database::open(db_dir.join("chain_syncer.db")).map_err(|e| e.into())
+        let mut db = Database::open(db_dir.join("chain_syncer.db"))?;
+        match schema_version(&db)? {
+            SCHEMA_VERSION => Ok(db),
+            // headers of an older layout may not be readable, which is no empty database either
+            _ if matches!(BlockHeader::last(&db.begin_read()?), Ok(None)) => {
+                write_schema_version(&db)?;
+                Ok(db)
+            }
+            version if version < SCHEMA_VERSION && rebuild_outdated => {
+                info!("Database in {} has schema version {}, rebuilding it as version {} from the node", db_dir.display(), version, SCHEMA_VERSION);
+                rebuild(&mut db)?;
+                Ok(db)
+            }
+            version if version < SCHEMA_VERSION => Err(AppError::Internal(format!(
+                "Database in {} has schema version {} but {} is required, set schema.rebuild_outdated to upgrade it in place \
+                 or index into an empty indexer.db_path and replace the old one once it catches up",
+                db_dir.display(),
+                version,
+                SCHEMA_VERSION
+            ))),
+            version => Err(AppError::Internal(format!(
+                "Database in {} has schema version {} of a newer explorer, {} is the latest this one can read",
+                db_dir.display(),
+                version,
+                SCHEMA_VERSION
+            ))),
+        }
+    }
+}
+
+/// Every version so far, including the unrecorded version 1, lacks data that only raw blocks have, ie. header bits, version
+/// and nonce, filters, script types, compressed scripts, public key and OP_RETURN indexes, so outdated blocks cannot be
+/// converted from what is stored. Their tables are dropped instead and the syncer fetches all blocks from the node again
+/// into the same file, which is compacted so that it does not keep the space of the old layout.
+fn rebuild(db: &mut Database) -> Result<(), AppError> {
+    let write_tx = db.begin_write()?;
+    for table in write_tx.list_tables()?.collect::<Vec<_>>() {
+        write_tx.delete_table(table)?;
+    }
+    for table in write_tx.list_multimap_tables()?.collect::<Vec<_>>() {
+        write_tx.delete_multimap_table(table)?;
+    }
+    write_tx.commit()?;
+    db.compact().map_err(|e| AppError::Internal(format!("Failed to compact database : {}", e)))?;
+    create_tables(db)
+}
+
+/// Creates tables of all entities up front and records the schema version
+fn create_tables(db: &Database) -> Result<(), AppError> {
+    let sample_block = Block::sample();
+    Block::store_and_commit(db, &sample_block)?;
+    Block::delete_and_commit(db, &sample_block.id)?;
+    let sample_reorg = Reorg::sample();
+    Reorg::store_and_commit(db, &sample_reorg)?;
+    Reorg::delete_and_commit(db, &sample_reorg.id)?;
+    let sample_event = FeedEvent::sample();
+    FeedEvent::store_and_commit(db, &sample_event)?;
+    FeedEvent::delete_and_commit(db, &sample_event.id)?;
+    #[cfg(feature = "ordinals")]
+    {
+        use crate::model::{Inscription, InscriptionTransfer};
+        let sample_inscription = Inscription::sample();
+        Inscription::store_and_commit(db, &sample_inscription)?;
+        Inscription::delete_and_commit(db, &sample_inscription.id)?;
+        let sample_transfer = InscriptionTransfer::sample();
+        InscriptionTransfer::store_and_commit(db, &sample_transfer)?;
+        InscriptionTransfer::delete_and_commit(db, &sample_transfer.id)?;
+    }
+    #[cfg(feature = "brc20")]
+    {
+        use crate::model::{Brc20Balance, Brc20Event, Brc20Supply, Brc20Token};
+        let sample_token = Brc20Token::sample();
+        Brc20Token::store_and_commit(db, &sample_token)?;
+        Brc20Token::delete_and_commit(db, &sample_token.id)?;
+        let sample_event = Brc20Event::sample();
+        Brc20Event::store_and_commit(db, &sample_event)?;
+        Brc20Event::delete_and_commit(db, &sample_event.id)?;
+        let sample_balance = Brc20Balance::sample();
+        Brc20Balance::store_and_commit(db, &sample_balance)?;
+        Brc20Balance::delete_and_commit(db, &sample_balance.id)?;
+        let sample_supply = Brc20Supply::sample();
+        Brc20Supply::store_and_commit(db, &sample_supply)?;
+        Brc20Supply::delete_and_commit(db, &sample_supply.id)?;
+    }
+    #[cfg(feature = "runes")]
+    {
+        use crate::model::{Rune, RuneBalance, RuneHolding, RuneMint, RuneSpend};
+        let sample_rune = Rune::sample();
+        Rune::store_and_commit(db, &sample_rune)?;
+        Rune::delete_and_commit(db, &sample_rune.id)?;
+        let sample_balance = RuneBalance::sample();
+        RuneBalance::store_and_commit(db, &sample_balance)?;
+        RuneBalance::delete_and_commit(db, &sample_balance.id)?;
+        let sample_spend = RuneSpend::sample();
+        RuneSpend::store_and_commit(db, &sample_spend)?;
+        RuneSpend::delete_and_commit(db, &sample_spend.id)?;
+        let sample_mint = RuneMint::sample();
+        RuneMint::store_and_commit(db, &sample_mint)?;
+        RuneMint::delete_and_commit(db, &sample_mint.id)?;
+        let sample_holding = RuneHolding::sample();
+        RuneHolding::store_and_commit(db, &sample_holding)?;
+        RuneHolding::delete_and_commit(db, &sample_holding.id)?;
+    }
+    #[cfg(feature = "silent_payments")]
+    {
+        use crate::model::SpTweak;
+        let sample_tweak = SpTweak::sample();
+        SpTweak::store_and_commit(db, &sample_tweak)?;
+        SpTweak::delete_and_commit(db, &sample_tweak.id)?;
     }
+    write_schema_version(db)
 }
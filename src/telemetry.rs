@@ -0,0 +1,118 @@
+use crate::model::{Block, ScriptType};
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Extension;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use redbit::*;
+use std::time::{Duration, Instant};
+
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const REORG_DEPTH_BUCKETS: &[f64] = &[1.0, 2.0, 3.0, 4.0, 6.0, 10.0, 20.0, 50.0, 100.0];
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Installs global prometheus recorder, metrics recorded before installation are dropped
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)?
+        .set_buckets_for_metric(Matcher::Full("explorer_reorg_depth_blocks".to_string()), REORG_DEPTH_BUCKETS)?
+        .install_recorder()?;
+    let upkeep_handle = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep_handle.run_upkeep();
+        }
+    });
+    Ok(handle)
+}
+
+pub fn routes(handle: PrometheusHandle) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/metrics", get(render)).layer(Extension(handle))
+}
+
+async fn render(Extension(handle): Extension<PrometheusHandle>) -> impl IntoResponse {
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], handle.render())
+}
+
+/// Middleware recording latency of every request labelled by matched route, not by raw uri to keep cardinality low
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map_or_else(|| "unmatched".to_string(), |path| path.as_str().to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+    histogram!("explorer_http_request_duration_seconds", "route" => route, "method" => method, "status" => status)
+        .record(start.elapsed().as_secs_f64());
+    response
+}
+
+pub fn record_rpc(method: &'static str, elapsed: Duration, ok: bool) {
+    histogram!("explorer_rpc_duration_seconds", "method" => method).record(elapsed.as_secs_f64());
+    if !ok {
+        counter!("explorer_rpc_errors_total", "method" => method).increment(1);
+    }
+}
+
+pub fn record_write(operation: &'static str, elapsed: Duration) {
+    histogram!("explorer_write_tx_duration_seconds", "operation" => operation).record(elapsed.as_secs_f64());
+}
+
+pub fn record_node_tip(height: u32) {
+    gauge!("explorer_node_tip_height").set(height as f64);
+}
+
+pub fn record_stored(block: &Block, unresolved_inputs: usize) {
+    let (inputs, outputs) = block.transactions.iter().fold((0, 0), |(ins, outs), tx| (ins + tx.inputs.len(), outs + tx.utxos.len()));
+    gauge!("explorer_indexed_height").set(block.id.0 as f64);
+    counter!("explorer_blocks_stored_total").increment(1);
+    counter!("explorer_txs_stored_total").increment(block.transactions.len() as u64);
+    counter!("explorer_inputs_stored_total").increment(inputs as u64);
+    counter!("explorer_outputs_stored_total").increment(outputs as u64);
+    counter!("explorer_unresolved_inputs_total").increment(unresolved_inputs as u64);
+    let mut by_script_type = [0u64; ScriptType::ALL.len()];
+    for utxo in block.transactions.iter().flat_map(|tx| tx.utxos.iter()) {
+        by_script_type[utxo.script_type.0 as usize % ScriptType::ALL.len()] += 1;
+    }
+    for (script_type, count) in ScriptType::ALL.iter().zip(by_script_type).filter(|(_, count)| *count > 0) {
+        counter!("explorer_outputs_by_script_type_total", "script_type" => script_type.name()).increment(count);
+    }
+}
+
+pub fn record_reorg(depth: usize) {
+    counter!("explorer_reorgs_total").increment(1);
+    histogram!("explorer_reorg_depth_blocks").record(depth as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_http_latency_is_labelled_by_route() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let app = Router::new()
+            .route("/tx/{txid}/hex", get(|| async { StatusCode::NOT_FOUND }))
+            .layer(axum::middleware::from_fn(track_http));
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                for uri in ["/tx/aa/hex", "/tx/bb/hex", "/nowhere"] {
+                    app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+                }
+            })
+        });
+        let rendered = handle.render();
+        assert!(rendered.contains(r#"explorer_http_request_duration_seconds_count{route="/tx/{txid}/hex",method="GET",status="404"} 2"#), "{}", rendered);
+        assert!(rendered.contains(r#"explorer_http_request_duration_seconds_count{route="unmatched",method="GET",status="404"} 1"#), "{}", rendered);
+        assert!(!rendered.contains("/tx/aa/hex"));
+    }
+}
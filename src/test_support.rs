@@ -0,0 +1,14 @@
+// fixtures shared by tests of several modules
+use bitcoin::hex::FromHex;
+
+/// First transaction between people, mined in block 170, it spends the coinbase output of block 9
+pub const TX_F4184: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+
+/// Genesis coinbase output, a P2PK of an uncompressed key
+pub const GENESIS_OUTPUT: &str =
+    "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+
+/// Uncompressed public key the genesis coinbase pays to
+pub fn genesis_key() -> Vec<u8> {
+    Vec::<u8>::from_hex(&GENESIS_OUTPUT[2..132]).unwrap()
+}
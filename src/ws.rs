@@ -0,0 +1,259 @@
+use crate::codec;
+use crate::notifier::{BlockRef, ChainEvent, Notifier};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Extension;
+use bitcoin::hex::DisplayHex;
+use redbit::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+// topics a single socket may subscribe to, so that one client cannot grow its subscriptions without bound
+const MAX_TOPICS: usize = 1000;
+
+/// Messages sent by clients, ie. `{"subscribe":"tips"}`, `{"subscribe":{"address":"bc1q..."}}` or `{"unsubscribe":{"tx":"<txid>"}}`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe(Topic),
+    Unsubscribe(Topic),
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Topic {
+    Tips,
+    Address(String),
+    Tx(String),
+}
+
+impl Topic {
+    /// Addresses as they are indexed and txids as lower case hex of either byte order, which are both matched
+    fn normalized(self) -> Result<Topic, String> {
+        match self {
+            Topic::Address(address) => Ok(Topic::Address(codec::normalize_address(address.trim())?)),
+            Topic::Tx(txid) => Ok(Topic::Tx(codec::parse_hash(&txid)?.to_lower_hex_string())),
+            Topic::Tips => Ok(Topic::Tips),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Subscribed { topic: &'a Topic },
+    Unsubscribed { topic: &'a Topic },
+    NewTip { block: &'a BlockRef, timestamp: u32, tx_count: usize },
+    Tx { txid: String, block: &'a BlockRef },
+    Address { address: &'a str, txid: &'a str, block: &'a BlockRef },
+    Reorg { fork_height: u32, rolled_back: &'a [BlockRef] },
+    Lagged { skipped: u64 },
+    Error { message: String },
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    topics: HashSet<Topic>,
+}
+
+impl Subscriptions {
+    /// Adds a topic unless the socket already has `MAX_TOPICS` of them
+    fn subscribe(&mut self, topic: Topic) -> bool {
+        if self.topics.len() >= MAX_TOPICS && !self.topics.contains(&topic) {
+            return false;
+        }
+        self.topics.insert(topic);
+        true
+    }
+
+    fn messages<'a>(&self, event: &'a ChainEvent) -> Vec<ServerMessage<'a>> {
+        let mut messages = Vec::new();
+        if self.topics.is_empty() {
+            return messages;
+        }
+        match event {
+            ChainEvent::Connected(connected) => {
+                let block = &connected.block;
+                if self.topics.contains(&Topic::Tips) {
+                    messages.push(ServerMessage::NewTip { block, timestamp: connected.timestamp, tx_count: connected.txids.len() });
+                }
+                // txids are reported in the byte order they were subscribed in
+                let tx_topics: HashSet<[u8; 32]> = self
+                    .topics
+                    .iter()
+                    .filter_map(|topic| match topic {
+                        Topic::Tx(txid) => codec::parse_hash(txid).ok(),
+                        _ => None,
+                    })
+                    .collect();
+                for txid in &connected.txids {
+                    for order in codec::byte_orders(*txid).into_iter().filter(|order| tx_topics.contains(order)) {
+                        messages.push(ServerMessage::Tx { txid: order.to_lower_hex_string(), block });
+                    }
+                }
+                for (address, txid) in &connected.address_activity {
+                    if self.topics.contains(&Topic::Address(address.clone())) {
+                        messages.push(ServerMessage::Address { address, txid, block });
+                    }
+                }
+            }
+            // every subscriber must learn that previously announced blocks are gone
+            ChainEvent::RolledBack { fork_height, rolled_back } => {
+                messages.push(ServerMessage::Reorg { fork_height: *fork_height, rolled_back });
+            }
+        }
+        messages
+    }
+}
+
+pub fn routes(notifier: Arc<Notifier>) -> OpenApiRouter<RequestState> {
+    OpenApiRouter::new().route("/ws", get(ws_handler)).layer(Extension(notifier))
+}
+
+async fn ws_handler(ws: WebSocketUpgrade, Extension(notifier): Extension<Arc<Notifier>>) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, notifier))
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage<'_>) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => socket.send(Message::Text(json.into())).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+async fn handle_socket(mut socket: WebSocket, notifier: Arc<Notifier>) {
+    let mut events = notifier.subscribe();
+    let mut subscriptions = Subscriptions::default();
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let text = match incoming {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let delivered = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Subscribe(topic)) => match topic.normalized() {
+                        Ok(topic) => {
+                            let message = if subscriptions.subscribe(topic.clone()) {
+                                ServerMessage::Subscribed { topic: &topic }
+                            } else {
+                                ServerMessage::Error { message: format!("Subscription limit of {} topics reached, unsubscribe first", MAX_TOPICS) }
+                            };
+                            send(&mut socket, &message).await
+                        }
+                        Err(message) => send(&mut socket, &ServerMessage::Error { message }).await,
+                    },
+                    Ok(ClientMessage::Unsubscribe(topic)) => {
+                        let topic = topic.clone().normalized().unwrap_or(topic);
+                        subscriptions.topics.remove(&topic);
+                        send(&mut socket, &ServerMessage::Unsubscribed { topic: &topic }).await
+                    }
+                    Err(e) => send(&mut socket, &ServerMessage::Error { message: format!("Invalid message {}: {}", text.as_str(), e) }).await,
+                };
+                if !delivered {
+                    break;
+                }
+            }
+            event = events.recv() => {
+                let delivered = match event {
+                    Ok(event) => {
+                        let mut delivered = true;
+                        for message in subscriptions.messages(&event) {
+                            if !send(&mut socket, &message).await {
+                                delivered = false;
+                                break;
+                            }
+                        }
+                        delivered
+                    }
+                    Err(RecvError::Lagged(skipped)) => send(&mut socket, &ServerMessage::Lagged { skipped }).await,
+                    Err(RecvError::Closed) => false,
+                };
+                if !delivered {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::ConnectedBlock;
+    use bitcoin::hashes::Hash;
+    use std::collections::BTreeSet;
+    use std::str::FromStr;
+
+    // coinbase and the first bitcoin transfer of block 170 as bitcoind displays them
+    const COINBASE_TXID: &str = "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082";
+    const TRANSFER_TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    fn stored(txid: &str) -> [u8; 32] {
+        bitcoin::Txid::from_str(txid).unwrap().to_byte_array()
+    }
+
+    fn connected() -> ChainEvent {
+        let block = BlockRef { height: 170, hash: "00".repeat(32) };
+        let address_activity = BTreeSet::from([("bc1qaddress".to_string(), "aa".to_string()), ("1Other".to_string(), "bb".to_string())]);
+        let txids = vec![stored(COINBASE_TXID), stored(TRANSFER_TXID)];
+        ChainEvent::Connected(Arc::new(ConnectedBlock { block, timestamp: 1231731025, txids, address_activity }))
+    }
+
+    #[test]
+    fn test_messages_follow_topics() {
+        let mut subscriptions = Subscriptions::default();
+        assert!(subscriptions.messages(&connected()).is_empty());
+
+        subscriptions.subscribe(Topic::Tips);
+        subscriptions.subscribe(Topic::Tx(TRANSFER_TXID.to_string()));
+        subscriptions.subscribe(Topic::Address("bc1qaddress".to_string()));
+        subscriptions.subscribe(Topic::Tx("00".repeat(32)));
+        let event = connected();
+        let messages = subscriptions.messages(&event);
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(messages[0], ServerMessage::NewTip { tx_count: 2, .. }));
+        assert!(matches!(&messages[1], ServerMessage::Tx { txid, .. } if txid == TRANSFER_TXID));
+        assert!(matches!(messages[2], ServerMessage::Address { address: "bc1qaddress", txid: "aa", .. }));
+
+        subscriptions.topics.remove(&Topic::Tips);
+        let rolled_back = ChainEvent::RolledBack { fork_height: 169, rolled_back: Arc::new(vec![BlockRef { height: 170, hash: "00".repeat(32) }]) };
+        assert!(matches!(subscriptions.messages(&rolled_back)[..], [ServerMessage::Reorg { fork_height: 169, .. }]));
+    }
+
+    #[test]
+    fn test_tx_topics_match_either_byte_order() {
+        let internal = stored(COINBASE_TXID).to_lower_hex_string();
+        assert_eq!(internal, "82501c1178fa0b222c1f3d474ec726b832013f0a532b44bb620cce8624a5feb1");
+        for txid in [COINBASE_TXID.to_uppercase(), internal.clone()] {
+            let mut subscriptions = Subscriptions::default();
+            let topic = Topic::Tx(format!(" {} ", txid)).normalized().unwrap();
+            assert_eq!(topic, Topic::Tx(txid.to_lowercase()));
+            subscriptions.subscribe(topic);
+            let event = connected();
+            let messages = subscriptions.messages(&event);
+            assert!(matches!(&messages[..], [ServerMessage::Tx { txid: reported, .. }] if *reported == txid.to_lowercase()));
+        }
+        assert!(Topic::Tx("bb".to_string()).normalized().is_err());
+        assert!(Topic::Address("not an address".to_string()).normalized().is_err());
+        assert_eq!(
+            Topic::Address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4".to_string()).normalized(),
+            Ok(Topic::Address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_topics_are_capped() {
+        let mut subscriptions = Subscriptions::default();
+        for i in 0..MAX_TOPICS {
+            assert!(subscriptions.subscribe(Topic::Tx(i.to_string())));
+        }
+        assert!(!subscriptions.subscribe(Topic::Tips));
+        assert!(subscriptions.subscribe(Topic::Tx("0".to_string())));
+        assert_eq!(subscriptions.topics.len(), MAX_TOPICS);
+    }
+}